@@ -11,6 +11,7 @@ use playit_agent_core::{
     utils::now_milli,
 };
 use playit_api_client::api::*;
+use playit_api_client::http_client::HttpClientSettings;
 use tokio::sync::mpsc;
 
 use crate::{
@@ -40,6 +41,7 @@ pub async fn autorun(ui: &mut UI, mut secret: PlayitSecret) -> Result<(), CliErr
     let settings = PlayitAgentSettings {
         udp_settings: UdpSettings::default(),
         tcp_settings: TcpSettings::default(),
+        http_settings: HttpClientSettings::default(),
         api_url: API_BASE.to_string(),
         secret_key: secret_code.clone(),
     };
@@ -28,6 +28,32 @@ pub enum SetupError {
     Timeout(TimeoutSource),
 }
 
+impl SetupError {
+    /// Failures that can clear up by themselves, ex. sockets or routes briefly missing
+    /// right after a network change. Auth and protocol errors won't go away on a retry.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SetupError::IoError(error) => !matches!(
+                error.kind(),
+                std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::InvalidInput
+            ),
+            SetupError::FailedToConnect
+            | SetupError::NoResponseFromAuthenticate
+            | SetupError::Timeout(_) => true,
+            SetupError::RequestError(error) => matches!(
+                error,
+                HttpClientError::RequestError(_) | HttpClientError::TooManyRequests { .. }
+            ),
+            SetupError::ApiFail(_)
+            | SetupError::ApiError(_)
+            | SetupError::AttemptingToAuthWithOldFlow
+            | SetupError::FailedToDecodeSignedAgentRegisterHex
+            | SetupError::RegisterInvalidSignature
+            | SetupError::RegisterUnauthorized => false,
+        }
+    }
+}
+
 impl From<TimeoutSource> for SetupError {
     fn from(value: TimeoutSource) -> Self {
         SetupError::Timeout(value)
@@ -149,3 +175,29 @@ impl From<TimeoutSource> for ControlError {
         ControlError::Timeout(value)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use super::{SetupError, TimeoutSource};
+
+    #[test]
+    fn transient_setup_errors() {
+        assert!(SetupError::IoError(ErrorKind::NetworkUnreachable.into()).is_transient());
+        /* the interface's address can be missing for a moment after a network change */
+        assert!(SetupError::IoError(ErrorKind::AddrNotAvailable.into()).is_transient());
+        assert!(SetupError::FailedToConnect.is_transient());
+        assert!(
+            SetupError::Timeout(TimeoutSource {
+                file_name: "test.rs",
+                line_no: 1
+            })
+            .is_transient()
+        );
+
+        assert!(!SetupError::IoError(ErrorKind::PermissionDenied.into()).is_transient());
+        assert!(!SetupError::RegisterUnauthorized.is_transient());
+        assert!(!SetupError::ApiFail("bad".to_string()).is_transient());
+    }
+}
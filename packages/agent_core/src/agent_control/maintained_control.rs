@@ -1,5 +1,7 @@
 use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use playit_agent_proto::control_feed::{ControlFeed, NewClient};
@@ -14,6 +16,41 @@ use super::connected_control::ConnectedControl;
 use super::errors::SetupError;
 use super::{AuthResource, PacketIO};
 
+/// How long a re-established session has to stay healthy before the reconnect
+/// attempt counter is reset.
+const STABLE_SESSION_MS: u64 = 30_000;
+
+/// Point in time view of the control session for troubleshooting, refreshed on every
+/// `update`. Times are unix ms, 0 for never.
+#[derive(Clone, Debug, Default)]
+pub struct ControlDiagnostics {
+    pub control_addr: Option<SocketAddr>,
+    pub tunnel_addr: Option<SocketAddr>,
+    pub client_addr: Option<SocketAddr>,
+    pub data_center_id: Option<u32>,
+    pub session_expire_at: u64,
+    /// Why the session is being re-established, `None` while healthy
+    pub expired: Option<String>,
+    pub last_authenticated: u64,
+    pub last_keep_alive: u64,
+    pub last_ping: u64,
+    pub last_pong: u64,
+    /// Local addresses of the control socket, filled in by whoever binds it
+    pub local_addrs: Vec<SocketAddr>,
+    pub state: ControlState,
+}
+
+/// Health of the control link itself, independent of whether any tunnel is enabled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlState {
+    /// The last attempt to re-establish the session failed
+    #[default]
+    Disconnected,
+    /// Session expired or timed out, re-establishing
+    Connecting,
+    Connected,
+}
+
 pub struct MaintainedControl<I: PacketIO, A: AuthResource> {
     control: EstablishedControl<A, I>,
     last_keep_alive: u64,
@@ -21,6 +58,11 @@ pub struct MaintainedControl<I: PacketIO, A: AuthResource> {
     last_pong: u64,
     last_udp_auth: u64,
     last_control_targets: Vec<SocketAddr>,
+    last_authenticated: u64,
+    state: ControlState,
+    reconnect_attempts: Arc<AtomicU32>,
+    observed_addr: Arc<Mutex<Option<SocketAddr>>>,
+    diagnostics: Arc<Mutex<ControlDiagnostics>>,
 }
 
 impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
@@ -36,19 +78,88 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
             .try_timeout(Duration::from_secs(10))
             .await?;
 
-        Ok(MaintainedControl {
+        let observed_addr = Arc::new(Mutex::new(Some(control_channel.pong_at_auth.client_addr)));
+
+        let control = MaintainedControl {
             control: control_channel,
             last_keep_alive: 0,
             last_ping: 0,
             last_pong: 0,
             last_udp_auth: 0,
             last_control_targets: addresses,
-        })
+            last_authenticated: now_milli(),
+            state: ControlState::Connected,
+            reconnect_attempts: Arc::new(AtomicU32::new(0)),
+            observed_addr,
+            diagnostics: Arc::new(Mutex::new(ControlDiagnostics::default())),
+        };
+        control.publish_diagnostics();
+
+        Ok(control)
+    }
+
+    /// Number of attempts to re-establish the session since it was last stable
+    pub fn reconnect_attempts(&self) -> Arc<AtomicU32> {
+        self.reconnect_attempts.clone()
+    }
+
+    /// Our address as seen by the control server, updated from every pong
+    pub fn observed_addr(&self) -> Arc<Mutex<Option<SocketAddr>>> {
+        self.observed_addr.clone()
+    }
+
+    pub fn diagnostics(&self) -> Arc<Mutex<ControlDiagnostics>> {
+        self.diagnostics.clone()
+    }
+
+    fn publish_diagnostics(&self) {
+        let mut lock = self.diagnostics.lock().expect("diagnostics lock poisoned");
+        lock.control_addr = Some(self.control.conn.control_addr);
+        lock.tunnel_addr = Some(self.control.conn.pong_latest.tunnel_addr);
+        lock.client_addr = Some(self.control.conn.pong_latest.client_addr);
+        lock.data_center_id = Some(self.control.conn.pong_latest.data_center_id);
+        lock.session_expire_at = self.control.get_expire_at();
+        lock.expired = self.control.is_expired().map(|reason| format!("{:?}", reason));
+        lock.last_authenticated = self.last_authenticated;
+        lock.last_keep_alive = self.last_keep_alive;
+        lock.last_ping = self.last_ping;
+        lock.last_pong = self.last_pong;
+        lock.state = match self.state {
+            ControlState::Connected if lock.expired.is_some() => ControlState::Connecting,
+            state => state,
+        };
+    }
+
+    /// Drop the current session so the next update re-establishes it, ex. when the
+    /// device was suspended and the session is likely dead without having timed out yet
+    pub fn set_expired(&mut self) {
+        self.last_pong = 0;
+        self.control.set_expired();
     }
 
     pub async fn reload_control_addr<E: Into<SetupError>, C: Future<Output = Result<I, E>>>(
         &mut self,
         create_io: C,
+    ) -> Result<bool, SetupError> {
+        self.reload(create_io, None::<fn(&mut I)>).await
+    }
+
+    /// `reload_control_addr` for an io that can't be bound twice, ex. on a fixed source
+    /// port: when the addresses changed `release` closes the current io before `create_io`
+    /// binds its replacement, which then always takes over. If that fails the addresses
+    /// stay unchanged, so the next reload binds again.
+    pub async fn rebind_control_addr<E: Into<SetupError>, C: Future<Output = Result<I, E>>>(
+        &mut self,
+        release: impl FnOnce(&mut I),
+        create_io: C,
+    ) -> Result<bool, SetupError> {
+        self.reload(create_io, Some(release)).await
+    }
+
+    async fn reload<E: Into<SetupError>, C: Future<Output = Result<I, E>>, R: FnOnce(&mut I)>(
+        &mut self,
+        create_io: C,
+        release: Option<R>,
     ) -> Result<bool, SetupError> {
         let addresses = self
             .control
@@ -61,6 +172,11 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
             return Ok(false);
         }
 
+        let rebind = release.is_some();
+        if let Some(release) = release {
+            release(&mut self.control.conn.packet_io);
+        }
+
         let new_io = async { create_io.await.map_err(|e| e.into()) }
             .try_timeout(Duration::from_secs(5))
             .await?;
@@ -71,7 +187,7 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
             .await?;
 
         let updated = self
-            .replace_connection(connected, false)
+            .replace_connection(connected, rebind)
             .try_timeout(Duration::from_secs(5))
             .await?;
 
@@ -122,8 +238,19 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
     }
 
     pub async fn update(&mut self) -> Option<TunnelControlEvent> {
+        let event = self.update_inner().await;
+        self.publish_diagnostics();
+        event
+    }
+
+    async fn update_inner(&mut self) -> Option<TunnelControlEvent> {
         if let Some(reason) = self.control.is_expired() {
             tracing::warn!(?reason, "session expired");
+            let attempt = self.reconnect_attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            tracing::info!(attempt, "re-establishing session");
+            if self.state == ControlState::Connected {
+                self.state = ControlState::Connecting;
+            }
 
             if let Err(error) = self
                 .control
@@ -132,9 +259,14 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
                 .await
             {
                 tracing::error!(?error, "failed to authenticate");
+                self.state = ControlState::Disconnected;
+                self.publish_diagnostics();
                 tokio::time::sleep(Duration::from_secs(2)).await;
                 return None;
             }
+
+            self.last_authenticated = now_milli();
+            self.state = ControlState::Connected;
         }
 
         let now = now_milli();
@@ -197,6 +329,8 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
                     }
                     ControlResponse::Pong(pong) => {
                         self.last_pong = now_milli();
+                        *self.observed_addr.lock().expect("observed addr lock poisoned") =
+                            Some(pong.client_addr);
 
                         if pong.client_addr != self.control.pong_at_auth.client_addr {
                             tracing::info!(
@@ -229,6 +363,12 @@ impl<I: PacketIO, A: AuthResource> MaintainedControl<I, A> {
 
             self.last_pong = 0;
             self.control.set_expired();
+        } else if self.last_authenticated < self.last_pong
+            && self.control.is_expired().is_none()
+            && STABLE_SESSION_MS < now_milli() - self.last_authenticated
+            && self.reconnect_attempts.load(Ordering::SeqCst) != 0
+        {
+            self.reconnect_attempts.store(0, Ordering::SeqCst);
         }
 
         None
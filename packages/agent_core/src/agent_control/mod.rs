@@ -1,6 +1,6 @@
 use std::{
     future::Future,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     sync::{Arc, atomic::AtomicUsize},
     task::Poll,
 };
@@ -14,6 +14,7 @@ pub use playit_api_client::api::SignedAgentKey;
 use playit_api_client::{
     PlayitApi,
     api::{ReqAgentsRoutingGet, ReqProtoRegister},
+    http_client::HttpClientSettings,
 };
 
 use crate::{agent_control::platform::current_platform, utils::error_helper::ErrorHelper};
@@ -94,18 +95,48 @@ impl<T: PacketIO> PacketTx for Arc<T> {
 }
 
 pub struct DualStackUdpSocket {
-    ip4: UdpSocket,
+    ip4: Option<UdpSocket>,
     ip6: Option<UdpSocket>,
     next: AtomicUsize,
 }
 
 impl DualStackUdpSocket {
     pub async fn new() -> std::io::Result<Self> {
-        let ip4 =
-            UdpSocket::bind(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))).await?;
+        Self::bind(None).await
+    }
+
+    /// Bind to a specific local address (ex. to force traffic over one interface). Only
+    /// the matching address family is available when an address is given.
+    pub async fn bind(local: Option<IpAddr>) -> std::io::Result<Self> {
+        Self::bind_port(local, 0).await
+    }
+
+    /// Like `bind` but from a fixed local port (0 picks one). Without a local address the
+    /// IPv6 socket is skipped if the port is only free on IPv4.
+    pub async fn bind_port(local: Option<IpAddr>, port: u16) -> std::io::Result<Self> {
+        let Some(local) = local else {
+            return Self::bind_unspecified(port).await;
+        };
+
+        let socket = UdpSocket::bind(SocketAddr::new(local, port)).await?;
+        let (ip4, ip6) = match local {
+            IpAddr::V4(_) => (Some(socket), None),
+            IpAddr::V6(_) => (None, Some(socket)),
+        };
+
+        Ok(DualStackUdpSocket {
+            ip4,
+            ip6,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    async fn bind_unspecified(port: u16) -> std::io::Result<Self> {
+        let ip4 = UdpSocket::bind(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)))
+            .await?;
         let ip6 = UdpSocket::bind(SocketAddr::V6(SocketAddrV6::new(
             Ipv6Addr::UNSPECIFIED,
-            0,
+            port,
             0,
             0,
         )))
@@ -113,19 +144,33 @@ impl DualStackUdpSocket {
         .ok();
 
         Ok(DualStackUdpSocket {
-            ip4,
+            ip4: Some(ip4),
             ip6,
             next: AtomicUsize::new(0),
         })
     }
 
     pub fn local_ip4_port(&self) -> Option<u16> {
-        Some(self.ip4.local_addr().ok()?.port())
+        Some(self.ip4.as_ref()?.local_addr().ok()?.port())
     }
 
     pub fn local_ip6_port(&self) -> Option<u16> {
         Some(self.ip6.as_ref()?.local_addr().ok()?.port())
     }
+
+    /// Drops both sockets, freeing their ports. Sends fail and receives wait from then on.
+    pub fn close(&mut self) {
+        self.ip4 = None;
+        self.ip6 = None;
+    }
+
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        [self.ip4.as_ref(), self.ip6.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|socket| socket.local_addr().ok())
+            .collect()
+    }
 }
 
 impl PacketIO for DualStackUdpSocket {
@@ -135,7 +180,10 @@ impl PacketIO for DualStackUdpSocket {
                 return ip6.send_to(buf, target).await;
             }
         }
-        self.ip4.send_to(buf, target).await
+        match &self.ip4 {
+            Some(ip4) => ip4.send_to(buf, target).await,
+            None => Err(std::io::ErrorKind::AddrNotAvailable.into()),
+        }
     }
 
     async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
@@ -145,13 +193,13 @@ impl PacketIO for DualStackUdpSocket {
             PoolBoth {
                 buffer: buf,
                 a: self.ip6.as_ref(),
-                b: Some(&self.ip4),
+                b: self.ip4.as_ref(),
             }
             .await
         } else {
             PoolBoth {
                 buffer: buf,
-                a: Some(&self.ip4),
+                a: self.ip4.as_ref(),
                 b: self.ip6.as_ref(),
             }
             .await
@@ -233,7 +281,15 @@ pub struct AuthApi {
 
 impl AuthApi {
     pub fn new(api_url: String, secret_key: String) -> Self {
-        let client = PlayitApi::create(api_url, Some(secret_key));
+        Self::with_http_settings(api_url, secret_key, &HttpClientSettings::default())
+    }
+
+    pub fn with_http_settings(
+        api_url: String,
+        secret_key: String,
+        http_settings: &HttpClientSettings,
+    ) -> Self {
+        let client = PlayitApi::create_with_settings(api_url, Some(secret_key), http_settings);
         AuthApi { client }
     }
 }
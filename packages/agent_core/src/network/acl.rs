@@ -0,0 +1,163 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// An address range in CIDR notation, a bare address is a single host
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpRange {
+    base: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidIpRange(pub String);
+
+impl fmt::Display for InvalidIpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid IP range: {}", self.0)
+    }
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.base, ip.to_canonical()) {
+            (IpAddr::V4(base), IpAddr::V4(ip)) => {
+                prefix_matches(&base.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(base), IpAddr::V6(ip)) => {
+                prefix_matches(&base.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(base: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let full = (prefix / 8) as usize;
+    if base[..full] != ip[..full] {
+        return false;
+    }
+
+    let rest = prefix % 8;
+    if rest == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - rest);
+    base[full] & mask == ip[full] & mask
+}
+
+impl FromStr for IpRange {
+    type Err = InvalidIpRange;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidIpRange(s.to_string());
+        let (ip, prefix) = match s.trim().split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (s.trim(), None),
+        };
+
+        let base = IpAddr::from_str(ip).map_err(|_| invalid())?.to_canonical();
+        let max = if base.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if max < prefix {
+            return Err(invalid());
+        }
+
+        Ok(IpRange { base, prefix })
+    }
+}
+
+/// Source address filter for new tunnel connections. Deny wins over allow, and an empty
+/// allow list allows everything not denied.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionAcl {
+    pub allow: Vec<IpRange>,
+    pub deny: Vec<IpRange>,
+}
+
+impl ConnectionAcl {
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|range| range.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip))
+    }
+}
+
+/// Handle to an ACL shared by the TCP and UDP accept paths so it can be replaced while
+/// the agent is running. Only new connections are checked.
+#[derive(Clone, Debug, Default)]
+pub struct SharedAcl(Arc<RwLock<ConnectionAcl>>);
+
+impl SharedAcl {
+    pub fn new(acl: ConnectionAcl) -> Self {
+        SharedAcl(Arc::new(RwLock::new(acl)))
+    }
+
+    pub fn set(&self, acl: ConnectionAcl) {
+        *self.0.write().expect("acl lock poisoned") = acl;
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        self.0.read().expect("acl lock poisoned").permits(ip)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConnectionAcl, IpRange};
+
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert!("10.0.0.0/8".parse::<IpRange>().is_ok());
+        assert!("2001:db8::/32".parse::<IpRange>().is_ok());
+        assert!("203.0.113.7".parse::<IpRange>().is_ok());
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("10.0.0/8".parse::<IpRange>().is_err());
+        assert!("wifi".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn range_contains() {
+        assert!(range("10.0.0.0/8").contains("10.200.1.1".parse().unwrap()));
+        assert!(!range("10.0.0.0/8").contains("11.0.0.1".parse().unwrap()));
+        assert!(range("192.168.1.128/25").contains("192.168.1.200".parse().unwrap()));
+        assert!(!range("192.168.1.128/25").contains("192.168.1.100".parse().unwrap()));
+        assert!(range("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+        assert!(range("203.0.113.7").contains("203.0.113.7".parse().unwrap()));
+        assert!(!range("203.0.113.7").contains("203.0.113.8".parse().unwrap()));
+        assert!(range("2001:db8::/32").contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!range("2001:db8::/32").contains("10.0.0.1".parse().unwrap()));
+
+        /* v4 clients can show up as v4 mapped v6 addresses */
+        assert!(range("10.0.0.0/8").contains("::ffff:10.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn deny_wins_and_empty_allow_allows_all() {
+        let acl = ConnectionAcl {
+            allow: vec![],
+            deny: vec![range("203.0.113.0/24")],
+        };
+        assert!(acl.permits("198.51.100.1".parse().unwrap()));
+        assert!(!acl.permits("203.0.113.9".parse().unwrap()));
+
+        let acl = ConnectionAcl {
+            allow: vec![range("198.51.100.0/24")],
+            deny: vec![range("198.51.100.66")],
+        };
+        assert!(acl.permits("198.51.100.1".parse().unwrap()));
+        assert!(!acl.permits("198.51.100.66".parse().unwrap()));
+        assert!(!acl.permits("203.0.113.9".parse().unwrap()));
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Byte budget for data path buffers, shared by the TCP and UDP paths like
+/// [SharedAcl](crate::network::acl::SharedAcl). With a limit, a buffer that doesn't fit
+/// waits for others to be released instead of being allocated. Usage is tracked either way.
+#[derive(Clone, Debug)]
+pub struct BufferBudget(Arc<BudgetInner>);
+
+#[derive(Debug)]
+struct BudgetInner {
+    limit: Option<usize>,
+    /// One permit per byte, only consulted with a limit
+    permits: Arc<Semaphore>,
+    in_use: AtomicUsize,
+}
+
+/// Bytes taken from a [BufferBudget], given back on drop
+#[derive(Debug)]
+pub struct BufferLease {
+    budget: Arc<BudgetInner>,
+    bytes: usize,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Default for BufferBudget {
+    fn default() -> Self {
+        Self::with_limit(None)
+    }
+}
+
+impl BufferBudget {
+    pub fn new(limit: usize) -> Self {
+        Self::with_limit(Some(limit))
+    }
+
+    fn with_limit(limit: Option<usize>) -> Self {
+        let permits = limit.unwrap_or(0).min(Semaphore::MAX_PERMITS);
+        BufferBudget(Arc::new(BudgetInner {
+            limit,
+            permits: Arc::new(Semaphore::new(permits)),
+            in_use: AtomicUsize::new(0),
+        }))
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.0.limit
+    }
+
+    /// Bytes held by live leases
+    pub fn in_use(&self) -> usize {
+        self.0.in_use.load(Ordering::Relaxed)
+    }
+
+    /// Waits until `bytes` fit in the budget, a request larger than the whole limit waits
+    /// for all of it
+    pub async fn acquire(&self, bytes: usize) -> BufferLease {
+        let permit = match self.0.limit {
+            None => None,
+            Some(limit) => {
+                let count = bytes.min(limit).min(u32::MAX as usize) as u32;
+                let permit = self.0.permits.clone().acquire_many_owned(count).await;
+                Some(permit.expect("buffer budget semaphore is never closed"))
+            }
+        };
+        self.lease(bytes, permit)
+    }
+
+    /// Like `acquire` without waiting, None if `bytes` don't fit right now
+    pub fn try_acquire(&self, bytes: usize) -> Option<BufferLease> {
+        let permit = match self.0.limit {
+            None => None,
+            Some(_) => {
+                let count = u32::try_from(bytes).ok()?;
+                Some(self.0.permits.clone().try_acquire_many_owned(count).ok()?)
+            }
+        };
+        Some(self.lease(bytes, permit))
+    }
+
+    fn lease(&self, bytes: usize, permit: Option<OwnedSemaphorePermit>) -> BufferLease {
+        self.0.in_use.fetch_add(bytes, Ordering::Relaxed);
+        BufferLease {
+            budget: self.0.clone(),
+            bytes,
+            _permit: permit,
+        }
+    }
+}
+
+impl Drop for BufferLease {
+    fn drop(&mut self) {
+        self.budget.in_use.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::BufferBudget;
+
+    #[tokio::test]
+    async fn waits_for_released_bytes() {
+        let budget = BufferBudget::new(4096);
+        let first = budget.acquire(2048).await;
+        let second = budget.try_acquire(2048).unwrap();
+        assert_eq!(budget.in_use(), 4096);
+        assert!(budget.try_acquire(1).is_none());
+
+        let waiting = tokio::time::timeout(Duration::from_millis(20), budget.acquire(2048));
+        assert!(waiting.await.is_err());
+
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_secs(1), budget.acquire(2048));
+        let _third = third.await.unwrap();
+        drop(second);
+        assert_eq!(budget.in_use(), 2048);
+
+        let unlimited = BufferBudget::default();
+        let _lease = unlimited.acquire(1 << 30).await;
+        assert_eq!(unlimited.in_use(), 1 << 30);
+        assert_eq!(unlimited.limit(), None);
+    }
+}
@@ -1,3 +1,5 @@
+pub mod acl;
+pub mod buffer_budget;
 pub mod errors;
 pub mod lan_address;
 pub mod origin_lookup;
@@ -1,20 +1,73 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    future::Future,
     net::{IpAddr, SocketAddr},
+    pin::Pin,
     str::FromStr,
+    sync::{Arc, Mutex as SyncMutex, RwLock as SyncRwLock},
+    time::Duration,
 };
 
 use playit_agent_proto::PortProto;
 use playit_api_client::api::{AgentRunDataV1, AgentTunnelV1, PortType, ProxyProtocol, TunnelType};
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 #[derive(Default)]
 pub struct OriginLookup {
     map: RwLock<HashMap<Key, OriginResource>>,
+    /// Origins set by the host per tunnel, kept apart from `map` so rundata updates
+    /// don't replace them
+    overrides: SyncRwLock<HashMap<u64, SocketAddr>>,
+    /// Tunnels whose `local_ip` is a hostname rather than an address
+    hostnames: SyncRwLock<HashMap<u64, String>>,
+    /// Last successful resolution per hostname, kept when a later one fails
+    resolved: Arc<SyncRwLock<HashMap<String, IpAddr>>>,
+    /// Hostnames a lookup has started resolving in the background
+    pending: Arc<SyncMutex<HashSet<String>>>,
+    resolver: Resolver,
+}
+
+/// How long a lookup waits on a hostname that isn't cached yet. The resolve carries on in
+/// the background and fills the cache for later connections.
+const RESOLVE_WAIT: Duration = Duration::from_millis(250);
+
+type ResolveFuture = Pin<Box<dyn Future<Output = std::io::Result<IpAddr>> + Send>>;
+
+#[derive(Clone)]
+struct Resolver(Arc<dyn Fn(String) -> ResolveFuture + Send + Sync>);
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver(Arc::new(|hostname| {
+            Box::pin(async move {
+                tokio::net::lookup_host((hostname.as_str(), 0))
+                    .await?
+                    .next()
+                    .map(|addr| addr.ip())
+                    .ok_or_else(|| {
+                        std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses")
+                    })
+            })
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct OriginResolveError {
+    pub tunnel_id: u64,
+    pub hostname: String,
+    pub error: std::io::Error,
 }
 
 impl OriginLookup {
     pub async fn update_from_run_data(&self, run_data: &AgentRunDataV1) {
+        self.set_hostnames(
+            run_data
+                .tunnels
+                .iter()
+                .filter_map(|tunn| Some((tunn.internal_id, origin_hostname(tunn)?))),
+        );
         self.update(
             run_data
                 .tunnels
@@ -69,12 +122,178 @@ impl OriginLookup {
     }
 
     pub async fn lookup(&self, tunnel_id: u64, is_tcp: bool) -> Option<OriginResource> {
-        self.map
+        let mut res = self
+            .map
             .read()
             .await
             .get(&Key { tunnel_id, is_tcp })
+            .cloned()?;
+
+        if let Some(origin) = self.get_override(tunnel_id) {
+            res.set_origin(origin);
+            return Some(res);
+        }
+
+        if let Some(hostname) = self.hostname(tunnel_id) {
+            let cached = self
+                .resolved
+                .read()
+                .expect("origin resolved lock poisoned")
+                .get(&hostname)
+                .copied();
+
+            let ip = match cached {
+                Some(ip) => ip,
+                None => self.resolve_for_lookup(hostname).await?,
+            };
+            res.set_origin_ip(ip);
+        }
+
+        Some(res)
+    }
+
+    /// Every mapped origin as loaded, without overrides or resolved hostnames applied.
+    /// Passing them to [OriginLookup::update] restores the same mapping.
+    pub async fn resources(&self) -> Vec<OriginResource> {
+        let lock = self.map.read().await;
+        let mut resources: Vec<OriginResource> = Vec::with_capacity(lock.len());
+        for res in lock.values() {
+            /* tcp+udp tunnels are mapped under both keys */
+            if !resources.iter().any(|r| r.tunnel_id == res.tunnel_id) {
+                resources.push(res.clone());
+            }
+        }
+        resources.sort_by_key(|res| res.tunnel_id);
+        resources
+    }
+
+    fn set_hostnames<I: Iterator<Item = (u64, String)>>(&self, hostnames: I) {
+        *self.hostnames.write().expect("origin hostnames lock poisoned") = hostnames.collect();
+    }
+
+    fn hostname(&self, tunnel_id: u64) -> Option<String> {
+        self.hostnames
+            .read()
+            .expect("origin hostnames lock poisoned")
+            .get(&tunnel_id)
             .cloned()
     }
+
+    fn resolve(&self, hostname: &str) -> impl Future<Output = std::io::Result<IpAddr>> + use<> {
+        let resolver = self.resolver.clone();
+        let resolved = self.resolved.clone();
+        let hostname = hostname.to_string();
+
+        async move {
+            let ip = (resolver.0)(hostname.clone()).await?;
+            resolved
+                .write()
+                .expect("origin resolved lock poisoned")
+                .insert(hostname, ip);
+            Ok(ip)
+        }
+    }
+
+    /// Resolves a hostname missing from the cache in its own task so a slow resolver
+    /// can't hold up the caller for more than [RESOLVE_WAIT]. Lookups that miss while a
+    /// resolve is already running don't wait at all.
+    async fn resolve_for_lookup(&self, hostname: String) -> Option<IpAddr> {
+        if !self
+            .pending
+            .lock()
+            .expect("origin pending lock poisoned")
+            .insert(hostname.clone())
+        {
+            tracing::info!(%hostname, "origin hostname still resolving");
+            return None;
+        }
+
+        let pending = self.pending.clone();
+        let resolve = self.resolve(&hostname);
+        let task_hostname = hostname.clone();
+        let task = tokio::spawn(async move {
+            let res = resolve.await;
+            pending
+                .lock()
+                .expect("origin pending lock poisoned")
+                .remove(&task_hostname);
+            res
+        });
+
+        match tokio::time::timeout(RESOLVE_WAIT, task).await {
+            Ok(Ok(Ok(ip))) => Some(ip),
+            Ok(Ok(Err(error))) => {
+                tracing::warn!(%hostname, %error, "failed to resolve origin hostname");
+                None
+            }
+            Ok(Err(error)) => {
+                tracing::error!(%hostname, ?error, "origin resolve task failed");
+                None
+            }
+            Err(_) => {
+                tracing::warn!(%hostname, "origin hostname still resolving");
+                None
+            }
+        }
+    }
+
+    /// Resolves every hostname origin now so connections don't wait on DNS. Failures keep
+    /// the previous address if there was one.
+    pub async fn resolve_hostnames(&self) -> Vec<OriginResolveError> {
+        let hostnames: Vec<_> = self
+            .hostnames
+            .read()
+            .expect("origin hostnames lock poisoned")
+            .iter()
+            .map(|(id, hostname)| (*id, hostname.clone()))
+            .collect();
+
+        let mut errors = Vec::new();
+        for (tunnel_id, hostname) in hostnames {
+            if let Err(error) = self.resolve(&hostname).await {
+                errors.push(OriginResolveError {
+                    tunnel_id,
+                    hostname,
+                    error,
+                });
+            }
+        }
+        errors
+    }
+
+    /// Send the tunnel's connections to `origin` instead of the rundata address, `None`
+    /// to go back to rundata. Applies to new connections.
+    pub fn set_override(&self, tunnel_id: u64, origin: Option<SocketAddr>) {
+        let mut lock = self.overrides.write().expect("origin overrides lock poisoned");
+        match origin {
+            Some(origin) => lock.insert(tunnel_id, origin),
+            None => lock.remove(&tunnel_id),
+        };
+    }
+
+    pub fn get_override(&self, tunnel_id: u64) -> Option<SocketAddr> {
+        self.overrides
+            .read()
+            .expect("origin overrides lock poisoned")
+            .get(&tunnel_id)
+            .copied()
+    }
+}
+
+/// The `local_ip` field when it's set to something other than an IP address
+pub fn origin_hostname(tunn: &AgentTunnelV1) -> Option<String> {
+    let value = tunn
+        .agent_config
+        .fields
+        .iter()
+        .find(|f| f.name.eq("local_ip"))?
+        .value
+        .trim();
+
+    if value.is_empty() || IpAddr::from_str(value).is_ok() {
+        return None;
+    }
+    Some(value.to_string())
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -83,7 +302,7 @@ struct Key {
     is_tcp: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OriginResource {
     pub tunnel_id: u64,
     pub proto: PortProto,
@@ -92,7 +311,8 @@ pub struct OriginResource {
     pub proxy_protocol: Option<ProxyProtocol>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum OriginTarget {
     Https {
         ip: IpAddr,
@@ -188,6 +408,28 @@ impl OriginResource {
         })
     }
 
+    /// Replace the local address of the first port, further ports keep their offset
+    pub fn set_origin(&mut self, origin: SocketAddr) {
+        self.target = match self.target {
+            OriginTarget::Https { https_port, .. } => OriginTarget::Https {
+                ip: origin.ip(),
+                http_port: origin.port(),
+                https_port,
+            },
+            OriginTarget::Port { .. } => OriginTarget::Port {
+                ip: origin.ip(),
+                port: origin.port(),
+            },
+        };
+    }
+
+    /// Replace only the local IP, ports stay as configured
+    pub fn set_origin_ip(&mut self, origin_ip: IpAddr) {
+        match &mut self.target {
+            OriginTarget::Https { ip, .. } | OriginTarget::Port { ip, .. } => *ip = origin_ip,
+        }
+    }
+
     pub fn resolve_local(&self, port_offset: u16) -> Option<SocketAddr> {
         match &self.target {
             OriginTarget::Https {
@@ -217,3 +459,120 @@ impl OriginResource {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use playit_agent_proto::PortProto;
+
+    use super::{OriginLookup, OriginResource, OriginTarget, RESOLVE_WAIT, Resolver};
+
+    fn resource(tunnel_id: u64, port: u16) -> OriginResource {
+        OriginResource {
+            tunnel_id,
+            proto: PortProto::Both,
+            target: OriginTarget::Port {
+                ip: "127.0.0.1".parse().unwrap(),
+                port,
+            },
+            port_count: 2,
+            proxy_protocol: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn override_survives_updates() {
+        let lookup = OriginLookup::default();
+        let origin: SocketAddr = "192.168.1.20:30000".parse().unwrap();
+        lookup.set_override(1, Some(origin));
+
+        lookup.update([resource(1, 25565), resource(2, 8080)].into_iter()).await;
+        let res = lookup.lookup(1, true).await.unwrap();
+        assert_eq!(res.resolve_local(0), Some(origin));
+        assert_eq!(res.resolve_local(1), Some("192.168.1.20:30001".parse().unwrap()));
+        assert_eq!(
+            lookup.lookup(2, false).await.unwrap().resolve_local(0),
+            Some("127.0.0.1:8080".parse().unwrap())
+        );
+
+        /* a later rundata update doesn't clobber it */
+        lookup.update([resource(1, 25566)].into_iter()).await;
+        assert_eq!(lookup.lookup(1, false).await.unwrap().resolve_local(0), Some(origin));
+
+        lookup.set_override(1, None);
+        assert_eq!(
+            lookup.lookup(1, true).await.unwrap().resolve_local(0),
+            Some("127.0.0.1:25566".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn hostname_origin_is_resolved() {
+        let lookup = OriginLookup::default();
+        lookup.set_hostnames([(1, "localhost".to_string())].into_iter());
+        lookup.update([resource(1, 25565), resource(2, 8080)].into_iter()).await;
+
+        assert!(lookup.resolve_hostnames().await.is_empty());
+        let origin = lookup.lookup(1, true).await.unwrap().resolve_local(1).unwrap();
+        assert!(origin.ip().is_loopback());
+        assert_eq!(origin.port(), 25566);
+
+        /* overrides still win over the hostname */
+        let over: SocketAddr = "192.168.1.20:30000".parse().unwrap();
+        lookup.set_override(1, Some(over));
+        assert_eq!(lookup.lookup(1, true).await.unwrap().resolve_local(0), Some(over));
+    }
+
+    #[tokio::test]
+    async fn slow_resolver_doesnt_hold_lookups() {
+        let lookup = OriginLookup {
+            resolver: Resolver(Arc::new(|_| {
+                Box::pin(async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    Ok("192.168.1.20".parse().unwrap())
+                })
+            })),
+            ..OriginLookup::default()
+        };
+        lookup.set_hostnames([(1, "slow.example".to_string())].into_iter());
+        lookup.update([resource(1, 25565)].into_iter()).await;
+
+        let started = Instant::now();
+        assert!(lookup.lookup(1, true).await.is_none());
+        assert!(started.elapsed() < RESOLVE_WAIT * 2);
+
+        /* a second miss doesn't wait on the resolve already running */
+        let started = Instant::now();
+        assert!(lookup.lookup(1, true).await.is_none());
+        assert!(started.elapsed() < RESOLVE_WAIT);
+
+        /* the background resolve fills the cache for later connections */
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert_eq!(
+            lookup.lookup(1, true).await.unwrap().resolve_local(0),
+            Some("192.168.1.20:25565".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn resources_restore_mapping() {
+        let lookup = OriginLookup::default();
+        lookup.update([resource(2, 8080), resource(1, 25565)].into_iter()).await;
+
+        let json = serde_json::to_string(&lookup.resources().await).unwrap();
+        let resources: Vec<OriginResource> = serde_json::from_str(&json).unwrap();
+        assert_eq!(resources.iter().map(|res| res.tunnel_id).collect::<Vec<_>>(), [1, 2]);
+
+        let restored = OriginLookup::default();
+        restored.update(resources.into_iter()).await;
+        assert_eq!(
+            restored.lookup(2, false).await.unwrap().resolve_local(1),
+            Some("127.0.0.1:8081".parse().unwrap())
+        );
+    }
+}
@@ -2,6 +2,7 @@ use serde::Serialize;
 use tokio::net::TcpStream;
 use tokio_util::sync::CancellationToken;
 
+use crate::network::buffer_budget::BufferBudget;
 use crate::stats::AgentStats;
 
 use super::tcp_pipe::{PipeDirection, TcpPipe};
@@ -13,10 +14,15 @@ pub struct TcpClient {
 
 impl TcpClient {
     pub async fn create(tunn: TcpStream, origin: TcpStream) -> Self {
-        Self::create_with_stats(tunn, origin, None).await
+        Self::create_with_stats(tunn, origin, None, BufferBudget::default()).await
     }
 
-    pub async fn create_with_stats(tunn: TcpStream, origin: TcpStream, stats: Option<AgentStats>) -> Self {
+    pub async fn create_with_stats(
+        tunn: TcpStream,
+        origin: TcpStream,
+        stats: Option<AgentStats>,
+        budget: BufferBudget,
+    ) -> Self {
         let (tunn_read, tunn_write) = tunn.into_split();
         let (origin_read, origin_write) = origin.into_split();
 
@@ -29,6 +35,7 @@ impl TcpClient {
                 origin_write,
                 stats.clone(),
                 PipeDirection::TunnelToOrigin,
+                budget.clone(),
             ),
             origin_to_tunn: TcpPipe::new_with_stats(
                 cancel,
@@ -36,6 +43,7 @@ impl TcpClient {
                 tunn_write,
                 stats,
                 PipeDirection::OriginToTunnel,
+                budget,
             ),
         }
     }
@@ -47,6 +55,11 @@ impl TcpClient {
         }
     }
 
+    /// Both directions have ended (EOF, error or cancelled)
+    pub fn is_closed(&self) -> bool {
+        self.tunn_to_origin.is_closed() && self.origin_to_tunn.is_closed()
+    }
+
     pub fn bytes_written(&self) -> TcpClientStat {
         TcpClientStat {
             tunn_to_origin: self.tunn_to_origin.bytes_written(),
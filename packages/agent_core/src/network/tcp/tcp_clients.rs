@@ -1,4 +1,14 @@
-use std::{net::SocketAddr, num::NonZeroU32, sync::Arc, time::Duration};
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
 use playit_agent_proto::control_feed::NewClient;
@@ -6,7 +16,7 @@ use playit_api_client::api::ProxyProtocol;
 use serde::Serialize;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    net::{TcpSocket, TcpStream},
     sync::mpsc::{channel, Receiver, Sender},
     time::Instant,
 };
@@ -23,9 +33,12 @@ use crate::{
 use super::{
     tcp_client::{TcpClient, TcpClientStat},
     tcp_errors::tcp_errors,
-    tcp_settings::TcpSettings,
+    tcp_settings::{ConnectionLimitMode, TcpSettings},
 };
 
+/// How often queued clients are started or timed out while over `max_connections`
+const QUEUE_DRAIN_INTERVAL: Duration = Duration::from_millis(250);
+
 pub struct TcpClients {
     events_tx: Sender<Event>,
     new_client_limiter: DefaultDirectRateLimiter,
@@ -42,6 +55,18 @@ struct Worker {
 
     clients: Vec<Client>,
     next_client_id: u64,
+    /// New clients waiting for a free slot when at max connections
+    queued: VecDeque<(Instant, NewClient)>,
+    /// Clients still claiming / connecting to origin
+    connecting: Arc<AtomicUsize>,
+}
+
+struct ConnectingGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
 struct Client {
@@ -53,6 +78,7 @@ struct Client {
     tunnel_addr: SocketAddr,
     origin_addr: SocketAddr,
     tcp: TcpClient,
+    stats: AgentStats,
 }
 
 impl Client {
@@ -71,6 +97,19 @@ impl Client {
     }
 }
 
+impl Drop for Client {
+    /* however the client goes, cleared, closed or worker shutdown, it's counted once */
+    fn drop(&mut self) {
+        let last_use = self.tcp.last_use();
+        let used_ms = last_use
+            .tunn_to_origin
+            .max(last_use.origin_to_tunn)
+            .saturating_sub(self.added_at);
+        self.stats.dec_tcp();
+        self.stats.record_duration(used_ms);
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct TcpClientDetails {
     pub id: u64,
@@ -88,6 +127,7 @@ enum Event {
     ClearOld,
     NewClient(NewClient),
     ConnectedClient(Client),
+    DrainQueue,
     GetDetails(tokio::sync::oneshot::Sender<Vec<TcpClientDetails>>),
 }
 
@@ -112,6 +152,8 @@ impl TcpClients {
                 settings,
                 stats,
                 clients: Vec::with_capacity(32),
+                queued: VecDeque::new(),
+                connecting: Arc::new(AtomicUsize::new(0)),
             }
             .start(),
         );
@@ -154,6 +196,8 @@ impl Drop for TcpClients {
 impl Worker {
     pub async fn start(mut self) {
         let mut next_clear = Instant::now() + Duration::from_secs(15);
+        /* outside the loop so a steady stream of other events can't keep pushing it back */
+        let mut next_drain = Instant::now() + QUEUE_DRAIN_INTERVAL;
 
         loop {
             let event = tokio::select! {
@@ -162,6 +206,10 @@ impl Worker {
                     next_clear = Instant::now() + Duration::from_secs(15);
                     Event::ClearOld
                 },
+                _ = tokio::time::sleep_until(next_drain), if !self.queued.is_empty() => {
+                    next_drain = Instant::now() + QUEUE_DRAIN_INTERVAL;
+                    Event::DrainQueue
+                },
                 _ = self.cancel.cancelled() => {
                     tracing::info!("TcpClients worker closed via cancel");
                     break
@@ -170,222 +218,25 @@ impl Worker {
 
             match event {
                 Event::NewClient(details) => {
-                    let client_id = self.next_client_id;
-                    self.next_client_id = client_id + 1;
-
-                    tracing::info!(?details, id = client_id, "New TCP Client");
-
-                    let Some(found) = self.lookup.lookup(details.tunnel_id, true).await else {
-                        tracing::info!(
-                            tunnel_id = details.tunnel_id,
-                            "Could not find tunnel for new client"
-                        );
-                        tcp_errors().new_client_origin_not_found.inc();
-                        continue;
-                    };
-
-                    let proxy_header = match (details.peer_addr, details.connect_addr) {
-                        (SocketAddr::V4(peer), SocketAddr::V4(tunn)) => {
-                            ProxyProtocolHeader::AfInet {
-                                client_ip: *peer.ip(),
-                                proxy_ip: *tunn.ip(),
-                                client_port: peer.port(),
-                                proxy_port: tunn.port(),
-                            }
-                        }
-                        (SocketAddr::V6(peer), SocketAddr::V6(tunn)) => {
-                            ProxyProtocolHeader::AfInet6 {
-                                client_ip: *peer.ip(),
-                                proxy_ip: *tunn.ip(),
-                                client_port: peer.port(),
-                                proxy_port: tunn.port(),
-                            }
-                        }
-                        _ => {
-                            tracing::error!("Tunnel server provide miss match protol versions for peer and connect addr");
-                            tcp_errors().invalid_proto_match.inc();
-                            continue;
-                        }
-                    };
-
-                    let Some(origin_addr) = found.resolve_local(details.port_offset) else {
-                        tracing::error!(
-                            port_offset = details.port_offset,
-                            tunnel_id = details.tunnel_id,
-                            "port offset not valid for tunnel"
-                        );
-                        tcp_errors().new_client_invalid_port_offset.inc();
-                        continue;
-                    };
-
-                    let setting_tcp_no_delay = self.settings.tcp_no_delay;
-
-                    let event_tx = self.events_tx.clone();
-                    let stats = self.stats.clone();
-                    tokio::spawn(async move {
-                        /* connect to tunnel server */
-
-                        let conn_res = tokio::time::timeout(
-                            Duration::from_secs(8),
-                            TcpStream::connect(details.claim_instructions.address),
-                        )
-                        .await;
-
-                        let mut tunn_stream = match conn_res {
-                            Ok(Ok(stream)) => stream,
-                            Err(_) => {
-                                tracing::error!("timeout connecting to claim address");
-                                tcp_errors().new_client_claim_connect_timeout.inc();
-                                return;
-                            }
-                            Ok(Err(error)) => {
-                                tracing::error!(?error, "io error connecting to claim address");
-                                tcp_errors().new_client_claim_connect_error.inc();
-                                return;
-                            }
-                        };
-
-                        if let Err(error) = tunn_stream.set_nodelay(setting_tcp_no_delay) {
-                            tracing::error!(
-                                ?error,
-                                "failed to set tunn tcp no delay, value: {}",
-                                setting_tcp_no_delay
-                            );
-                            tcp_errors().new_client_set_tunnel_no_delay_error.inc();
-                        }
-
-                        /* send token to tunnel server to claim client */
-
-                        let send_res = tokio::time::timeout(
-                            Duration::from_secs(8),
-                            tunn_stream.write_all(&details.claim_instructions.token),
-                        )
-                        .await;
-                        match send_res {
-                            Ok(Ok(_)) => {}
-                            Err(_) => {
-                                tracing::error!("timeout sending claim token");
-                                tcp_errors().new_client_send_claim_timeout.inc();
-                                return;
-                            }
-                            Ok(Err(error)) => {
-                                tracing::error!(
-                                    ?error,
-                                    "io error sending claim instruction to claim address"
-                                );
-                                tcp_errors().new_client_send_claim_error.inc();
-                                return;
-                            }
-                        }
-
-                        let mut expect_buffer = [0u8; 8];
-                        let confirm_res = tokio::time::timeout(
-                            Duration::from_secs(4),
-                            tunn_stream.read_exact(&mut expect_buffer[..]),
-                        )
-                        .await;
-                        match confirm_res {
-                            Ok(Ok(_)) => {}
-                            Err(_) => {
-                                tracing::error!("timeout reading claim token response");
-                                tcp_errors().new_client_claim_expect_timeout.inc();
-                                return;
-                            }
-                            Ok(Err(error)) => {
-                                tracing::error!(?error, "io error reading claim response");
-                                tcp_errors().new_client_claim_expect_error.inc();
-                                return;
-                            }
-                        }
-
-                        /* connect to origin */
-
-                        let connect_res = tokio::time::timeout(
-                            Duration::from_secs(2),
-                            LanAddress::tcp_socket(true, details.peer_addr, origin_addr),
-                        )
-                        .await;
-
-                        let mut origin_stream = match connect_res {
-                            Ok(Ok(stream)) => stream,
-                            Ok(Err(error)) => {
-                                tracing::error!(
-                                    ?error,
-                                    "io error failed to connect to origin: {:?}",
-                                    origin_addr
-                                );
-                                tcp_errors().new_client_origin_connect_error.inc();
-                                return;
-                            }
-                            Err(_) => {
-                                tracing::error!("timeout connecting to origin: {}", origin_addr);
-                                tcp_errors().new_client_origin_connect_timeout.inc();
-                                return;
-                            }
-                        };
-
-                        if let Err(error) = origin_stream.set_nodelay(true) {
-                            tracing::error!(?error, "failed to set origin tcp no delay");
-                            tcp_errors().new_client_set_origin_no_delay_error.inc();
-                        }
-
-                        let proxy_write_res = match found.proxy_protocol {
-                            Some(ProxyProtocol::ProxyProtocolV1) => {
-                                tokio::time::timeout(
-                                    Duration::from_secs(2),
-                                    proxy_header.write_v1_tcp(&mut origin_stream),
-                                )
-                                .await
-                            }
-                            Some(ProxyProtocol::ProxyProtocolV2) => {
-                                tokio::time::timeout(
-                                    Duration::from_secs(2),
-                                    proxy_header.write_v2_tcp(&mut origin_stream),
-                                )
-                                .await
-                            }
-                            None => Ok(Ok(())),
-                        };
-
-                        match proxy_write_res {
-                            Ok(Ok(_)) => {}
-                            Err(_) => {
-                                tracing::error!("timeout sending proxy protocol header");
-                                tcp_errors().new_client_write_proxy_proto_timeout.inc();
-                                return;
-                            }
-                            Ok(Err(error)) => {
-                                tracing::error!(?error, "failed to write proxy protocol header");
-                                tcp_errors().new_client_write_proxy_proto_error.inc();
-                                return;
-                            }
-                        }
-
-                        let tcp_client = TcpClient::create_with_stats(tunn_stream, origin_stream, Some(stats)).await;
-                        let _ = event_tx
-                            .send(Event::ConnectedClient(Client {
-                                id: client_id,
-                                added_at: now_milli(),
-                                tunnel_id: details.tunnel_id,
-                                port_offset: details.port_offset,
-                                source_addr: details.peer_addr,
-                                tunnel_addr: details.connect_addr,
-                                origin_addr,
-                                tcp: tcp_client,
-                            }))
-                            .await;
-                    });
+                    self.accept_or_queue(details).await;
                 }
                 Event::GetDetails(resp) => {
                     let _ = resp.send(self.clients.iter().map(Client::details).collect());
                 }
                 Event::ConnectedClient(client) => {
                     self.clients.push(client);
-                    self.stats.set_tcp(self.clients.len() as u32);
+                }
+                Event::DrainQueue => {
+                    self.drain_queue().await;
                 }
                 Event::ClearOld => {
                     let now = now_milli();
                     self.clients.retain(|client| {
+                        if client.tcp.is_closed() {
+                            tracing::info!(id = client.id, "clear old: connection closed");
+                            return false;
+                        }
+
                         let last_use = client.tcp.last_use();
 
                         let since_tunn = now.max(last_use.tunn_to_origin) - last_use.tunn_to_origin;
@@ -408,11 +259,333 @@ impl Worker {
 
                         true
                     });
-
-                    // Update active TCP connection count
-                    self.stats.set_tcp(self.clients.len() as u32);
                 }
             }
         }
     }
+
+    async fn accept_or_queue(&mut self, details: NewClient) {
+        if !self.settings.acl.permits(details.peer_addr.ip()) {
+            tracing::info!(
+                tunnel_id = details.tunnel_id,
+                peer = %details.peer_addr,
+                "new client blocked by acl"
+            );
+            tcp_errors().new_client_blocked.inc();
+            self.stats.for_tunnel(details.tunnel_id).inc_blocked();
+            return;
+        }
+
+        if !self.at_capacity() {
+            self.handle_new_client(details).await;
+            return;
+        }
+
+        match self.settings.connection_limit_mode {
+            ConnectionLimitMode::Reject => {
+                tracing::info!(tunnel_id = details.tunnel_id, "at max connections, rejecting new client");
+                self.reject(&details);
+            }
+            ConnectionLimitMode::Queue { .. } => {
+                tracing::info!(tunnel_id = details.tunnel_id, "at max connections, queueing new client");
+                self.queued.push_back((Instant::now(), details));
+            }
+        }
+    }
+
+    /// Start queued clients while there is room, rejecting any that waited too long
+    async fn drain_queue(&mut self) {
+        let ConnectionLimitMode::Queue { timeout } = self.settings.connection_limit_mode else {
+            return;
+        };
+
+        while let Some((queued_at, _)) = self.queued.front() {
+            if timeout <= queued_at.elapsed() {
+                let (_, details) = self.queued.pop_front().unwrap();
+                tracing::info!(tunnel_id = details.tunnel_id, "queued client timed out");
+                self.reject(&details);
+                continue;
+            }
+
+            if self.at_capacity() {
+                break;
+            }
+
+            let (_, details) = self.queued.pop_front().unwrap();
+            self.handle_new_client(details).await;
+        }
+    }
+
+    fn at_capacity(&self) -> bool {
+        let Some(max) = self.settings.max_connections else {
+            return false;
+        };
+
+        let open = self.clients.iter().filter(|c| !c.tcp.is_closed()).count()
+            + self.connecting.load(Ordering::Acquire);
+        max as usize <= open
+    }
+
+    fn reject(&self, details: &NewClient) {
+        tcp_errors().new_client_over_capacity.inc();
+        self.stats.for_tunnel(details.tunnel_id).inc_rejected_tcp();
+    }
+
+    async fn handle_new_client(&mut self, details: NewClient) {
+        let client_id = self.next_client_id;
+        self.next_client_id = client_id + 1;
+
+        tracing::info!(?details, id = client_id, "New TCP Client");
+
+        let Some(found) = self.lookup.lookup(details.tunnel_id, true).await else {
+            tracing::info!(
+                tunnel_id = details.tunnel_id,
+                "Could not find tunnel for new client"
+            );
+            tcp_errors().new_client_origin_not_found.inc();
+            return;
+        };
+
+        let proxy_header = match (details.peer_addr, details.connect_addr) {
+            (SocketAddr::V4(peer), SocketAddr::V4(tunn)) => {
+                ProxyProtocolHeader::AfInet {
+                    client_ip: *peer.ip(),
+                    proxy_ip: *tunn.ip(),
+                    client_port: peer.port(),
+                    proxy_port: tunn.port(),
+                }
+            }
+            (SocketAddr::V6(peer), SocketAddr::V6(tunn)) => {
+                ProxyProtocolHeader::AfInet6 {
+                    client_ip: *peer.ip(),
+                    proxy_ip: *tunn.ip(),
+                    client_port: peer.port(),
+                    proxy_port: tunn.port(),
+                }
+            }
+            _ => {
+                tracing::error!("Tunnel server provide miss match protol versions for peer and connect addr");
+                tcp_errors().invalid_proto_match.inc();
+                return;
+            }
+        };
+
+        let Some(origin_addr) = found.resolve_local(details.port_offset) else {
+            tracing::error!(
+                port_offset = details.port_offset,
+                tunnel_id = details.tunnel_id,
+                "port offset not valid for tunnel"
+            );
+            tcp_errors().new_client_invalid_port_offset.inc();
+            return;
+        };
+
+        let setting_tcp_no_delay = self.settings.tcp_no_delay;
+        let origin_connect_retries = self.settings.origin_connect_retries;
+        let origin_connect_retry_delay = self.settings.origin_connect_retry_delay;
+        let bind_address = self.settings.bind_address;
+        let buffer_budget = self.settings.buffer_budget.clone();
+
+        let event_tx = self.events_tx.clone();
+        let stats = self.stats.for_tunnel(details.tunnel_id);
+
+        self.connecting.fetch_add(1, Ordering::AcqRel);
+        let connecting = ConnectingGuard(self.connecting.clone());
+
+        tokio::spawn(async move {
+            let _connecting = connecting;
+
+            /* connect to tunnel server */
+
+            let conn_res = tokio::time::timeout(
+                Duration::from_secs(8),
+                connect_from(bind_address, details.claim_instructions.address),
+            )
+            .await;
+
+            let mut tunn_stream = match conn_res {
+                Ok(Ok(stream)) => stream,
+                Err(_) => {
+                    tracing::error!("timeout connecting to claim address");
+                    tcp_errors().new_client_claim_connect_timeout.inc();
+                    return;
+                }
+                Ok(Err(error)) => {
+                    tracing::error!(?error, "io error connecting to claim address");
+                    tcp_errors().new_client_claim_connect_error.inc();
+                    return;
+                }
+            };
+
+            if let Err(error) = tunn_stream.set_nodelay(setting_tcp_no_delay) {
+                tracing::error!(
+                    ?error,
+                    "failed to set tunn tcp no delay, value: {}",
+                    setting_tcp_no_delay
+                );
+                tcp_errors().new_client_set_tunnel_no_delay_error.inc();
+            }
+
+            /* send token to tunnel server to claim client */
+
+            let send_res = tokio::time::timeout(
+                Duration::from_secs(8),
+                tunn_stream.write_all(&details.claim_instructions.token),
+            )
+            .await;
+            match send_res {
+                Ok(Ok(_)) => {}
+                Err(_) => {
+                    tracing::error!("timeout sending claim token");
+                    tcp_errors().new_client_send_claim_timeout.inc();
+                    return;
+                }
+                Ok(Err(error)) => {
+                    tracing::error!(
+                        ?error,
+                        "io error sending claim instruction to claim address"
+                    );
+                    tcp_errors().new_client_send_claim_error.inc();
+                    return;
+                }
+            }
+
+            let mut expect_buffer = [0u8; 8];
+            let confirm_res = tokio::time::timeout(
+                Duration::from_secs(4),
+                tunn_stream.read_exact(&mut expect_buffer[..]),
+            )
+            .await;
+            match confirm_res {
+                Ok(Ok(_)) => {}
+                Err(_) => {
+                    tracing::error!("timeout reading claim token response");
+                    tcp_errors().new_client_claim_expect_timeout.inc();
+                    return;
+                }
+                Ok(Err(error)) => {
+                    tracing::error!(?error, "io error reading claim response");
+                    tcp_errors().new_client_claim_expect_error.inc();
+                    return;
+                }
+            }
+
+            /* connect to origin */
+
+            let mut attempt = 0;
+            let mut origin_stream = loop {
+                let connect_res = tokio::time::timeout(
+                    Duration::from_secs(2),
+                    LanAddress::tcp_socket(true, details.peer_addr, origin_addr),
+                )
+                .await;
+
+                match connect_res {
+                    Ok(Ok(stream)) => break stream,
+                    Ok(Err(error))
+                        if error.kind() == ErrorKind::ConnectionRefused
+                            && attempt < origin_connect_retries =>
+                    {
+                        attempt += 1;
+                        tracing::warn!(
+                            attempt,
+                            "origin refused connection, retrying: {}",
+                            origin_addr
+                        );
+                        tokio::time::sleep(origin_connect_retry_delay).await;
+                    }
+                    Ok(Err(error)) => {
+                        tracing::error!(
+                            ?error,
+                            "io error failed to connect to origin: {:?}",
+                            origin_addr
+                        );
+                        if error.kind() == ErrorKind::ConnectionRefused {
+                            stats.inc_origin_refused();
+                        }
+                        tcp_errors().new_client_origin_connect_error.inc();
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::error!("timeout connecting to origin: {}", origin_addr);
+                        tcp_errors().new_client_origin_connect_timeout.inc();
+                        return;
+                    }
+                }
+            };
+
+            if let Err(error) = origin_stream.set_nodelay(setting_tcp_no_delay) {
+                tracing::error!(?error, "failed to set origin tcp no delay");
+                tcp_errors().new_client_set_origin_no_delay_error.inc();
+            }
+
+            let proxy_write_res = match found.proxy_protocol {
+                Some(ProxyProtocol::ProxyProtocolV1) => {
+                    tokio::time::timeout(
+                        Duration::from_secs(2),
+                        proxy_header.write_v1_tcp(&mut origin_stream),
+                    )
+                    .await
+                }
+                Some(ProxyProtocol::ProxyProtocolV2) => {
+                    tokio::time::timeout(
+                        Duration::from_secs(2),
+                        proxy_header.write_v2_tcp(&mut origin_stream),
+                    )
+                    .await
+                }
+                None => Ok(Ok(())),
+            };
+
+            match proxy_write_res {
+                Ok(Ok(_)) => {}
+                Err(_) => {
+                    tracing::error!("timeout sending proxy protocol header");
+                    tcp_errors().new_client_write_proxy_proto_timeout.inc();
+                    return;
+                }
+                Ok(Err(error)) => {
+                    tracing::error!(?error, "failed to write proxy protocol header");
+                    tcp_errors().new_client_write_proxy_proto_error.inc();
+                    return;
+                }
+            }
+
+            let tcp_client = TcpClient::create_with_stats(
+                tunn_stream,
+                origin_stream,
+                Some(stats.clone()),
+                buffer_budget,
+            )
+            .await;
+            /* counted down when the Client drops, even if the worker is already gone */
+            stats.inc_tcp();
+            let _ = event_tx
+                .send(Event::ConnectedClient(Client {
+                    id: client_id,
+                    added_at: now_milli(),
+                    tunnel_id: details.tunnel_id,
+                    port_offset: details.port_offset,
+                    source_addr: details.peer_addr,
+                    tunnel_addr: details.connect_addr,
+                    origin_addr,
+                    tcp: tcp_client,
+                    stats,
+                }))
+                .await;
+        });
+    }
+}
+
+async fn connect_from(local: Option<IpAddr>, target: SocketAddr) -> std::io::Result<TcpStream> {
+    let Some(local) = local else {
+        return TcpStream::connect(target).await;
+    };
+
+    let socket = match local {
+        IpAddr::V4(_) => TcpSocket::new_v4()?,
+        IpAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.bind(SocketAddr::new(local, 0))?;
+    socket.connect(target).await
 }
@@ -11,6 +11,8 @@ pub struct TcpErrors {
     pub new_client_origin_not_found: IntCounter,
     pub invalid_proto_match: IntCounter,
     pub new_client_rate_limited: IntCounter,
+    pub new_client_over_capacity: IntCounter,
+    pub new_client_blocked: IntCounter,
     pub new_client_invalid_port_offset: IntCounter,
     pub new_client_claim_connect_timeout: IntCounter,
     pub new_client_claim_connect_error: IntCounter,
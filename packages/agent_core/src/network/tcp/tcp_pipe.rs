@@ -6,9 +6,12 @@ use std::sync::{
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 
+use crate::network::buffer_budget::BufferBudget;
 use crate::stats::AgentStats;
 use crate::utils::now_milli;
 
+pub const PIPE_BUFFER_LEN: usize = 2048;
+
 /// Direction of data flow for stats tracking
 #[derive(Clone, Copy)]
 pub enum PipeDirection {
@@ -44,7 +47,14 @@ impl TcpPipe {
         from: R,
         to: W,
     ) -> Self {
-        Self::new_with_stats(cancel, from, to, None, PipeDirection::TunnelToOrigin)
+        Self::new_with_stats(
+            cancel,
+            from,
+            to,
+            None,
+            PipeDirection::TunnelToOrigin,
+            BufferBudget::default(),
+        )
     }
 
     pub fn new_with_stats<
@@ -56,6 +66,7 @@ impl TcpPipe {
         to: W,
         stats: Option<AgentStats>,
         direction: PipeDirection,
+        budget: BufferBudget,
     ) -> Self {
         let shared = Arc::new(Shared {
             last_activity: AtomicU64::new(now_milli()),
@@ -72,6 +83,7 @@ impl TcpPipe {
                 to,
                 stats,
                 direction,
+                budget,
             }
             .start(),
         );
@@ -115,11 +127,18 @@ struct Worker<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
     to: W,
     stats: Option<AgentStats>,
     direction: PipeDirection,
+    budget: BufferBudget,
 }
 
 impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Worker<R, W> {
     pub async fn start(mut self) {
-        let mut buffer = vec![0u8; 2048];
+        /* with a memory limit this waits for other pipes to free their buffer */
+        let acquire = self.budget.acquire(PIPE_BUFFER_LEN);
+        let Some(_lease) = self.cancel.run_until_cancelled(acquire).await else {
+            self.shared.last_activity.store(u64::MAX, Ordering::Release);
+            return;
+        };
+        let mut buffer = vec![0u8; PIPE_BUFFER_LEN];
 
         loop {
             tokio::task::yield_now().await;
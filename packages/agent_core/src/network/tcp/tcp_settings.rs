@@ -1,8 +1,39 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::network::acl::SharedAcl;
+use crate::network::buffer_budget::BufferBudget;
+
 #[derive(Clone, Debug)]
 pub struct TcpSettings {
     pub new_client_ratelimit: u32,
+    /// New clients let through at once before `new_client_ratelimit` applies, the closest
+    /// thing to an accept backlog as clients arrive over the control channel
     pub new_client_ratelimit_burst: u32,
+    /// TCP_NODELAY on both the tunnel server and the origin side of each connection
     pub tcp_no_delay: bool,
+    /// Extra origin connect attempts when the origin refuses, ex. while the server restarts
+    pub origin_connect_retries: u32,
+    /// Wait before each extra origin connect attempt
+    pub origin_connect_retry_delay: Duration,
+    /// Local address for connections to the tunnel server, origin connections are not affected
+    pub bind_address: Option<IpAddr>,
+    /// Cap on open connections across all tunnels, unlimited if None
+    pub max_connections: Option<u32>,
+    pub connection_limit_mode: ConnectionLimitMode,
+    /// Source address filter, shared with [UdpSettings](crate::network::udp::udp_settings::UdpSettings)
+    pub acl: SharedAcl,
+    /// Pipe buffers of every connection come out of this, shared with the UDP packet pool
+    pub buffer_budget: BufferBudget,
+}
+
+/// What happens to new clients while at `max_connections`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionLimitMode {
+    /// Drop the client right away
+    Reject,
+    /// Hold the client until a connection closes, dropping it after `timeout`
+    Queue { timeout: Duration },
 }
 
 impl Default for TcpSettings {
@@ -11,6 +42,13 @@ impl Default for TcpSettings {
             new_client_ratelimit: 5,
             new_client_ratelimit_burst: 32,
             tcp_no_delay: true,
+            origin_connect_retries: 0,
+            origin_connect_retry_delay: Duration::from_millis(500),
+            bind_address: None,
+            max_connections: None,
+            connection_limit_mode: ConnectionLimitMode::Reject,
+            acl: SharedAcl::default(),
+            buffer_budget: BufferBudget::default(),
         }
     }
 }
@@ -5,7 +5,11 @@ use std::{
 
 use crossbeam::queue::ArrayQueue;
 
+use crate::network::buffer_budget::{BufferBudget, BufferLease};
+
 pub const PACKET_LEN: usize = 2048;
+/// Smallest pool `within_budget` sizes, however tight the limit
+const MIN_BUDGET_PACKETS: usize = 16;
 
 #[derive(Clone)]
 pub struct Packets {
@@ -17,6 +21,8 @@ struct PacketsInner {
     packet_count: usize,
     free_packets: ArrayQueue<*mut u8>,
     waiting: ArrayQueue<Waker>,
+    /// The pool's bytes counted against a [BufferBudget], held as long as the pool
+    _lease: Option<BufferLease>,
 }
 
 unsafe impl Send for PacketsInner {}
@@ -53,10 +59,36 @@ impl Packets {
                 packet_count,
                 free_packets,
                 waiting: ArrayQueue::new(1024),
+                _lease: None,
             }),
         }
     }
 
+    /// Pool of up to `max_count` packets taking at most half of the budget's limit, the rest
+    /// is left for TCP buffers. The whole pool is counted as in use from the start.
+    pub fn within_budget(max_count: usize, budget: &BufferBudget) -> Self {
+        let count = match budget.limit() {
+            None => max_count,
+            Some(limit) => {
+                let fits = (limit / 2 / PACKET_LEN).max(MIN_BUDGET_PACKETS);
+                /* new() rounds up to a power of two, round down here to stay under */
+                let fits = 1 << fits.ilog2();
+                max_count.min(fits)
+            }
+        };
+
+        let mut packets = Self::new(count);
+        let bytes = packets.packet_count() * PACKET_LEN;
+        let lease = budget.try_acquire(bytes);
+        if lease.is_none() {
+            tracing::warn!(bytes, "udp packet pool doesn't fit in the buffer budget");
+        }
+        Arc::get_mut(&mut packets.inner)
+            .expect("new packet pool isn't shared")
+            ._lease = lease;
+        packets
+    }
+
     pub fn packet_count(&self) -> usize {
         self.inner.packet_count
     }
@@ -145,3 +177,22 @@ impl Packet {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{PACKET_LEN, Packets};
+    use crate::network::buffer_budget::BufferBudget;
+
+    #[test]
+    fn pool_fits_half_the_budget() {
+        let budget = BufferBudget::new(1_000_000);
+        let packets = Packets::within_budget(1024 * 16, &budget);
+        assert_eq!(packets.packet_count(), 128);
+        assert_eq!(budget.in_use(), 128 * PACKET_LEN);
+
+        drop(packets);
+        assert_eq!(budget.in_use(), 0);
+        let unlimited = Packets::within_budget(1024, &BufferBudget::default());
+        assert_eq!(unlimited.packet_count(), 1024);
+    }
+}
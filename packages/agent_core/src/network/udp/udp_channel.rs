@@ -36,8 +36,8 @@ struct Shared {
     establish_tx_epoch: AtomicU64,
 }
 
-struct Task {
-    socket: DualStackUdpSocket,
+struct Task<I: PacketIO> {
+    socket: I,
     session: Option<UdpChannelDetails>,
     session_rx: Receiver<UdpChannelDetails>,
 
@@ -52,7 +52,12 @@ struct Task {
 impl UdpChannel {
     pub async fn new(packets: Packets) -> Result<Self, std::io::Error> {
         let socket = DualStackUdpSocket::new().await?;
+        Ok(Self::with_io(socket, packets))
+    }
 
+    /// Create a channel that sends and receives tunneled packets through `socket`
+    /// instead of binding its own UDP sockets.
+    pub fn with_io<I: PacketIO>(socket: I, packets: Packets) -> Self {
         let (session_tx, session_rx) = channel(32);
 
         let (send_tx, send_rx) = channel(1024);
@@ -73,12 +78,12 @@ impl UdpChannel {
             .start(),
         );
 
-        Ok(UdpChannel {
+        UdpChannel {
             session_tx,
             send: send_tx,
             recv: recv_rx,
             shared,
-        })
+        }
     }
 
     pub fn time_since_established(&self) -> Option<Duration> {
@@ -114,7 +119,7 @@ impl UdpChannel {
     }
 }
 
-impl Task {
+impl<I: PacketIO> Task<I> {
     async fn start(mut self) {
         let mut packet = self.packets.allocate_wait().await;
         let mut last_establish_send = Instant::now();
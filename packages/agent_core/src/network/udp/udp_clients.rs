@@ -14,6 +14,7 @@ use tokio::{
 };
 
 use crate::network::{
+    acl::SharedAcl,
     lan_address::LanAddress,
     origin_lookup::{OriginLookup, OriginTarget},
     proxy_protocol::ProxyProtocolHeader,
@@ -37,9 +38,15 @@ pub struct UdpClients {
     rx: Receiver<UdpReceivedPacket>,
 
     new_client_limiter: DefaultDirectRateLimiter,
+    acl: SharedAcl,
+    /// Flows refused by the ACL with when they last sent, so each counts as blocked once
+    blocked_flows: HashMap<UdpClientKey, u64>,
     stats: AgentStats,
 }
 
+/// Blocked flows remembered at most, past that all are forgotten and count again
+const MAX_BLOCKED_FLOWS: usize = 4096;
+
 struct Client {
     id: u64,
     key: UdpClientKey,
@@ -49,8 +56,11 @@ struct Client {
     /* when dropped, rx task get killed */
     _receiver: UdpReceiver,
 
+    created_at: u64,
     from_tunnel_ts: u64,
     from_origin_ts: u64,
+
+    stats: AgentStats,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -85,11 +95,15 @@ impl UdpClients {
             },
             rx: origin_rx,
             new_client_limiter: RateLimiter::direct(quota),
+            acl: settings.acl,
+            blocked_flows: HashMap::new(),
             stats,
         }
     }
 
     pub fn clear_old(&mut self, now_ms: u64) {
+        self.blocked_flows.retain(|_, last_seen| now_ms.saturating_sub(*last_seen) <= 60_000);
+
         self.virtual_clients.retain(|slot, client| {
             let since_origin = now_ms.saturating_sub(client.from_origin_ts);
             let since_tunnel = now_ms.saturating_sub(client.from_tunnel_ts);
@@ -106,14 +120,15 @@ impl UdpClients {
                 let removed = self.virtual_client_lookup.remove(&client.key).unwrap();
                 assert_eq!(removed, slot);
 
+                // Update active UDP count
+                client.stats.dec_udp();
+                let last_use = client.from_tunnel_ts.max(client.from_origin_ts);
+                client.stats.record_duration(last_use.saturating_sub(client.created_at));
                 false
             } else {
                 true
             }
         });
-
-        // Update active UDP count
-        self.stats.set_udp(self.virtual_clients.len() as u32);
     }
 
     pub async fn recv_origin_packet(&mut self) -> UdpReceivedPacket {
@@ -176,7 +191,7 @@ impl UdpClients {
 
         // Track bytes going out (from origin to tunnel)
         let packet_len = packet.packet.len() as u64;
-        self.stats.add_bytes_out(packet_len);
+        client.stats.add_bytes_out(packet_len);
 
         let mut flow = client.flow;
         match &mut flow {
@@ -230,15 +245,14 @@ impl UdpClients {
             SocketAddrV4::new(ip, port_start + extension.port_offset)
         };
 
-        // Track bytes coming in (from tunnel to origin)
         let packet_len = packet.len() as u64;
-        self.stats.add_bytes_in(packet_len);
 
         match self.virtual_client_lookup.entry(key) {
             hash_map::Entry::Occupied(o) => {
                 let slot = *o.get();
 
                 let client = self.virtual_clients.get_mut(slot).unwrap();
+                client.stats.add_bytes_in(packet_len);
 
                 client.from_tunnel_ts = now_ms;
                 if client
@@ -251,6 +265,21 @@ impl UdpClients {
                 }
             }
             hash_map::Entry::Vacant(v) => {
+                let stats = self.stats.for_tunnel(v.key().tunnel_id);
+
+                /* denied packets aren't tunnel traffic, a flow is blocked once while it sends */
+                if !self.acl.permits(v.key().source_addr.ip()) {
+                    if MAX_BLOCKED_FLOWS <= self.blocked_flows.len() {
+                        self.blocked_flows.clear();
+                    }
+                    if self.blocked_flows.insert(v.key().clone(), now_ms).is_none() {
+                        udp_errors().new_client_blocked.inc();
+                        stats.inc_blocked();
+                    }
+                    return;
+                }
+                stats.add_bytes_in(packet_len);
+
                 if self.new_client_limiter.check().is_err() {
                     udp_errors().new_client_ratelimit.inc();
                     return;
@@ -307,8 +336,10 @@ impl UdpClients {
                     socket,
                     _receiver: receiver,
                     flow: client_flow,
+                    created_at: now_ms,
                     from_tunnel_ts: now_ms,
                     from_origin_ts: now_ms,
+                    stats,
                 };
 
                 if let Some(proto) = origin.proxy_protocol {
@@ -337,11 +368,11 @@ impl UdpClients {
                     udp_errors().origin_send_io_error.inc();
                 }
 
+                // Update active UDP count for new client
+                client.stats.inc_udp();
+
                 v.insert(slot);
                 entry.insert(client);
-
-                // Update active UDP count for new client
-                self.stats.set_udp(self.virtual_clients.len() as u32);
             }
         }
     }
@@ -20,6 +20,7 @@ pub struct UdpErrors {
     pub establish_no_session: IntCounter,
 
     pub new_client_ratelimit: IntCounter,
+    pub new_client_blocked: IntCounter,
     pub origin_client_missing: IntCounter,
     pub origin_reject_bad_id: IntCounter,
     pub origin_tunnel_not_found: IntCounter,
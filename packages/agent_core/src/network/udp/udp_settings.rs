@@ -1,7 +1,21 @@
+use std::net::IpAddr;
+
+use crate::network::acl::SharedAcl;
+use crate::network::buffer_budget::BufferBudget;
+
 #[derive(Clone, Debug)]
 pub struct UdpSettings {
     pub new_client_ratelimit: u32,
     pub new_client_ratelimit_burst: u32,
+    /// Local address for the control and tunnel sockets, origin sockets are not affected
+    pub bind_address: Option<IpAddr>,
+    /// Fixed local port for the control socket (ex. for firewall allow rules), None picks
+    /// a free one
+    pub control_source_port: Option<u16>,
+    /// Checked for new flows only, existing flows keep working after an update
+    pub acl: SharedAcl,
+    /// The packet pool is sized to fit in half of its limit, shared with TCP pipe buffers
+    pub buffer_budget: BufferBudget,
 }
 
 impl Default for UdpSettings {
@@ -9,6 +23,10 @@ impl Default for UdpSettings {
         UdpSettings {
             new_client_ratelimit: 16,
             new_client_ratelimit_burst: 32,
+            bind_address: None,
+            control_source_port: None,
+            acl: SharedAcl::default(),
+            buffer_budget: BufferBudget::default(),
         }
     }
 }
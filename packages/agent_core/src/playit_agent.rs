@@ -1,14 +1,18 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use playit_api_client::http_client::HttpClientSettings;
 use tokio::sync::mpsc::channel;
 use tokio::time::Instant;
 use tracing::Instrument;
 
 use crate::agent_control::errors::SetupError;
-use crate::agent_control::maintained_control::{MaintainedControl, TunnelControlEvent};
-use crate::agent_control::{AuthApi, DualStackUdpSocket};
+use crate::agent_control::maintained_control::{
+    ControlDiagnostics, MaintainedControl, TunnelControlEvent,
+};
+use crate::agent_control::{AuthApi, DualStackUdpSocket, PacketIO};
 use crate::network::origin_lookup::OriginLookup;
 use crate::network::tcp::tcp_clients::TcpClients;
 use crate::network::tcp::tcp_settings::TcpSettings;
@@ -27,7 +31,13 @@ pub struct PlayitAgent {
 
     tcp_clients: TcpClients,
     keep_running: Arc<AtomicBool>,
+    reconnect_attempts: Arc<AtomicU32>,
+    force_reconnect: Arc<AtomicBool>,
+    observed_addr: Arc<Mutex<Option<SocketAddr>>>,
+    diagnostics: Arc<Mutex<ControlDiagnostics>>,
     stats: AgentStats,
+    bind_address: Option<IpAddr>,
+    control_source_port: Option<u16>,
 }
 
 #[derive(Clone, Debug)]
@@ -36,6 +46,7 @@ pub struct PlayitAgentSettings {
     pub secret_key: String,
     pub tcp_settings: TcpSettings,
     pub udp_settings: UdpSettings,
+    pub http_settings: HttpClientSettings,
 }
 
 impl PlayitAgent {
@@ -43,14 +54,34 @@ impl PlayitAgent {
         settings: PlayitAgentSettings,
         lookup: Arc<OriginLookup>,
     ) -> Result<Self, SetupError> {
-        let io = DualStackUdpSocket::new().await?;
-        let auth = AuthApi::new(settings.api_url, settings.secret_key);
+        let udp_io = DualStackUdpSocket::bind(settings.udp_settings.bind_address).await?;
+        Self::new_with_udp_io(settings, lookup, udp_io).await
+    }
+
+    /// Setup the agent with tunneled UDP traffic sent and received through `udp_io`
+    /// rather than sockets bound by the agent (ex. a host provided packet flow).
+    pub async fn new_with_udp_io<I: PacketIO>(
+        settings: PlayitAgentSettings,
+        lookup: Arc<OriginLookup>,
+        udp_io: I,
+    ) -> Result<Self, SetupError> {
+        let bind_address = settings.udp_settings.bind_address;
+        let control_source_port = settings.udp_settings.control_source_port;
+        let io = bind_control_socket(bind_address, control_source_port).await?;
+        let local_addrs = io.local_addrs();
+        let auth = AuthApi::with_http_settings(
+            settings.api_url,
+            settings.secret_key,
+            &settings.http_settings,
+        );
         let control = MaintainedControl::setup(io, auth).await?;
+        let reconnect_attempts = control.reconnect_attempts();
+        let observed_addr = control.observed_addr();
+        let diagnostics = control.diagnostics();
+        diagnostics.lock().expect("diagnostics lock poisoned").local_addrs = local_addrs;
 
-        let packets = Packets::new(1024 * 16);
-        let udp_channel = UdpChannel::new(packets.clone())
-            .await
-            .map_err(SetupError::IoError)?;
+        let packets = Packets::within_budget(1024 * 16, &settings.udp_settings.buffer_budget);
+        let udp_channel = UdpChannel::with_io(udp_io, packets.clone());
 
         let stats = AgentStats::new();
         let udp_clients = UdpClients::new(settings.udp_settings, lookup.clone(), packets.clone(), stats.clone());
@@ -62,7 +93,13 @@ impl PlayitAgent {
             udp_channel,
             tcp_clients,
             keep_running: Arc::new(AtomicBool::new(true)),
+            reconnect_attempts,
+            force_reconnect: Arc::new(AtomicBool::new(false)),
+            observed_addr,
+            diagnostics,
             stats,
+            bind_address,
+            control_source_port,
         })
     }
 
@@ -70,6 +107,28 @@ impl PlayitAgent {
         self.keep_running.clone()
     }
 
+    /// Counter of attempts to re-establish the control session, reset to zero once the
+    /// session has been stable for a while
+    pub fn reconnect_attempts(&self) -> Arc<AtomicU32> {
+        self.reconnect_attempts.clone()
+    }
+
+    /// Set to true to have the control session re-established right away, the flag is
+    /// cleared once the agent picks it up
+    pub fn force_reconnect(&self) -> Arc<AtomicBool> {
+        self.force_reconnect.clone()
+    }
+
+    /// Our public address as seen by the playit control server, changes with the network
+    pub fn observed_addr(&self) -> Arc<Mutex<Option<SocketAddr>>> {
+        self.observed_addr.clone()
+    }
+
+    /// Control session details for troubleshooting, kept current while the agent runs
+    pub fn diagnostics(&self) -> Arc<Mutex<ControlDiagnostics>> {
+        self.diagnostics.clone()
+    }
+
     /// Get a handle to the agent stats
     pub fn stats(&self) -> AgentStats {
         self.stats.clone()
@@ -78,6 +137,10 @@ impl PlayitAgent {
     pub async fn run(self) {
         let mut control = self.control;
         let tunnel_run = self.keep_running.clone();
+        let bind_address = self.bind_address;
+        let control_source_port = self.control_source_port;
+        let force_reconnect = self.force_reconnect.clone();
+        let diagnostics = self.diagnostics.clone();
 
         let (udp_session_tx, mut udp_session_rx) = channel(8);
         let udp_session_should_renew = Arc::new(AtomicBool::new(false));
@@ -89,6 +152,13 @@ impl PlayitAgent {
             while tunnel_run.load(Ordering::SeqCst) {
                 tokio::task::yield_now().await;
 
+                if force_reconnect.swap(false, Ordering::AcqRel) {
+                    tracing::info!("reconnect requested");
+                    control.set_expired();
+                    /* network may have changed too, recheck control address now */
+                    last_control_addr_check = 0;
+                }
+
                 if should_renew_udp.load(Ordering::Acquire)
                     && control.send_udp_session_auth(now_milli(), 5_000).await
                 {
@@ -101,11 +171,29 @@ impl PlayitAgent {
                     if 30_000 < now_milli() - last_control_addr_check {
                         last_control_addr_check = now;
 
-                        if let Err(error) = control
-                            .reload_control_addr(async { DualStackUdpSocket::new().await })
-                            .await
-                        {
-                            tracing::error!(?error, "failed to reload_control_addr");
+                        let mut new_local_addrs = Vec::new();
+                        let create_io = async {
+                            let io = bind_control_socket(bind_address, control_source_port).await?;
+                            new_local_addrs = io.local_addrs();
+                            Ok::<_, std::io::Error>(io)
+                        };
+
+                        /* a fixed source port can't be bound twice, the old socket goes first */
+                        let reloaded = match control_source_port {
+                            Some(_) => {
+                                control
+                                    .rebind_control_addr(DualStackUdpSocket::close, create_io)
+                                    .await
+                            }
+                            None => control.reload_control_addr(create_io).await,
+                        };
+                        match reloaded {
+                            Ok(true) => {
+                                diagnostics.lock().expect("diagnostics lock poisoned").local_addrs =
+                                    new_local_addrs;
+                            }
+                            Ok(false) => {}
+                            Err(error) => tracing::error!(?error, "failed to reload_control_addr"),
                         }
                     }
                 }
@@ -170,3 +258,62 @@ impl PlayitAgent {
         udp_task.await.unwrap();
     }
 }
+
+/// The control socket, from `source_port` if set. A port held by another socket gets an
+/// error naming it rather than a bare "address in use".
+async fn bind_control_socket(
+    bind_address: Option<IpAddr>,
+    source_port: Option<u16>,
+) -> std::io::Result<DualStackUdpSocket> {
+    let Some(port) = source_port else {
+        return DualStackUdpSocket::bind(bind_address).await;
+    };
+
+    DualStackUdpSocket::bind_port(bind_address, port)
+        .await
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::AddrInUse => std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("control_source_port {} is already in use", port),
+            ),
+            _ => error,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::bind_control_socket;
+
+    #[tokio::test]
+    async fn control_source_port_in_use() {
+        let local = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let first = bind_control_socket(local, None).await.unwrap();
+        let port = first.local_ip4_port().unwrap();
+
+        let error = bind_control_socket(local, Some(port)).await.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+        assert_eq!(
+            error.to_string(),
+            format!("control_source_port {} is already in use", port)
+        );
+
+        drop(first);
+        let second = bind_control_socket(local, Some(port)).await.unwrap();
+        assert_eq!(second.local_ip4_port(), Some(port));
+    }
+
+    #[tokio::test]
+    async fn closed_control_socket_frees_port() {
+        let local = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let mut first = bind_control_socket(local, None).await.unwrap();
+        let port = first.local_ip4_port().unwrap();
+
+        /* what rebind_control_addr does before binding the same port again */
+        first.close();
+        assert!(first.local_addrs().is_empty());
+        let second = bind_control_socket(local, Some(port)).await.unwrap();
+        assert_eq!(second.local_ip4_port(), Some(port));
+    }
+}
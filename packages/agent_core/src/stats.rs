@@ -1,12 +1,18 @@
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 /// Shared statistics for the agent
 #[derive(Debug, Default, Clone)]
 pub struct AgentStats {
     inner: Arc<StatsInner>,
+    /// Set when scoped to a tunnel with [AgentStats::for_tunnel]
+    tunnel: Option<Arc<TunnelCounters>>,
 }
 
+/// Upper bounds of the connection duration buckets, the last bucket has no bound
+pub const DURATION_BUCKETS_MS: [u64; 3] = [10_000, 60_000, 600_000];
+
 #[derive(Debug, Default)]
 struct StatsInner {
     /// Bytes received from tunnel (incoming to local)
@@ -17,33 +23,122 @@ struct StatsInner {
     pub active_tcp: AtomicU32,
     /// Active UDP flows
     pub active_udp: AtomicU32,
+    /// TCP clients turned away at max connections
+    pub rejected_tcp: AtomicU64,
+    /// TCP clients and new UDP flows refused by the connection ACL
+    pub blocked: AtomicU64,
+    /// TCP clients dropped because the origin refused the connection
+    pub origin_refused: AtomicU64,
+    /// Counters broken down by tunnel id
+    pub tunnels: Mutex<HashMap<u64, Arc<TunnelCounters>>>,
+    /// Closed TCP connections and UDP flows per `DURATION_BUCKETS_MS` bucket
+    pub durations: [AtomicU64; DURATION_BUCKETS_MS.len() + 1],
+}
+
+#[derive(Debug, Default)]
+struct TunnelCounters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    active_tcp: AtomicU32,
+    active_udp: AtomicU32,
+    rejected_tcp: AtomicU64,
+    blocked: AtomicU64,
+    origin_refused: AtomicU64,
 }
 
 impl AgentStats {
     pub fn new() -> Self {
         AgentStats {
             inner: Arc::new(StatsInner::default()),
+            tunnel: None,
+        }
+    }
+
+    /// Get a handle that updates both the global and the tunnel's counters
+    pub fn for_tunnel(&self, tunnel_id: u64) -> AgentStats {
+        let counters = self
+            .inner
+            .tunnels
+            .lock()
+            .unwrap()
+            .entry(tunnel_id)
+            .or_default()
+            .clone();
+
+        AgentStats {
+            inner: self.inner.clone(),
+            tunnel: Some(counters),
         }
     }
 
     /// Add bytes received from tunnel
     pub fn add_bytes_in(&self, bytes: u64) {
         self.inner.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+        }
     }
 
     /// Add bytes sent to tunnel
     pub fn add_bytes_out(&self, bytes: u64) {
         self.inner.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+        }
     }
 
     /// Increment active TCP connections
     pub fn inc_tcp(&self) {
         self.inner.active_tcp.fetch_add(1, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.active_tcp.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
     /// Decrement active TCP connections
     pub fn dec_tcp(&self) {
         self.inner.active_tcp.fetch_sub(1, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.active_tcp.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a TCP client rejected at max connections
+    pub fn inc_rejected_tcp(&self) {
+        self.inner.rejected_tcp.fetch_add(1, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.rejected_tcp.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a connection refused by the ACL
+    pub fn inc_blocked(&self) {
+        self.inner.blocked.fetch_add(1, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.blocked.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a TCP client dropped after the origin refused every connect attempt
+    pub fn inc_origin_refused(&self) {
+        self.inner.origin_refused.fetch_add(1, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.origin_refused.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record how long a closed TCP connection or UDP flow was in use
+    pub fn record_duration(&self, duration_ms: u64) {
+        let bucket = DURATION_BUCKETS_MS
+            .iter()
+            .position(|&bound| duration_ms < bound)
+            .unwrap_or(DURATION_BUCKETS_MS.len());
+        self.inner.durations[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Closed connection counts per `DURATION_BUCKETS_MS` bucket, then the unbounded one
+    pub fn duration_histogram(&self) -> [u64; DURATION_BUCKETS_MS.len() + 1] {
+        std::array::from_fn(|i| self.inner.durations[i].load(Ordering::Relaxed))
     }
 
     /// Set active TCP connection count
@@ -54,11 +149,17 @@ impl AgentStats {
     /// Increment active UDP flows
     pub fn inc_udp(&self) {
         self.inner.active_udp.fetch_add(1, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.active_udp.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
     /// Decrement active UDP flows
     pub fn dec_udp(&self) {
         self.inner.active_udp.fetch_sub(1, Ordering::Relaxed);
+        if let Some(tunnel) = &self.tunnel {
+            tunnel.active_udp.fetch_sub(1, Ordering::Relaxed);
+        }
     }
 
     /// Set active UDP flow count
@@ -93,8 +194,27 @@ impl AgentStats {
             bytes_out: self.bytes_out(),
             active_tcp: self.active_tcp(),
             active_udp: self.active_udp(),
+            rejected_tcp: self.inner.rejected_tcp.load(Ordering::Relaxed),
+            blocked: self.inner.blocked.load(Ordering::Relaxed),
+            origin_refused: self.inner.origin_refused.load(Ordering::Relaxed),
         }
     }
+
+    /// Get a snapshot of a single tunnel's stats, None if the tunnel has no counters yet
+    pub fn tunnel_snapshot(&self, tunnel_id: u64) -> Option<StatsSnapshot> {
+        let tunnels = self.inner.tunnels.lock().unwrap();
+        let tunnel = tunnels.get(&tunnel_id)?;
+
+        Some(StatsSnapshot {
+            bytes_in: tunnel.bytes_in.load(Ordering::Relaxed),
+            bytes_out: tunnel.bytes_out.load(Ordering::Relaxed),
+            active_tcp: tunnel.active_tcp.load(Ordering::Relaxed),
+            active_udp: tunnel.active_udp.load(Ordering::Relaxed),
+            rejected_tcp: tunnel.rejected_tcp.load(Ordering::Relaxed),
+            blocked: tunnel.blocked.load(Ordering::Relaxed),
+            origin_refused: tunnel.origin_refused.load(Ordering::Relaxed),
+        })
+    }
 }
 
 /// A snapshot of stats at a point in time
@@ -104,4 +224,21 @@ pub struct StatsSnapshot {
     pub bytes_out: u64,
     pub active_tcp: u32,
     pub active_udp: u32,
+    pub rejected_tcp: u64,
+    pub blocked: u64,
+    pub origin_refused: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::AgentStats;
+
+    #[test]
+    fn duration_buckets() {
+        let stats = AgentStats::new();
+        for duration_ms in [0, 9_999, 10_000, 59_999, 600_000, 86_400_000] {
+            stats.for_tunnel(1).record_duration(duration_ms);
+        }
+        assert_eq!(stats.duration_histogram(), [2, 2, 0, 2]);
+    }
 }
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::now_milli;
+
+/// Source of unix time in milliseconds, so time dependent logic can be tested without
+/// sleeping.
+pub trait Clock: Send + Sync {
+    fn now_ms(&self) -> u64;
+}
+
+/// Wall clock, same as [`now_milli`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        now_milli()
+    }
+}
+
+/// Clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now_ms: u64) -> Self {
+        ManualClock {
+            now: AtomicU64::new(now_ms),
+        }
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now.store(now_ms, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
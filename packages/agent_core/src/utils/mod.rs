@@ -1,3 +1,4 @@
+pub mod clock;
 pub mod error_helper;
 pub mod id_slab;
 pub mod instance_count;
@@ -1,6 +1,8 @@
 #ifndef PLAYIT_AGENT_H
 #define PLAYIT_AGENT_H
 
+#include <stdbool.h>
+#include <stddef.h>
 #include <stdint.h>
 
 #ifdef __cplusplus
@@ -8,34 +10,784 @@ extern "C" {
 #endif
 
 typedef enum {
-    PLAYIT_STATUS_STOPPED = 0,
+    PLAYIT_STATUS_STOPPED = 0,  // also set (with last_error) if the agent shut itself down
     PLAYIT_STATUS_CONNECTING = 1,
     PLAYIT_STATUS_CONNECTED = 2,
     PLAYIT_STATUS_DISCONNECTED = 3,
     PLAYIT_STATUS_ERROR = 4,
+    PLAYIT_STATUS_AUTH_FAILED = 5,  // secret key was rejected by the API
+    PLAYIT_STATUS_IDLE = 6,  // started with lazy, not connected until activated
+    // Tunnel is connected (last_address is set) but the local origin of a TCP tunnel can't
+    // be reached, e.g. the game server isn't running. last_error names the host:port.
+    // Probed on start and on each poll while in this state, so it clears by itself.
+    PLAYIT_STATUS_ORIGIN_UNREACHABLE = 7,
+    // The API answered 429. The agent waits for the server's Retry-After (or the poll
+    // interval) and retries by itself; last_error reads "rate limited, retrying in Ns".
+    // Not an error status, the error callback doesn't fire for it.
+    PLAYIT_STATUS_RATE_LIMITED = 8,
+    // Tunnel is connected but a tunnel's local origin is a hostname that doesn't resolve.
+    // last_error names the hostname. Reported instead of ORIGIN_UNREACHABLE so DNS
+    // problems can be told apart from a server that isn't running.
+    PLAYIT_STATUS_ORIGIN_RESOLVE_FAILED = 9,
+    // Terminal: the status stayed DISCONNECTED for longer than disconnect_give_up_ms, so
+    // the agent stopped polling and shut down (playit_is_running is false). Nothing
+    // retries by itself; call playit_start to try again.
+    PLAYIT_STATUS_GAVE_UP = 10,
+    // Terminal: started with require_tunnels and the secret key is valid, but the account
+    // has no tunnels. The agent stopped without creating one; last_error reads "the
+    // account has no tunnels". An error status, the error callback fires for it.
+    PLAYIT_STATUS_NO_TUNNELS = 11,
+    // Was CONNECTED, and the tunnel session dropped (network change, suspend, timeout) and
+    // is being re-established; last_address is kept. Back to CONNECTED once the session
+    // is up, checked every 250ms. Only ever follows CONNECTED, the first connection after
+    // playit_start is CONNECTING. reconnect_attempts counts the tries.
+    PLAYIT_STATUS_RECONNECTING = 12,
+    // Started with a schedule and outside all of its windows: the agent is stopped (no
+    // tunnel session, last_address cleared) and starts by itself at the next window,
+    // through CONNECTING. playit_is_running stays true.
+    PLAYIT_STATUS_SCHEDULED_OFF = 13,
 } playit_status_code;
 
+// Layout version of playit_status, playit_stats and playit_metrics. All start with
+// struct_version and struct_size; check them against PLAYIT_ABI_VERSION and sizeof before
+// reading later fields, so a mismatched build is detected instead of misread. Fields are
+// only ever appended, so struct_size >= sizeof with the same version is safe to read.
+// Functions filling one through a pointer (playit_get_status_out, playit_get_stats,
+// playit_get_tunnel_stats, playit_get_all_metrics) write no more than the struct_size the
+// caller set, which should be sizeof in the caller's build (0 fills only the header), and
+// set struct_size to the size they filled.
+#define PLAYIT_ABI_VERSION 2
+uint32_t playit_status_abi_version(void);
+
 typedef struct {
+    uint32_t struct_version;  // PLAYIT_ABI_VERSION of the library that filled it in
+    uint32_t struct_size;     // sizeof(playit_status) filled in, see above
     int32_t code;
+    // When it differs from the last address seen (across disconnects and restarts) the
+    // change is logged at INFO: "tunnel address changed previous=... address=..."
     const char *last_address;
     const char *last_error;
+    // attempts to re-establish the tunnel session since it was last stable for 30s
+    uint32_t reconnect_attempts;
 } playit_status;
 
 typedef void (*playit_log_callback)(int32_t level, const char *message, void *user_data);
 
 // -1=TRACE, 0=DEBUG, 1=INFO, 2=WARN, 3=ERROR
+// Lines logged while no callback is set (e.g. by playit_init called first) are kept, up
+// to the last 200, and delivered to the callback from inside this call when it is
+// registered, so the first lines received may predate the registration.
+// playit_set_log_callback replaces all log callbacks with this one (NULL removes all).
 void playit_set_log_callback(playit_log_callback callback, void *user_data);
 
+// Several log callbacks can be registered at once (e.g. a file logger and a UI log view);
+// each line is passed to all of them in registration order. playit_add_log_callback
+// returns a handle for removal, 0 for a NULL callback. After playit_remove_log_callback
+// returns the callback is not invoked again; called from inside a log callback, the
+// removal takes effect once the current line has been delivered.
+uint64_t playit_add_log_callback(playit_log_callback callback, void *user_data);
+void playit_remove_log_callback(uint64_t handle);
+
+// Same lines as the log callback, plus category: the tracing target of the line, e.g.
+// "playit_agent_core::network::tcp::tcp_clients", for mapping to an os_log category.
+// Usable instead of or next to playit_set_log_callback; each line goes to every callback
+// set. Same buffering and level filter.
+typedef void (*playit_log_event_callback)(int32_t level, const char *category,
+                                          const char *message, void *user_data);
+void playit_set_log_event_callback(playit_log_event_callback callback, void *user_data);
+
+// Each log event as one line of JSON (no trailing newline), for log aggregators:
+// {"timestamp": "2024-01-02T03:04:05.678Z", "level": "info", "target": "playit_agent",
+//  "message": "...", "fields": {"tunnel_id": 7, "error": "..."}}
+// message is the event text without the fields, which keep their number/bool type where
+// the event recorded one (anything else is its debug text). Same buffering and level
+// filter as the other log callbacks, usable next to them. NULL removes it.
+void playit_set_json_log_callback(playit_log_callback callback, void *user_data);
+
+// Forward (enabled=true) or drop log events by tracing target, e.g. only
+// "playit_agent_core::network::tcp" to see just TCP logs. prefix matches that target and
+// the modules under it ("playit_agent" doesn't match "playit_agent_core"; "" matches all).
+// With several rules the longest matching prefix wins. A target no rule matches is
+// forwarded, unless any rule has enabled=true, then only enabled prefixes are. Applies to
+// every log callback and the log fd, before the log_level filter. Setting a prefix again
+// replaces its rule; NULL removes all rules. 0=ok, -2=invalid UTF-8
+int32_t playit_set_log_target_filter(const char *prefix, bool enabled);
+
+// Longest log message passed to the log callbacks and log fd, in bytes (default 4096).
+// Longer ones, e.g. debug dumps, are cut on a UTF-8 character boundary and end in "…".
+// 0 removes the limit.
+void playit_set_max_log_length(uint32_t bytes);
+
+// Caps log lines passed to the log callbacks and log fd at per_second a second, with
+// bursts of up to a second's worth, so a log storm (e.g. a tight error loop) can't swamp
+// the app's log view or file. Applied after log_level and the target filters; lines over
+// the cap are dropped and counted. 0 (the default) removes the cap.
+void playit_set_log_rate_limit(uint32_t per_second);
+// Lines dropped by the rate limit since the library was loaded (or playit_reset_all).
+uint64_t playit_get_dropped_log_count(void);
+
+// Also write log lines straight to fd (e.g. a log file the app rotates) without crossing
+// the FFI per line, as "2026-01-02T03:04:05.678Z  INFO message\n" in UTC. Works alongside
+// the log callback and uses the same log_level filter. The library never closes fd; keep
+// it open until it is replaced or removed with a negative fd. If a write fails the fd sink
+// is dropped and a WARN line saying so goes to the log callback. Unix only.
+void playit_set_log_fd(int32_t fd);
+
+// Whether this library's tracing subscriber is installed as the process global one. false
+// means something else in the process set a global subscriber first and the log callback
+// will never be called; that subscriber has to forward playit's events instead.
+bool playit_logging_active(void);
+
 // - secret_key (string, required)
 // - api_url (string, optional; default https://api.playit.gg)
 // - poll_interval_ms (number, optional; default 3000)
+// - agent_version (string, optional) - version registered with the API for this agent
+// - agent_name (string, optional; default the device model if set) - shown for this agent
+//   on the playit dashboard and sent in the User-Agent
+// - bind_address (string, optional) - local IP (e.g. the WiFi interface address) that
+//   connections to playit are sent from. Local origin connections are not affected. If the
+//   address isn't assigned to any interface, the default interface is used and last_error
+//   says so.
+// - control_source_port (number, optional; default any free port) - fixed local UDP port
+//   for the connection to playit's control servers, for networks whose firewall only
+//   allows known source ports. 1 to 65535. If another socket holds the port, setup fails
+//   with ERROR and last_error says "control_source_port N is already in use". When the
+//   control server addresses change the socket is closed and bound again on the same
+//   port, so the control link is briefly down during the switch.
+// - log_level (string, optional; default "trace") - lowest level passed to the log callback:
+//   "trace", "debug", "info", "warn" or "error"
+// - worker_threads (number or "auto", optional; default 2) - runtime threads used by the
+//   agent. "auto" uses one per CPU core, at most 1 with a max_connections of 8 or less, 2
+//   with up to 64 and 4 otherwise (including no max_connections). For playit_init_kv pass
+//   "auto" as the value.
+// - runtime_drivers (array of strings, optional; default all) - tokio drivers enabled on
+//   the agent runtime, from "io" and "time". For footprint experiments only: the agent
+//   needs both today, so a list without either is rejected (-3) rather than failing later.
+//   For playit_init_kv use a comma separated list.
+// - max_connections (number, optional; default unlimited) - cap on open TCP connections;
+//   clients over the cap are rejected and counted in playit_stats.rejected_tcp
+// - max_connections_queue_ms (number, optional) - queue clients over the cap for up to this
+//   long waiting for a connection to close, instead of rejecting them right away
+// - tcp_backlog (number, optional; default 32) - new TCP clients accepted at once, after
+//   which new clients are let through at 5 per second and the rest are refused. Raise it
+//   when many players join at the same moment. Clients let through still count against
+//   max_connections and are queued or rejected there, so a backlog above max_connections
+//   only helps together with max_connections_queue_ms.
+// - max_buffer_memory_kb (number, optional; default unlimited, at least 128) - budget for
+//   the data path's buffers, for busy servers on memory constrained devices. The UDP
+//   packet pool is sized to half of it (32MB without a limit) and every TCP connection
+//   takes 4KB from the rest; a connection that doesn't fit waits for another to close
+//   before data flows, rather than allocating more. See playit_metrics.buffer_bytes_in_use.
+// - required_tunnel_id (number, optional) - tunnel id (as in playit_get_tunnels_json) that
+//   decides CONNECTED: only when it is enabled and has an address, otherwise DISCONNECTED
+//   whatever the other tunnels do. last_address is its address. Default: any tunnel.
+//   Also used by playit_fetch_address.
+// - allowed_tunnel_ids (array of numbers, optional; default all) - tunnel ids the agent
+//   manages, for accounts whose other tunnels belong to something else: the others are
+//   not served and left out of the status, last_address and playit_get_tunnels_json. If
+//   none of them are in the account the status is DISCONNECTED with last_error "none of
+//   the account's tunnels are in allowed_tunnel_ids". An empty list means all tunnels.
+//   Also used by playit_fetch_address. For playit_init_kv use a comma separated list.
+// - schedule (array of objects, optional; default always on) - windows the tunnel is
+//   served in, e.g. [{"start": "18:00", "end": "23:30", "days": [5, 6]}]. start and end
+//   are "HH:MM" local time, end before start runs past midnight, days are 0 (Sunday) to 6
+//   and default to every day. Outside all windows the status is SCHEDULED_OFF; the agent
+//   stops at the end of a window (open connections are dropped) and starts again at the
+//   next one, checked at every minute. For playit_init_kv use a comma separated list of
+//   "HH:MM-HH:MM" windows for every day.
+// - schedule_utc_offset_minutes (number, optional; default 0) - local time for schedule as
+//   minutes from UTC, -720 to 840, e.g. 120 for UTC+2. Not adjusted for daylight saving,
+//   update it with playit_reconfigure and restart.
+// - allow_ips, deny_ips (arrays of strings, optional) - source address filter for new
+//   tunnel connections, CIDR ranges ("203.0.113.0/24", "2001:db8::/32") or single IPs.
+//   deny wins over allow; an empty allow list allows everything not denied. Blocked
+//   connections are dropped before reaching the origin and counted in playit_stats.blocked.
+//   For playit_init_kv use a comma separated list.
+// - setup_retries (number, optional; default 3) - retries of a transient agent setup
+//   failure (socket or network briefly unavailable, timeouts) before giving up with ERROR.
+//   Status stays CONNECTING meanwhile. Auth and protocol errors fail right away. 0 disables.
+// - setup_retry_ms (number, optional; default 1000) - wait before the first setup retry,
+//   doubled for each following one up to 30s, plus up to 25% random jitter
+// - startup_jitter_ms (number, optional; default 300) - wait a random 0 to this many ms
+//   before the initial rundata load, so many devices started at once (e.g. by the same
+//   push notification) don't hit the API together. Status stays CONNECTING meanwhile.
+//   0 loads right away, for latency sensitive single device setups.
+// - stop_wait_ms (number, optional; default 2000) - how long playit_stop waits for the
+//   agent thread to shut down
+// - lazy (bool, optional; default false) - playit_start only sets up the agent, see
+//   playit_activate
+// - require_traffic_for_connected (bool, optional; default false) - stay CONNECTING (with
+//   last_address set) until data has come back from a local origin through the tunnel,
+//   then go CONNECTED. A stronger guarantee than a tunnel address alone, but an idle
+//   tunnel that never gets a connection stays CONNECTING indefinitely.
+// - require_tunnels (bool, optional; default false) - for apps that treat an account
+//   without tunnels as a setup error: if the first rundata load finds no tunnels, stop
+//   with PLAYIT_STATUS_NO_TUNNELS instead of creating a default tunnel and waiting.
+//   Only checked at start.
+// - account_info (bool, optional; default false) - keep the account details that come
+//   with each rundata load for playit_get_account_json. Read at start.
+// - idle_reconnect_ms (number, optional; default off) - re-establish the tunnel session
+//   once no tunnel traffic has moved and no connection has been open for this long, for
+//   carriers that drop idle NAT mappings despite keep-alives. Logged at INFO each time,
+//   so expect a periodic "refreshing the tunnel session" line on an idle tunnel.
+// - disconnect_give_up_ms (number, optional; default unlimited) - once the status has been
+//   DISCONNECTED without a break for this long, stop polling and shut the agent down with
+//   PLAYIT_STATUS_GAVE_UP rather than retrying forever. Any other status, ERROR from a
+//   failed poll included, starts the wait over. Needs poll_rundata.
+// - disconnect_grace_ms (number, optional; default 0) - when a poll finds no enabled
+//   tunnel with an address while CONNECTED, stay CONNECTED (keeping last_address) until
+//   polls have gone without one for this long, so a brief rundata hiccup doesn't show as
+//   DISCONNECTED. Only holds CONNECTED; errors, STOPPED and other statuses are reported
+//   as they happen.
+// - poll_rundata (bool, optional; default true) - false for static setups: rundata is
+//   loaded once at start and never polled again, saving battery and API quota. The agent
+//   still keeps its tunnel session alive, but tunnel changes made on the website (new,
+//   removed or disabled tunnels, address changes) are not picked up until the next
+//   playit_start. Status and last_address keep what the initial load reported (an
+//   ORIGIN_UNREACHABLE found then doesn't clear by itself).
+// - tcp_nodelay (bool, optional; default true) - set TCP_NODELAY on both sides of every
+//   tunneled TCP connection so small packets go out right away instead of waiting on
+//   Nagle's algorithm. false trades latency for fewer packets on bulk transfers.
+// - origin_connect_retries (number, optional; default 0) - when the local server refuses
+//   a new TCP connection (ex. mid-restart), try again this many times before dropping the
+//   player. Clients dropped this way are counted in playit_stats.origin_refused.
+// - origin_connect_retry_ms (number, optional; default 500) - wait before each retry
+// - api_timeout_ms (number, optional; default 15000) - limit for each API request (rundata
+//   polls, account checks), so a hung network fails the poll and the usual error and
+//   backoff handling starts instead of waiting indefinitely. 0 disables the limit.
+// - event_log_capacity (number, optional; default 100) - most events kept for
+//   playit_get_event_log_json, the oldest are dropped first. 0 turns the event log off.
+// - insecure_skip_tls_verify (bool, optional; default false) - development/self-host only,
+//   disables certificate checks for api_url and logs a warning
+// -1=null config, -2=invalid UTF-8, -3=invalid JSON (including a non-object such as an
+// array) or missing/empty secret_key, -4=bind_address is not an IP address
 int32_t playit_init(const char *config_json);
 
+// Same as playit_init with the config as exactly len bytes of UTF-8 JSON, for hosts that
+// hold it as a length-delimited buffer (Swift's Data). No nul terminator is needed and
+// nothing past len is read. -1=NULL with len > 0, otherwise the same codes as playit_init.
+int32_t playit_init_buf(const uint8_t *config_json, size_t len);
+
+// Why the last call that parses a config (playit_init, playit_init_kv,
+// playit_reconfigure, playit_ping_api, ...) returned an error, e.g. "expected a JSON
+// object, got an array", "missing required field secret_key", "field poll_interval_ms:
+// invalid type: ..." or "syntax error at position 32 (line 2, column 13): ...". For
+// display and logs only, the wording may change. Same return value as the other buffer
+// functions, -1 if the last config parsed fine.
+int32_t playit_last_init_error(char *buf, size_t len);
+
+// Replace allow_ips/deny_ips without restarting: {"allow": [...], "deny": [...]}, a
+// missing list is empty. Applies to new connections right away when running, open ones
+// are kept. Also stored in the config for the next playit_start (playit_reconfigure
+// applies these live too). 0=ok, -1=null, -2=invalid UTF-8, -3=invalid JSON or range
+int32_t playit_update_acl(const char *acl_json);
+
+// Same as playit_init with the config given as count parallel key/value strings using
+// the keys listed above, e.g. "poll_interval_ms" = "5000", "insecure_skip_tls_verify" = "true".
+// -1=null array or entry (with count > 0), -2=invalid UTF-8, -3=invalid value or
+// missing/empty secret_key (count == 0 gives -3), -4=bind_address is not an IP address
+int32_t playit_init_kv(const char *const *keys, const char *const *values, size_t count);
+
+// The agent's id as the playit.gg dashboard shows it (a UUID), so users can confirm they
+// manage the right agent. Loaded with the first rundata after playit_start and kept after
+// a stop. Returns the id length (truncated if >= len), -1 until it has loaded for the
+// current secret_key (changing the key hides the old id).
+int32_t playit_get_agent_id(char *buf, size_t len);
+
+// Account details for display, with account_info set in the config:
+//   {"plan": "free" | "premium", "account_status": "guest" | "email-not-verified" |
+//    "verified", "self_managed": bool, "limits": null,
+//    "usage": {"tunnels": n, "pending_tunnels": n, "ports": n}}
+// The API has no account endpoint for agents, so this is what each rundata poll says
+// about the account (no extra requests, refreshed with every poll and kept after a stop).
+// Plan limits aren't available to agents; limits stays null. usage.ports is the ports of
+// all tunnels combined. Returns the JSON length (truncated if >= len), -1 until a load for
+// the current secret_key, -3 if account_info isn't set.
+int32_t playit_get_account_json(char *buf, size_t len);
+
+// The API endpoint from the loaded config, api_url or "https://api.playit.gg" when it
+// isn't set, to confirm a self-hosted setup points where it should. Follows
+// playit_reconfigure right away; a running agent keeps using the URL it started with
+// until restarted (PLAYIT_RESTART_ACCOUNT). Returns the URL length (truncated if >= len),
+// -1 if no config is loaded.
+int32_t playit_get_api_url(char *buf, size_t len);
+
+// Fallback for agent_name, e.g. "iPad13,4". Takes effect on the next playit_start.
+// NULL clears it. 0=ok, -2=invalid UTF-8
+int32_t playit_set_device_model(const char *model);
+
+// Replace the config while running, e.g. from a settings screen's apply button.
+// log_level, event_log_capacity, poll_interval_ms (from the next poll), allow_ips and
+// deny_ips apply immediately and stop_wait_ms is read by the next playit_stop; every other
+// field is stored and used by the next playit_start. Returns the same negative codes as
+// playit_init, otherwise a bitmask of changes waiting for a restart (0 = all applied),
+// compared with the config the agent was started with so a pending change is reported
+// again until the restart. Always 0 when the agent isn't running. An unavailable
+// bind_address falls back to the default interface as with playit_init.
+#define PLAYIT_RESTART_ACCOUNT  (1 << 0)  // secret_key, api_url, insecure_skip_tls_verify,
+                                          // api_timeout_ms, account_info
+#define PLAYIT_RESTART_NETWORK  (1 << 1)  // bind_address, control_source_port, tcp_nodelay,
+                                          // tcp_backlog, max_buffer_memory_kb,
+                                          // max_connections, max_connections_queue_ms,
+                                          // origin_connect_retries, origin_connect_retry_ms
+#define PLAYIT_RESTART_RUNTIME  (1 << 2)  // worker_threads, runtime_drivers, poll_rundata,
+                                          // schedule, schedule_utc_offset_minutes, lazy,
+                                          // startup_jitter_ms, setup_retries,
+                                          // setup_retry_ms, idle_reconnect_ms,
+                                          // disconnect_give_up_ms, disconnect_grace_ms,
+                                          // require_traffic_for_connected
+#define PLAYIT_RESTART_IDENTITY (1 << 3)  // agent_name, agent_version
+#define PLAYIT_RESTART_TUNNELS  (1 << 4)  // required_tunnel_id, allowed_tunnel_ids,
+                                          // require_tunnels
+int32_t playit_reconfigure(const char *config_json);
+
+// Stops the agent and starts it again with the current config, e.g. to apply the
+// PLAYIT_RESTART_* changes playit_reconfigure reported. Open connections are dropped.
+// quiet=false reports the restart like any stop and start (STOPPED -> CONNECTING ->
+// CONNECTED). quiet=true keeps the UI steady: the status, error and start result
+// callbacks skip STOPPED and CONNECTING until the new agent settles, then fire once with
+// where it ended up (CONNECTED, IDLE for lazy, DISCONNECTED, RATE_LIMITED or an error).
+// Polling getters and the event log still see every step. playit_stop during a quiet
+// restart ends it. 0=restarted, -1=not running, -2=the stop timed out, -3=the start failed
+// (the callbacks are then told the current status)
+int32_t playit_restart(bool quiet);
+
+// For automation: checks the JSON config file at path every 2s while the agent is running
+// and passes it to playit_reconfigure whenever its modification time or size changes, so
+// editing the file updates the agent. The contents at the time of this call count as
+// applied. A file that doesn't parse is logged (WARN, with the playit_last_init_error
+// reason) and the current config kept. Kept across starts; NULL stops watching.
+// 0=ok, -2=invalid UTF-8, -3=the file can't be read
+int32_t playit_watch_config_file(const char *path);
+
+// Rotates the secret key of the config loaded by playit_init, e.g. after the user
+// re-links the device. Behavior for a running agent depends on reconnect:
+// - true: the agent is stopped and started again with the new key right away. Open
+//   connections are dropped; the status goes STOPPED -> CONNECTING (or IDLE for lazy) ->
+//   CONNECTED like a normal start, through the status callback.
+// - false: the running session and its connections are left alone on the old key, status
+//   doesn't change, and the new key is used from the next playit_start.
+// An agent that isn't running just picks the key up on its next start.
+// 0=in use (or nothing changed), 1=stored, used from the next start, -1=null, -2=invalid
+// UTF-8, -3=empty key, -5=no config loaded, -6=restart failed (the stop timed out, or the
+// start failed; the key is stored either way)
+int32_t playit_update_secret_key(const char *secret_key, bool reconnect);
+
+// Starts the agent on its own thread. The return value only covers what can fail right
+// away: 0=agent thread started, -1=no config loaded (call playit_init first), -2=already
+// running (or still finishing a stop, see playit_stop), -3=the agent thread could not be
+// spawned. 0 does NOT mean connected: creating the runtime, loading rundata, auth and
+// tunnel setup all happen afterwards on the agent thread. Follow them through the status
+// callback, or use the start result callback.
 int32_t playit_start(void);
+
+// playit_start with a choice of what a call while the agent is already running does, for
+// callers that start from several places (app launch, foregrounding, settings):
+#define PLAYIT_START_STRICT            0  // -2, same as playit_start
+#define PLAYIT_START_IDEMPOTENT        1  // 0 if it runs the loaded config, else -2
+#define PLAYIT_START_RESTART_ON_CHANGE 2  // 0 if it runs the loaded config, else restart it
+// "The loaded config" compares the playit_config_fingerprint of the config from the last
+// playit_init/playit_reconfigure (and playit_set_device_model) with the one the agent was
+// started with. The restart works like playit_restart(false). Same codes as playit_start,
+// plus -4=unknown mode and -6=restart failed (the stop timed out, or the start failed)
+int32_t playit_start_with(int32_t mode);
+
+// Fired once for each playit_start that returned 0, from the agent thread, with how that
+// start went: PLAYIT_STATUS_CONNECTED once the tunnel first comes up (ORIGIN_UNREACHABLE
+// or ORIGIN_RESOLVE_FAILED if it came up but the local server didn't answer, message
+// names the cause), or, if the agent thread ended before that, the code it ended with and
+// its last_error: ERROR (e.g. runtime creation or rundata load failed), AUTH_FAILED,
+// GAVE_UP, NO_TUNNELS, or STOPPED when playit_stop came first. SCHEDULED_OFF when started
+// outside the schedule. message is NULL when there is none and only valid during the
+// call. Transient states (CONNECTING, RATE_LIMITED, retries) don't fire it.
+typedef void (*playit_start_result_callback)(int32_t code, const char *message,
+                                             void *user_data);
+void playit_set_start_result_callback(playit_start_result_callback callback,
+                                      void *user_data);
+
+// Onboarding: fired once for each playit_start, from the agent thread, when provisioning
+// completes. With the address of the tunnel the status reports once the first rundata
+// load succeeds and an enabled tunnel has an address. If that first load finds none (the
+// key is valid but the account has no usable tunnel yet) it fires with address NULL
+// instead, then once more with the address when a later poll finds one. Unlike the status
+// callback it doesn't fire again on reconnects. address is only valid during the call.
+typedef void (*playit_ready_callback)(const char *address, void *user_data);
+void playit_set_ready_callback(playit_ready_callback callback, void *user_data);
+
+// Opt-in, fired on the agent thread after every rundata poll while running, failed or
+// not, with how long the request took. error_code is the PLAYIT_STATUS_* the failure maps
+// to (such as PLAYIT_STATUS_RATE_LIMITED), 0 when success is true. NULL clears it.
+typedef void (*playit_poll_callback)(bool success, uint64_t duration_ms, int32_t error_code,
+                                     void *user_data);
+void playit_set_poll_callback(playit_poll_callback callback, void *user_data);
+
+// Signals the agent to stop and waits up to stop_wait_ms for its thread to finish.
+// 0=stopped (or wasn't running), 1=wait expired and shutdown may still be in progress.
+// After 1, playit_is_running stays true and playit_start returns -2 until the thread has
+// exited; calling playit_stop again waits for it once more.
 int32_t playit_stop(void);
+
+// Whether the agent machinery is active: true from playit_start until its thread has
+// exited, including while IDLE, reconnecting, in an error status or still finishing after
+// a playit_stop that returned 1; false once the thread is gone.
+// Independent of the status code, e.g. to choose between a Start and a Stop button.
+bool playit_is_running(void);
+
+// Whether the agent is in use by anyone in the process: a config is loaded (playit_init
+// succeeded) or it is running. There is one agent per process, shared by every module
+// linking this library, so a module that finds it active should coordinate rather than
+// call playit_init (which replaces the config) or playit_start (-2 while running).
+// false again after playit_reset_all.
+bool playit_instance_active(void);
+
+// Milliseconds since the status last changed to CONNECTED, for "connected for 2h 13m"
+// displays; -1 while the status is anything else. Restarts from 0 on every reconnect,
+// including after a brief DISCONNECTED or ORIGIN_UNREACHABLE.
+int64_t playit_get_connected_duration_ms(void);
+
+// Watchdog primitive: a counter a task on the agent runtime bumps about once a second,
+// from playit_start until the agent stops (also while IDLE or retrying). Read it with a
+// single atomic load, no locks. If it hasn't advanced for several seconds while
+// playit_is_running is true, the runtime is stuck and the host can stop and start again.
+// Never decreases, not reset between starts.
+uint64_t playit_liveness(void);
+
+// TESTS / ADVANCED USE ONLY, not needed in a normal app lifecycle. Stops the agent and
+// clears every callback (log, log event, JSON log, log fd, status, error, raw rundata,
+// throughput, tunnel state, rate limit, start result, ready, poll, packet flow, random
+// source), tunnel origin overrides and priorities, the imported and exported origin maps,
+// the watched config file, log target filters and rate limit, the event log, the status
+// timeline, the config and the last init error, device model, agent id, account details
+// and status, and resets log level, max log length, poll interval, counters and the last
+// poll result, as if the library had just been loaded. playit_init is required again
+// afterwards. The tracing subscriber stays installed (see playit_logging_active).
+void playit_reset_all(void);
+
+// Lazy start: with "lazy": true, playit_start goes STOPPED -> IDLE without touching the
+// network. playit_activate moves IDLE -> CONNECTING and the agent continues as a normal
+// start would. Status getters and callbacks never activate, so IDLE can be shown without
+// connecting. playit_stop from IDLE goes straight back to STOPPED.
+// 0=connecting (or already active), -1=not running
+int32_t playit_activate(void);
+
+// Call from applicationDidBecomeActive (or similar) after the app may have been suspended.
+// Drops the tunnel session so it is re-established and polls rundata immediately instead
+// of trusting state from before the suspend. The poll loop also does this by itself when
+// it wakes more than 10s later than planned. 0=ok, -1=not running
+int32_t playit_notify_resumed(void);
+
+// Public IP the playit control server sees this device connecting from (after NAT/CGNAT
+// or VPN), from the tunnel session's keepalive responses so it follows network changes
+// and reconnects. No extra requests are made. Returns the IP length (truncated if >= len),
+// -1 if not running or the session isn't established yet.
+int32_t playit_get_observed_ip(char *buf, size_t len);
+
 playit_status playit_get_status(void);
 void playit_get_status_out(playit_status *out_status);
 
+// Only the status code (a playit_status_code value) with a single atomic load, no locks or
+// allocation. Suitable for per-frame polling; call playit_get_status when it changes.
+int32_t playit_get_status_code(void);
+
+// Forward compatible status ABI: one scalar accessor per field. playit_status (above) is
+// kept for convenience but may grow; these never change signature, so prefer them in glue
+// that has to keep working against newer builds of this library.
+int32_t playit_status_current_code(void);  // same as playit_get_status_code
+int32_t playit_status_error_code(void);  // the status code while it is an error status, else 0
+uint32_t playit_status_connections(void);  // open TCP connections + UDP flows, 0 if stopped
+uint32_t playit_status_reconnect_attempts(void);
+// Copy last_address / last_error into buf. Return the length like the other buffer
+// functions (truncated if >= len), -1 if the field is NULL.
+int32_t playit_status_last_address(char *buf, size_t len);
+int32_t playit_status_last_error(char *buf, size_t len);
+
+// Counter bumped whenever the status code, last_address, last_error or the tunnel list
+// (playit_get_tunnels_json) changes; never 0. Compare with the last seen value and only
+// fetch the full status when it differs. reconnect_attempts is not covered.
+uint64_t playit_get_status_revision(void);
+
+// Estimated milliseconds until the next rundata poll for "refreshing in 3s" style UI.
+// 0 while a poll is in progress, -1 if the agent isn't running, is IDLE or was started
+// with poll_rundata false (after the initial load).
+int64_t playit_get_next_poll_ms(void);
+
+// Whether the most recent rundata poll (or the initial load) succeeded, for a refresh
+// indicator: 1 yes, 0 it failed, -1 unknown (none completed since playit_start). A failed
+// poll alone doesn't change the status, so this can be 0 while CONNECTED.
+int32_t playit_last_poll_ok(void);
+
+// Status callbacks fire once per change of status code, from the thread that caused the
+// change (the agent's runtime thread or the caller of playit_start/playit_stop).
+typedef void (*playit_status_callback)(int32_t code, void *user_data);
+
+// Fired once each time the status enters ERROR, AUTH_FAILED or ORIGIN_UNREACHABLE, not
+// again while it stays there. Recovery shows up as a normal status change. message is
+// only valid during the call and may be NULL.
+typedef void (*playit_error_callback)(int32_t code, const char *message, void *user_data);
+
+void playit_set_status_callback(playit_status_callback callback, void *user_data);
+void playit_set_error_callback(playit_error_callback callback, void *user_data);
+
+// Escape hatch for rundata fields this API doesn't model yet. Off by default; once set,
+// fired from the runtime thread with the JSON "data" of every rundata response (initial
+// load and each poll). The schema is the playit server's and is NOT a stable contract
+// of this library. json is only valid during the call.
+typedef void (*playit_raw_rundata_callback)(const char *json, void *user_data);
+void playit_set_raw_rundata_callback(playit_raw_rundata_callback callback, void *user_data);
+
+// Throughput samples in bytes per second, averaged over each interval_ms (0 = 1000,
+// minimum 100). Fired from an agent thread while the agent is running. Each sample covers
+// the time since the previous one, so there is no sample without a baseline: the first
+// arrives one interval after the agent starts, or within two when set while running.
+// NULL callback stops sampling.
+typedef void (*playit_throughput_callback)(uint64_t bytes_in_per_sec,
+                                           uint64_t bytes_out_per_sec, void *user_data);
+void playit_set_throughput_callback(uint32_t interval_ms, playit_throughput_callback callback,
+                                    void *user_data);
+
+// Fired from the runtime thread each time an API request is answered with 429, right
+// after the status moves to RATE_LIMITED. retry_after_ms is the server's Retry-After, 0
+// if it sent none. The next request is held off at least that long.
+typedef void (*playit_rate_limit_callback)(uint64_t retry_after_ms, void *user_data);
+void playit_set_rate_limit_callback(playit_rate_limit_callback callback, void *user_data);
+
+// Source for the randomness this library draws itself, currently the jitter added to
+// setup retries and rate limit backoff. Must fill len bytes at buf and return 0; on any
+// other return the standard RNG is used for that draw. Randomness inside the tunnel core
+// isn't routed through it. May be called from any agent thread. NULL restores the
+// standard RNG.
+typedef int32_t (*playit_random_source)(uint8_t *buf, size_t len, void *user_data);
+void playit_set_random_source(playit_random_source callback, void *user_data);
+
+typedef struct {
+    uint32_t struct_version;  // see PLAYIT_ABI_VERSION
+    uint32_t struct_size;
+    uint64_t bytes_in;      // tunnel -> local origin
+    uint64_t bytes_out;     // local origin -> tunnel
+    uint32_t active_tcp;
+    uint32_t active_udp;
+    uint64_t rejected_tcp;  // TCP clients turned away at max_connections
+    uint64_t blocked;       // TCP clients and new UDP flows refused by allow_ips/deny_ips
+    uint64_t origin_refused;  // TCP clients dropped after the local server refused them
+} playit_stats;
+
+// Totals for the running agent. 0=ok, -1=null out_stats, -2=not running (zeroed)
+int32_t playit_get_stats(playit_stats *out_stats);
+
+// Same for a single tunnel id (see playit_get_tunnels_json), -3=unknown tunnel.
+// A known tunnel without traffic yet returns 0 with zeroed stats.
+int32_t playit_get_tunnel_stats(uint64_t tunnel_id, playit_stats *out_stats);
+
+// Every counter in one call, for dashboards refreshing several times a second.
+typedef struct {
+    uint32_t struct_version;  // see PLAYIT_ABI_VERSION
+    uint32_t struct_size;
+    int32_t status_code;          // same as playit_get_status_code
+    uint32_t reconnect_attempts;  // same as playit_status.reconnect_attempts
+    uint32_t active_tcp;          // this and the next 7 fields as in playit_stats
+    uint32_t active_udp;
+    uint64_t bytes_in;
+    uint64_t bytes_out;
+    uint64_t rejected_tcp;
+    uint64_t blocked;
+    uint64_t origin_refused;
+    uint64_t poll_errors;     // failed rundata polls since the library was loaded
+    uint64_t dropped_logs;    // same as playit_get_dropped_log_count
+    int64_t connected_ms;     // same as playit_get_connected_duration_ms, -1 if not connected
+    // Round trip of the tunnel session's latest ping, -1 until its pong arrives. Rough:
+    // pongs are noticed up to ~100ms late.
+    int64_t control_rtt_ms;
+    // Data path buffers (UDP packet pool and TCP pipe buffers) in use, and the
+    // max_buffer_memory_kb limit in bytes, 0 if unlimited
+    uint64_t buffer_bytes_in_use;
+    uint64_t buffer_bytes_limit;
+} playit_metrics;
+
+// 0=ok, -1=null out_metrics, -2=not running (counters of the running agent are zero,
+// status_code, poll_errors and dropped_logs are still filled in)
+int32_t playit_get_all_metrics(playit_metrics *out_metrics);
+
+// Closed TCP connections and UDP flows by how long they were in use, counted since
+// playit_start: {"under_10s": n, "under_1m": n, "under_10m": n, "over_10m": n}.
+// All zero when not running. Returns the JSON length (truncated if >= len).
+int32_t playit_get_duration_histogram_json(char *buf, size_t len);
+
+// Activity feed since the last playit_start, oldest first, for an app's history view or a
+// bug report. A JSON array of {"timestamp_ms": unix ms, "type": ..., "details": {...}}:
+// - "status"    {"code": PLAYIT_STATUS_*, "error": string or null} on each status change
+// - "error"     same details, for a change to an error status (ERROR, AUTH_FAILED, ...)
+// - "reconnect" {"reason": "resumed" | "suspended" | "idle"} when the library re-establishes
+//   the tunnel session itself
+// - "address"   {"previous": ..., "address": ...} when the tunnel address changes
+// Holds up to event_log_capacity events. Returns the JSON length (truncated if >= len).
+int32_t playit_get_event_log_json(char *buf, size_t len);
+
+// When the status last changed to each code, for "last connected at X, last error at Y"
+// and spotting flapping. A JSON array ordered by code with one {"code": PLAYIT_STATUS_*,
+// "last_entered_ms": unix ms} per code entered so far, kept across starts. Returns the
+// JSON length (truncated if >= len).
+int32_t playit_get_status_timeline_json(char *buf, size_t len);
+
+// OpenMetrics text snapshot of the counters above plus status, reconnect attempts and
+// rundata poll errors, ready to serve as-is from a local /metrics endpoint. Per tunnel
+// series carry a tunnel_id label. Returns the text length (truncated if >= len).
+int32_t playit_metrics_text(char *buf, size_t len);
+
+// Verbose JSON dump of the agent internals for a "copy diagnostics" button on support
+// tickets. Safe to call at any time; when stopped "running" is false and "control" is
+// null. The secret key is never included. Fields are for humans and may change:
+// {"library_version", "now_ms", "running", "status", "status_revision", "last_address",
+//  "last_error", "next_poll_ms", "poll_errors", "reconnect_attempts", "observed_addr",
+//  "config": {... "secret_key": "<redacted>"} or null,
+//  "control": {"control_addr", "tunnel_addr", "client_addr", "data_center_id",
+//   "session_expire_at", "expired", "last_authenticated", "last_keep_alive", "last_ping",
+//   "last_pong", "local_addrs": [...], "state": playit_control_state} or null,
+//  "tunnels": [same entries as playit_get_tunnels_json]}
+// Times are unix ms, 0 for never. Returns the JSON length (truncated if >= len).
+int32_t playit_diagnostics_json(char *buf, size_t len);
+
+// The control link to playit on its own, separate from the rundata derived status: e.g.
+// CONNECTED with status DISCONNECTED means the link is fine but no tunnel is enabled,
+// while DISCONNECTED here means the control server can't be reached.
+typedef enum {
+    PLAYIT_CONTROL_DISCONNECTED = 0,  // not running, idle, or re-establishing failed
+    PLAYIT_CONTROL_CONNECTING = 1,    // being set up, or session expired and re-establishing
+    PLAYIT_CONTROL_CONNECTED = 2,
+} playit_control_state;
+int32_t playit_get_control_state(void);
+
+// Fingerprint of the config loaded by playit_init / playit_reconfigure, to confirm two
+// devices have the same setup: SHA-256 hex (64 chars) over the effective config, with
+// defaults filled in, so field order and spelling out a default don't change it. The
+// secret key only contributes a salted hash, so devices with the same key match but the
+// fingerprint reveals nothing about it. Same return value as the other buffer functions,
+// -1 if no config is loaded.
+int32_t playit_config_fingerprint(char *buf, size_t len);
+
+typedef enum {
+    PLAYIT_TUNNEL_TCP = 0,
+    PLAYIT_TUNNEL_UDP = 1,
+    PLAYIT_TUNNEL_TCP_UDP = 2,
+    PLAYIT_TUNNEL_WEB = 3,  // HTTPS tunnel, hostname and url are set
+} playit_tunnel_type;
+
+typedef enum {
+    PLAYIT_DISABLED_NONE = 0,  // tunnel is enabled
+    PLAYIT_DISABLED_OVER_QUOTA = 1,
+    PLAYIT_DISABLED_ACCOUNT_SUSPENDED = 2,
+    PLAYIT_DISABLED_REGION_UNAVAILABLE = 3,
+    PLAYIT_DISABLED_MANUALLY_DISABLED = 4,
+    PLAYIT_DISABLED_UNKNOWN = 5,  // newer reason, see disabled_reason_raw
+    PLAYIT_DISABLED_REMOVED = 6,  // tunnel state callback only: gone from rundata
+} playit_disabled_reason;
+
+// JSON array of the tunnels from the latest rundata ("[]" before it first loads):
+// [{"id": 1, "name": "...", "address": "host:port",
+//   "local_address": "127.0.0.1:25565" or null, "tunnel_type": playit_tunnel_type,
+//   "tunnel_type_name": "minecraft-java" or null, "hostname": "x.example" or null,
+//   "url": "https://x.example" or null, "enabled": true,
+//   "disabled_reason": playit_disabled_reason, "disabled_reason_raw": "..." or null,
+//   "from_port": 30000 or null, "to_port": 30010 or null, "port_count": 11}]
+// from_port..to_port is the external port range (equal for a single port tunnel), taken
+// from the port the address ends in; null for addresses without one (web tunnels, domains
+// using SRV records). port_count is always set. id matches playit_get_tunnel_stats.
+// Returns the JSON length (truncated if >= len).
+int32_t playit_get_tunnels_json(char *buf, size_t len);
+
+// Send a tunnel's connections to origin_addr ("127.0.0.1:25566", "[::1]:8080") instead of
+// the local address configured on the website, e.g. to route tunnels to different local
+// services the app runs. NULL removes the override. Applies to new connections right away
+// when running, survives rundata polls and is kept for later playit_start calls. For
+// multi-port tunnels this is the first port, the rest keep their offsets. Also used by the
+// origin probe and shown as local_address in playit_get_tunnels_json after the next poll.
+// 0=ok, -2=invalid UTF-8, -3=not an ip:port address
+int32_t playit_set_tunnel_origin(uint64_t tunnel_id, const char *origin_addr);
+
+// Preference among enabled tunnels for the status address (last_address): the enabled
+// tunnel with the highest priority is reported, ties go to rundata order, so a preferred
+// tunnel falls back to the next one while it's disabled or gone. Tunnels default to 0;
+// setting 0 removes a priority, negative ones rank below the default. Applies from the
+// next rundata poll and is kept for later starts. required_tunnel_id still takes
+// precedence. Also used by playit_fetch_address.
+void playit_set_tunnel_priority(uint64_t tunnel_id, int32_t priority);
+
+// Tunnel to local origin mapping from the latest rundata load as a JSON array, for the
+// host to persist: [{"tunnel_id": 1, "proto": "tcp"|"udp"|"both", "port_count": 1,
+// "proxy_protocol": null, "target": {"type": "port", "ip": "127.0.0.1", "port": 25565}}]
+// ("type": "https" targets have http_port and https_port instead of port). Overrides from
+// playit_set_tunnel_origin are not included. Kept after playit_stop. Returns the JSON
+// length (truncated if >= len), -1 if rundata hasn't loaded yet.
+int32_t playit_export_origin_map(char *buf, size_t len);
+
+// Restores a mapping from playit_export_origin_map at each playit_start, before the first
+// rundata load, which then replaces it (overrides still apply on top). Kept until replaced;
+// NULL drops it. 0=ok, -2=invalid UTF-8, -3=not a valid origin map
+int32_t playit_import_origin_map(const char *json);
+
+// Tunnels whose local origin is a hostname have it resolved and cached as soon as rundata
+// is loaded, ahead of the first connection, and again every 60s. Call this to resolve
+// again right away, e.g. after a network change. A failed refresh keeps the last address.
+// Failures are logged and reported as PLAYIT_STATUS_ORIGIN_RESOLVE_FAILED on the next
+// poll. A connection that arrives before its hostname is cached waits at most 250ms for
+// it and is dropped if it's still resolving; the resolve keeps going for later ones.
+// Returns 0 if queued, -1 if the agent isn't running.
+int32_t playit_prewarm_origin(void);
+
+// Fired when a tunnel's enabled state flips between two rundata polls, e.g. for a
+// "tunnel was disabled: over quota" toast. disabled_reason is a playit_disabled_reason
+// (NONE when enabled). Tunnels in the first rundata after playit_start are the baseline
+// and not reported; tunnels added later are reported with their initial state and removed
+// ones as disabled with PLAYIT_DISABLED_REMOVED. Called from the runtime thread.
+typedef void (*playit_tunnel_state_callback)(uint64_t tunnel_id, bool enabled,
+                                             int32_t disabled_reason, void *user_data);
+void playit_set_tunnel_state_callback(playit_tunnel_state_callback callback, void *user_data);
+
+// One-shot rundata fetch that writes the primary tunnel address into buf.
+// Does not use or modify the state set up by playit_init/playit_start.
+// Returns the address length (truncated if >= len) or:
+// -1..-4 same as playit_init, -5=runtime failure, -6=secret key rejected,
+// -7=network failure, -8=API error, -9=no enabled tunnel
+int32_t playit_fetch_address(const char *config_json, char *buf, size_t len);
+
+// Checks api_url is reachable from the current network with an unauthenticated HEAD
+// request, to tell "network blocks playit" apart from "secret key is wrong" during
+// onboarding. Does not use or modify the state set up by playit_init/playit_start.
+// timeout_ms 0 uses 5000.
+// 0=reachable, -1..-4 same as playit_init, -5=runtime failure, -6=unreachable
+// (DNS/connect/TLS failure), -7=timed out
+int32_t playit_ping_api(const char *config_json, uint32_t timeout_ms);
+
+// End-to-end check for a "Test" button: runs what playit_start does up to CONNECTED with
+// config_json (loads rundata with the secret key, requires an enabled tunnel, sets up a
+// tunnel session) on a private runtime, then tears it down. Nothing is proxied and no
+// probe connection is sent through the tunnel. Does not use or modify the state set up
+// by playit_init/playit_start, so a running agent keeps running; with the same secret
+// key the tunnel server briefly sees a second session. Blocks the calling thread for up
+// to timeout_ms (0 uses 15000); don't call it from the main thread.
+// 0=connected, -1..-4 same as playit_init, -5=runtime failure, -7=timed out, otherwise
+// the PLAYIT_STATUS_* code a start would have ended in (AUTH_FAILED, ERROR,
+// DISCONNECTED when no tunnel is enabled, RATE_LIMITED).
+int32_t playit_test_connection(const char *config_json, uint32_t timeout_ms);
+
+// Packet flow (e.g. NEPacketTunnelFlow): tunneled UDP goes through the host instead of
+// sockets the agent binds itself. The control connection still uses native sockets.
+typedef struct {
+    uint8_t is_ipv6;  // 0: first 4 bytes of ip are IPv4, 1: all 16 bytes are IPv6
+    uint8_t ip[16];
+    uint16_t port;
+} playit_packet_addr;
+
+// Called from the agent's runtime thread for every outbound packet; must not block.
+// Return 0 if the packet was accepted, anything else is counted as a send error.
+typedef int32_t (*playit_packet_send_callback)(const uint8_t *data, size_t len, const playit_packet_addr *target, void *user_data);
+
+// Takes effect on the next playit_start; pass NULL to use native sockets again.
+void playit_set_packet_flow(playit_packet_send_callback send, void *user_data);
+
+// Deliver an inbound packet that arrived from source. Data is copied.
+// 0=queued, -1=null argument, -2=no agent is using the packet flow, -3=queue full (dropped)
+int32_t playit_packet_flow_input(const uint8_t *data, size_t len, const playit_packet_addr *source);
+
 #ifdef __cplusplus
 }
 #endif
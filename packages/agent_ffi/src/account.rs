@@ -0,0 +1,86 @@
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+use playit_api_client::api::AgentRunDataV1;
+use serde_json::{Value, json};
+
+use crate::{state, write_c_buffer};
+
+/// (secret key, account JSON) from the latest rundata load with `account_info` on. The API
+/// has no account endpoint for agents, so this is what rundata says about the account and
+/// costs no extra requests.
+static ACCOUNT: Mutex<Option<(String, Value)>> = Mutex::new(None);
+
+fn account_json(data: &AgentRunDataV1) -> Value {
+    let permissions = &data.permissions;
+    json!({
+        "plan": if permissions.has_premium { "premium" } else { "free" },
+        "account_status": permissions.account_status,
+        "self_managed": permissions.is_self_managed,
+        /* not exposed to agents, kept so apps can show them once they are */
+        "limits": null,
+        "usage": {
+            "tunnels": data.tunnels.len(),
+            "pending_tunnels": data.pending.len(),
+            "ports": data.tunnels.iter().map(|t| t.port_count as u64).sum::<u64>(),
+        },
+    })
+}
+
+pub(crate) fn remember(secret_key: &str, data: &AgentRunDataV1) {
+    *ACCOUNT.lock().expect("account lock poisoned") =
+        Some((secret_key.to_string(), account_json(data)));
+}
+
+pub(crate) fn clear() {
+    *ACCOUNT.lock().expect("account lock poisoned") = None;
+}
+
+/// Plan, account status and usage from the latest rundata load, kept after a stop. -1 if
+/// none loaded yet for the configured secret key, -3 if the config doesn't set
+/// `account_info`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_account_json(buf: *mut c_char, len: usize) -> i32 {
+    let secret_key = match &state().lock().expect("state lock poisoned").config {
+        Some(config) if config.account_info => config.secret_key.clone(),
+        Some(_) => return -3,
+        None => return -1,
+    };
+
+    let json = match &*ACCOUNT.lock().expect("account lock poisoned") {
+        Some((key, account)) if *key == secret_key => account.to_string(),
+        _ => return -1,
+    };
+    unsafe { write_c_buffer(&json, buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_api_client::api::AgentRunDataV1;
+
+    use super::account_json;
+
+    #[test]
+    fn account_from_rundata() {
+        let data: AgentRunDataV1 = serde_json::from_value(serde_json::json!({
+            "agent_id": "00000000-0000-0000-0000-000000000001",
+            "tunnels": [],
+            "pending": [],
+            "notices": [],
+            "permissions": {
+                "is_self_managed": false,
+                "has_premium": true,
+                "account_status": "email-not-verified"
+            }
+        }))
+        .unwrap();
+
+        let account = account_json(&data);
+        assert_eq!(account["plan"], "premium");
+        assert_eq!(account["account_status"], "email-not-verified");
+        assert_eq!(account["self_managed"], false);
+        assert!(account["limits"].is_null());
+        assert_eq!(account["usage"]["tunnels"], 0);
+        assert_eq!(account["usage"]["ports"], 0);
+    }
+}
@@ -0,0 +1,65 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+use playit_agent_core::network::acl::{ConnectionAcl, InvalidIpRange};
+use serde::Deserialize;
+
+use crate::state;
+
+#[derive(Deserialize)]
+struct AclJson {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+pub(crate) fn parse_acl(
+    allow: &[String],
+    deny: &[String],
+) -> Result<ConnectionAcl, InvalidIpRange> {
+    Ok(ConnectionAcl {
+        allow: allow.iter().map(|v| v.parse()).collect::<Result<_, _>>()?,
+        deny: deny.iter().map(|v| v.parse()).collect::<Result<_, _>>()?,
+    })
+}
+
+/// Replaces the allow/deny lists, live for new connections if the agent is running and
+/// kept in the config for the next `playit_start`. 0=ok, -1=null, -2=invalid UTF-8,
+/// -3=invalid JSON or range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_update_acl(acl_json: *const c_char) -> i32 {
+    if acl_json.is_null() {
+        return -1;
+    }
+
+    let Ok(json) = unsafe { CStr::from_ptr(acl_json) }.to_str() else {
+        return -2;
+    };
+    let Ok(update) = serde_json::from_str::<AclJson>(json) else {
+        return -3;
+    };
+    let acl = match parse_acl(&update.allow, &update.deny) {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::warn!(%error, "rejected acl update");
+            return -3;
+        }
+    };
+
+    tracing::info!(
+        allow = acl.allow.len(),
+        deny = acl.deny.len(),
+        "acl updated"
+    );
+
+    let mut lock = state().lock().expect("state lock poisoned");
+    if let Some(running) = &lock.acl {
+        running.set(acl);
+    }
+    if let Some(config) = lock.config.as_mut() {
+        config.allow_ips = update.allow;
+        config.deny_ips = update.deny;
+    }
+    0
+}
@@ -0,0 +1,349 @@
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::PlayitStatusCode;
+use crate::tunnels::TunnelStateChange;
+
+pub(crate) type StatusCallback = extern "C" fn(code: i32, user_data: *mut c_void);
+pub(crate) type ErrorCallback =
+    extern "C" fn(code: i32, message: *const c_char, user_data: *mut c_void);
+pub(crate) type RawRundataCallback = extern "C" fn(json: *const c_char, user_data: *mut c_void);
+pub(crate) type TunnelStateCallback =
+    extern "C" fn(tunnel_id: u64, enabled: bool, disabled_reason: i32, user_data: *mut c_void);
+pub(crate) type ThroughputCallback =
+    extern "C" fn(bytes_in_per_sec: u64, bytes_out_per_sec: u64, user_data: *mut c_void);
+pub(crate) type RateLimitCallback = extern "C" fn(retry_after_ms: u64, user_data: *mut c_void);
+pub(crate) type StartResultCallback =
+    extern "C" fn(code: i32, message: *const c_char, user_data: *mut c_void);
+pub(crate) type ReadyCallback = extern "C" fn(address: *const c_char, user_data: *mut c_void);
+pub(crate) type PollCallback =
+    extern "C" fn(success: bool, duration_ms: u64, error_code: i32, user_data: *mut c_void);
+
+/// A host registered callback and the user data pointer passed back to it.
+pub(crate) struct CallbackSlot<F: Copy> {
+    inner: Mutex<Option<(F, UserData)>>,
+}
+
+#[derive(Copy, Clone)]
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+impl<F: Copy> CallbackSlot<F> {
+    pub(crate) const fn new() -> Self {
+        CallbackSlot {
+            inner: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn set(&self, callback: Option<F>, user_data: *mut c_void) {
+        let mut lock = self.inner.lock().expect("callback lock poisoned");
+        *lock = callback.map(|cb| (cb, UserData(user_data)));
+    }
+
+    /// Copy out the callback so it can be invoked without holding the lock.
+    pub(crate) fn get(&self) -> Option<(F, *mut c_void)> {
+        let lock = self.inner.lock().expect("callback lock poisoned");
+        lock.map(|(cb, user_data)| (cb, user_data.0))
+    }
+}
+
+static STATUS_CALLBACK: CallbackSlot<StatusCallback> = CallbackSlot::new();
+static ERROR_CALLBACK: CallbackSlot<ErrorCallback> = CallbackSlot::new();
+static RAW_RUNDATA_CALLBACK: CallbackSlot<RawRundataCallback> = CallbackSlot::new();
+static TUNNEL_STATE_CALLBACK: CallbackSlot<TunnelStateCallback> = CallbackSlot::new();
+static RATE_LIMIT_CALLBACK: CallbackSlot<RateLimitCallback> = CallbackSlot::new();
+static START_RESULT_CALLBACK: CallbackSlot<StartResultCallback> = CallbackSlot::new();
+/// Set by a successful `playit_start` until its outcome has been reported
+static START_PENDING: AtomicBool = AtomicBool::new(false);
+static READY_CALLBACK: CallbackSlot<ReadyCallback> = CallbackSlot::new();
+static POLL_CALLBACK: CallbackSlot<PollCallback> = CallbackSlot::new();
+/// Where the latest start is in reporting provisioning, one of the READY_ states
+static READY_STATE: AtomicU8 = AtomicU8::new(READY_DONE);
+
+const READY_DONE: u8 = 0;
+const READY_PENDING: u8 = 1;
+/// Provisioned without a tunnel address, still waiting for one
+const READY_NO_TUNNELS: u8 = 2;
+pub(crate) static THROUGHPUT_CALLBACK: CallbackSlot<ThroughputCallback> = CallbackSlot::new();
+pub(crate) static THROUGHPUT_INTERVAL_MS: AtomicU64 = AtomicU64::new(DEFAULT_THROUGHPUT_INTERVAL_MS);
+
+const DEFAULT_THROUGHPUT_INTERVAL_MS: u64 = 1_000;
+const MIN_THROUGHPUT_INTERVAL_MS: u64 = 100;
+
+/// Called after the status code changed, never while a status lock is held.
+pub(crate) fn status_changed(code: PlayitStatusCode, error: Option<&CString>) {
+    if let Some((callback, user_data)) = STATUS_CALLBACK.get() {
+        callback(code as i32, user_data);
+    }
+
+    /* the tunnel is up even if the origin behind it isn't, or off until its schedule */
+    if matches!(
+        code,
+        PlayitStatusCode::Connected
+            | PlayitStatusCode::OriginUnreachable
+            | PlayitStatusCode::OriginResolveFailed
+            | PlayitStatusCode::ScheduledOff
+    ) {
+        start_finished(code, error);
+    }
+
+    if code.is_error()
+        && let Some((callback, user_data)) = ERROR_CALLBACK.get()
+    {
+        let message = error.map(|v| v.as_ptr()).unwrap_or(std::ptr::null());
+        callback(code as i32, message, user_data);
+    }
+}
+
+pub(crate) fn start_pending(pending: bool) {
+    START_PENDING.store(pending, Ordering::Release);
+}
+
+/// A new start, the ready callback fires again for its rundata
+pub(crate) fn ready_pending() {
+    READY_STATE.store(READY_PENDING, Ordering::Release);
+}
+
+/// Called with the tunnel address after each successful rundata load. The first load
+/// reports ready or provisioned without tunnels, later ones only the first address.
+pub(crate) fn provisioned(address: Option<&str>) {
+    let (from, to) = match address {
+        Some(_) if READY_STATE.load(Ordering::Acquire) == READY_NO_TUNNELS => {
+            (READY_NO_TUNNELS, READY_DONE)
+        }
+        Some(_) => (READY_PENDING, READY_DONE),
+        None => (READY_PENDING, READY_NO_TUNNELS),
+    };
+    if READY_STATE
+        .compare_exchange(from, to, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return;
+    }
+
+    if let Some((callback, user_data)) = READY_CALLBACK.get() {
+        let address = address.map(crate::cstring_sanitize);
+        let address = address.as_ref().map(|v| v.as_ptr()).unwrap_or(std::ptr::null());
+        callback(address, user_data);
+    }
+}
+
+/// Reports how the latest start went, only the first call after `start_pending` does
+pub(crate) fn start_finished(code: PlayitStatusCode, error: Option<&CString>) {
+    if !START_PENDING.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    if let Some((callback, user_data)) = START_RESULT_CALLBACK.get() {
+        let message = error.map(|v| v.as_ptr()).unwrap_or(std::ptr::null());
+        callback(code as i32, message, user_data);
+    }
+}
+
+pub(crate) fn tunnel_state_changed(change: &TunnelStateChange) {
+    if let Some((callback, user_data)) = TUNNEL_STATE_CALLBACK.get() {
+        callback(change.id, change.enabled, change.disabled_reason, user_data);
+    }
+}
+
+/// `retry_after` is the server's Retry-After, sent as 0 when it gave none
+pub(crate) fn rate_limited(retry_after: Option<Duration>) {
+    if let Some((callback, user_data)) = RATE_LIMIT_CALLBACK.get() {
+        let retry_after_ms = retry_after.map(|v| v.as_millis() as u64).unwrap_or(0);
+        callback(retry_after_ms, user_data);
+    }
+}
+
+/// After each rundata load, `error` is the status code the failure maps to
+pub(crate) fn poll_finished(duration: Duration, error: Option<PlayitStatusCode>) {
+    if let Some((callback, user_data)) = POLL_CALLBACK.get() {
+        let error_code = error.map(|code| code as i32).unwrap_or(0);
+        callback(error.is_none(), duration.as_millis() as u64, error_code, user_data);
+    }
+}
+
+pub(crate) fn raw_rundata_enabled() -> bool {
+    RAW_RUNDATA_CALLBACK.get().is_some()
+}
+
+pub(crate) fn rundata_received(raw: &serde_json::Value) {
+    let Some((callback, user_data)) = RAW_RUNDATA_CALLBACK.get() else {
+        return;
+    };
+
+    let Ok(json) = CString::new(raw.to_string()) else {
+        return;
+    };
+    callback(json.as_ptr(), user_data);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_status_callback(
+    callback: Option<StatusCallback>,
+    user_data: *mut c_void,
+) {
+    STATUS_CALLBACK.set(callback, user_data);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_error_callback(
+    callback: Option<ErrorCallback>,
+    user_data: *mut c_void,
+) {
+    ERROR_CALLBACK.set(callback, user_data);
+}
+
+/// Opt-in, off until a callback is set. Fired with the rundata JSON from every poll.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_raw_rundata_callback(
+    callback: Option<RawRundataCallback>,
+    user_data: *mut c_void,
+) {
+    RAW_RUNDATA_CALLBACK.set(callback, user_data);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_tunnel_state_callback(
+    callback: Option<TunnelStateCallback>,
+    user_data: *mut c_void,
+) {
+    TUNNEL_STATE_CALLBACK.set(callback, user_data);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_rate_limit_callback(
+    callback: Option<RateLimitCallback>,
+    user_data: *mut c_void,
+) {
+    RATE_LIMIT_CALLBACK.set(callback, user_data);
+}
+
+/// Fired once per started agent: Connected (or an origin status) when the tunnel first
+/// comes up, otherwise the code it ended with if it stopped before that
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_start_result_callback(
+    callback: Option<StartResultCallback>,
+    user_data: *mut c_void,
+) {
+    START_RESULT_CALLBACK.set(callback, user_data);
+}
+
+/// Fired once per start when provisioning completes: with the tunnel address, or NULL if
+/// the account has no tunnel with an address yet, followed by the address once one shows up
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_ready_callback(
+    callback: Option<ReadyCallback>,
+    user_data: *mut c_void,
+) {
+    READY_CALLBACK.set(callback, user_data);
+}
+
+/// Opt-in, fired after every rundata load with whether it succeeded and how long it took
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_poll_callback(callback: Option<PollCallback>, user_data: *mut c_void) {
+    POLL_CALLBACK.set(callback, user_data);
+}
+
+/// Fired every `interval_ms` (0 for 1000, at least 100) while the agent is running with
+/// the average rate over that interval. Pass a null callback to stop.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_throughput_callback(
+    interval_ms: u32,
+    callback: Option<ThroughputCallback>,
+    user_data: *mut c_void,
+) {
+    let interval_ms = match interval_ms as u64 {
+        0 => DEFAULT_THROUGHPUT_INTERVAL_MS,
+        v => v.max(MIN_THROUGHPUT_INTERVAL_MS),
+    };
+
+    THROUGHPUT_INTERVAL_MS.store(interval_ms, Ordering::Relaxed);
+    THROUGHPUT_CALLBACK.set(callback, user_data);
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+    use std::os::raw::{c_char, c_void};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::{
+        playit_set_poll_callback, playit_set_ready_callback, playit_set_start_result_callback,
+        poll_finished, provisioned, ready_pending, start_finished, start_pending, status_changed,
+    };
+    use crate::PlayitStatusCode;
+
+    #[test]
+    fn start_result_fires_once() {
+        static RECEIVED: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+        extern "C" fn record(code: i32, _: *const c_char, _: *mut c_void) {
+            RECEIVED.lock().unwrap().push(code);
+        }
+
+        playit_set_start_result_callback(Some(record), std::ptr::null_mut());
+        start_pending(true);
+        status_changed(PlayitStatusCode::Connecting, None);
+        status_changed(PlayitStatusCode::Connected, None);
+        start_finished(PlayitStatusCode::Stopped, None);
+
+        start_pending(true);
+        start_finished(PlayitStatusCode::AuthFailed, None);
+        playit_set_start_result_callback(None, std::ptr::null_mut());
+
+        assert_eq!(
+            *RECEIVED.lock().unwrap(),
+            [PlayitStatusCode::Connected as i32, PlayitStatusCode::AuthFailed as i32]
+        );
+    }
+
+    #[test]
+    fn ready_waits_for_a_tunnel() {
+        static RECEIVED: Mutex<Vec<Option<String>>> = Mutex::new(Vec::new());
+        extern "C" fn record(address: *const c_char, _: *mut c_void) {
+            let address = (!address.is_null())
+                .then(|| unsafe { CStr::from_ptr(address) }.to_str().unwrap().to_string());
+            RECEIVED.lock().unwrap().push(address);
+        }
+
+        playit_set_ready_callback(Some(record), std::ptr::null_mut());
+        provisioned(Some("before-start.playit.gg"));
+        ready_pending();
+        provisioned(None);
+        provisioned(None);
+        provisioned(Some("mc.playit.gg"));
+        provisioned(Some("mc.playit.gg"));
+
+        ready_pending();
+        provisioned(Some("other.playit.gg"));
+        provisioned(None);
+        playit_set_ready_callback(None, std::ptr::null_mut());
+
+        assert_eq!(
+            *RECEIVED.lock().unwrap(),
+            [None, Some("mc.playit.gg".to_string()), Some("other.playit.gg".to_string())]
+        );
+    }
+
+    #[test]
+    fn poll_reports_outcome() {
+        static RECEIVED: Mutex<Vec<(bool, u64, i32)>> = Mutex::new(Vec::new());
+        extern "C" fn record(success: bool, duration_ms: u64, error_code: i32, _: *mut c_void) {
+            RECEIVED.lock().unwrap().push((success, duration_ms, error_code));
+        }
+
+        poll_finished(Duration::from_millis(5), None);
+        playit_set_poll_callback(Some(record), std::ptr::null_mut());
+        poll_finished(Duration::from_millis(120), None);
+        poll_finished(Duration::from_millis(30), Some(PlayitStatusCode::RateLimited));
+        playit_set_poll_callback(None, std::ptr::null_mut());
+
+        assert_eq!(
+            *RECEIVED.lock().unwrap(),
+            [(true, 120, 0), (false, 30, PlayitStatusCode::RateLimited as i32)]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+use serde_json::{Map, Value};
+
+use crate::{FfiConfig, write_c_buffer};
+
+/// Why the most recent config parse failed, `None` after one succeeded
+static LAST_INIT_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Records `message` as the reason for `code` and returns `code`
+pub(crate) fn fail(code: i32, message: impl Into<String>) -> i32 {
+    *LAST_INIT_ERROR.lock().expect("init error lock poisoned") = Some(message.into());
+    code
+}
+
+pub(crate) fn last() -> Option<String> {
+    LAST_INIT_ERROR.lock().expect("init error lock poisoned").clone()
+}
+
+pub(crate) fn clear() {
+    *LAST_INIT_ERROR.lock().expect("init error lock poisoned") = None;
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Byte offset of serde's 1-based line and column
+fn position(json: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = json
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    line_start + column.saturating_sub(1)
+}
+
+pub(crate) fn syntax_error(json: &str, error: &serde_json::Error) -> String {
+    let at = position(json, error.line(), error.column());
+    if error.is_eof() {
+        return format!("config JSON ends early at position {}", at);
+    }
+
+    /* serde appends the line and column itself */
+    let text = error.to_string();
+    let reason = text.split(" at line ").next().unwrap_or(&text);
+    format!(
+        "syntax error at position {} (line {}, column {}): {}",
+        at,
+        error.line(),
+        error.column(),
+        reason
+    )
+}
+
+pub(crate) fn expected_object(value: &Value) -> String {
+    format!("expected a JSON object, got {}", kind(value))
+}
+
+/// Message for a config JSON value that doesn't deserialize, naming the field at fault.
+/// serde doesn't report the path, so each field is tried on its own.
+pub(crate) fn shape_error(value: &Value, error: &serde_json::Error) -> String {
+    let Value::Object(object) = value else {
+        return expected_object(value);
+    };
+    if !object.contains_key("secret_key") {
+        return "missing required field secret_key".to_string();
+    }
+
+    for (key, field) in object {
+        let mut single = Map::new();
+        single.insert("secret_key".to_string(), object["secret_key"].clone());
+        single.insert(key.clone(), field.clone());
+
+        if let Err(error) = serde_json::from_value::<FfiConfig>(Value::Object(single)) {
+            return format!("field {}: {}", key, error);
+        }
+    }
+    format!("invalid config: {}", error)
+}
+
+/// Human readable reason the last `playit_init` (or other call taking a config) returned
+/// an error, -1 if it succeeded.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_last_init_error(buf: *mut c_char, len: usize) -> i32 {
+    let message = LAST_INIT_ERROR
+        .lock()
+        .expect("init error lock poisoned")
+        .clone();
+    match message {
+        Some(message) => unsafe { write_c_buffer(&message, buf, len) },
+        None => -1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::{expected_object, position, shape_error, syntax_error};
+    use crate::FfiConfig;
+
+    /* same steps as parse_config_json, without the shared last error */
+    fn error_for(json: &str) -> String {
+        match serde_json::from_str::<Value>(json) {
+            Err(error) => syntax_error(json, &error),
+            Ok(value) if !value.is_object() => expected_object(&value),
+            Ok(value) => {
+                let error = serde_json::from_value::<FfiConfig>(value.clone())
+                    .err()
+                    .unwrap();
+                shape_error(&value, &error)
+            }
+        }
+    }
+
+    #[test]
+    fn explains_rejected_config() {
+        assert_eq!(
+            error_for(r#"["secret"]"#),
+            "expected a JSON object, got an array"
+        );
+        assert_eq!(
+            error_for(r#"{"agent_name": "iPad"}"#),
+            "missing required field secret_key"
+        );
+        assert!(
+            error_for(r#"{"secret_key": "k", "poll_interval_ms": "soon"}"#)
+                .starts_with("field poll_interval_ms: invalid type: string \"soon\"")
+        );
+        assert_eq!(
+            error_for("{\"secret_key\": \"k\",\n \"lazy\": tru}"),
+            "syntax error at position 32 (line 2, column 13): expected ident"
+        );
+    }
+
+    #[test]
+    fn position_from_line_and_column() {
+        assert_eq!(position("abc", 1, 2), 1);
+        assert_eq!(position("ab\ncd\nef", 3, 1), 6);
+    }
+}
@@ -0,0 +1,134 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::watch;
+
+use crate::reconfigure::playit_reconfigure;
+use crate::{config_error, until_stopped};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+struct WatchedFile {
+    path: PathBuf,
+    /// (modified time, length) when last read, a change in either means a new version
+    version: Option<(SystemTime, u64)>,
+}
+
+static WATCHED: Mutex<Option<WatchedFile>> = Mutex::new(None);
+
+fn file_version(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+pub(crate) fn clear() {
+    *WATCHED.lock().expect("config watch lock poisoned") = None;
+}
+
+/// The watched file's contents if it changed since it was last read
+fn changed_contents() -> Option<(PathBuf, std::io::Result<String>)> {
+    let mut lock = WATCHED.lock().expect("config watch lock poisoned");
+    let watched = lock.as_mut()?;
+
+    let version = file_version(&watched.path);
+    if version.is_none() || version == watched.version {
+        return None;
+    }
+    watched.version = version;
+    Some((watched.path.clone(), std::fs::read_to_string(&watched.path)))
+}
+
+fn apply(path: &Path, contents: std::io::Result<String>) {
+    let contents = match contents {
+        Ok(contents) => contents,
+        Err(error) => {
+            tracing::warn!(path = %path.display(), %error, "failed to read watched config");
+            return;
+        }
+    };
+    let Ok(json) = CString::new(contents) else {
+        tracing::warn!(path = %path.display(), "watched config contains a nul byte, ignored");
+        return;
+    };
+
+    match unsafe { playit_reconfigure(json.as_ptr()) } {
+        code if code < 0 => {
+            let reason = config_error::last().unwrap_or_default();
+            tracing::warn!(
+                path = %path.display(),
+                code,
+                "watched config is invalid, keeping the current one: {}",
+                reason
+            );
+        }
+        restart => tracing::info!(path = %path.display(), restart, "reloaded watched config"),
+    }
+}
+
+/// Reapplies the watched file whenever it changes, for as long as the agent runs
+pub(crate) async fn run(mut stop_rx: watch::Receiver<bool>) {
+    loop {
+        if let Some((path, contents)) = changed_contents() {
+            apply(&path, contents);
+        }
+        let wait = tokio::time::sleep(CHECK_INTERVAL);
+        if until_stopped(&mut stop_rx, wait).await.is_none() {
+            return;
+        }
+    }
+}
+
+/// Calls `playit_reconfigure` with the file's contents each time it changes while the
+/// agent is running, NULL stops watching. The contents at the time of this call count as
+/// already applied. 0 ok, -2 invalid UTF-8, -3 the file can't be read.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_watch_config_file(path: *const c_char) -> i32 {
+    if path.is_null() {
+        clear();
+        return 0;
+    }
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return -2;
+    };
+
+    let path = PathBuf::from(path);
+    let Some(version) = file_version(&path) else {
+        return -3;
+    };
+
+    tracing::info!(path = %path.display(), "watching config file");
+    *WATCHED.lock().expect("config watch lock poisoned") = Some(WatchedFile {
+        path,
+        version: Some(version),
+    });
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+
+    use super::{changed_contents, clear, playit_watch_config_file};
+
+    #[test]
+    fn change_is_picked_up_once() {
+        let path = std::env::temp_dir().join(format!("playit-watch-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"secret_key": "a"}"#).unwrap();
+
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { playit_watch_config_file(c_path.as_ptr()) }, 0);
+        assert!(changed_contents().is_none());
+
+        std::fs::write(&path, r#"{"secret_key": "abc"}"#).unwrap();
+        let (_, contents) = changed_contents().unwrap();
+        assert_eq!(contents.unwrap(), r#"{"secret_key": "abc"}"#);
+        assert!(changed_contents().is_none());
+
+        clear();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(unsafe { playit_watch_config_file(c_path.as_ptr()) }, -3);
+    }
+}
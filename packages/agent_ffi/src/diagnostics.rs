@@ -0,0 +1,181 @@
+use std::os::raw::c_char;
+use std::sync::atomic::Ordering;
+
+use playit_agent_core::agent_control::maintained_control::{ControlDiagnostics, ControlState};
+use serde_json::{Value, json};
+
+use crate::{
+    FfiConfig, NEXT_POLL_AT, POLL_ERRORS, PlayitStatusCode, STATUS_CODE, STATUS_REVISION, clock,
+    next_poll_ms, state, write_c_buffer,
+};
+
+/// Everything but the secret key, which is only reported as set or not
+pub(crate) fn config_json(config: &FfiConfig) -> Value {
+    json!({
+        "secret_key": if config.secret_key.is_empty() { "" } else { "<redacted>" },
+        "api_url": config.api_url(),
+        "agent_name": config.agent_name,
+        "agent_version": config.agent_version,
+        "bind_address": config.bind_address,
+        "log_level": config.log_level,
+        "control_source_port": config.control_source_port,
+        "poll_interval_ms": config.poll_interval_ms(),
+        "poll_rundata": config.poll_rundata(),
+        "setup_retries": config.setup_retries,
+        "setup_retry_ms": config.setup_retry_ms,
+        "startup_jitter_ms": config.startup_jitter().as_millis() as u64,
+        "lazy": config.lazy,
+        "stop_wait_ms": config.stop_wait().as_millis() as u64,
+        "require_traffic_for_connected": config.require_traffic_for_connected,
+        "require_tunnels": config.require_tunnels,
+        "account_info": config.account_info,
+        "idle_reconnect_ms": config.idle_reconnect_ms,
+        "disconnect_give_up_ms": config.disconnect_give_up_ms,
+        "disconnect_grace_ms": config.disconnect_grace_ms,
+        "worker_threads": config.worker_threads(),
+        "runtime_drivers": config.runtime_drivers,
+        "max_connections": config.max_connections,
+        "max_connections_queue_ms": config.max_connections_queue_ms,
+        "tcp_backlog": config.tcp_backlog(),
+        "max_buffer_memory_kb": config.max_buffer_memory_kb,
+        "required_tunnel_id": config.required_tunnel_id,
+        "allowed_tunnel_ids": config.allowed_tunnel_ids,
+        "schedule": config.schedule,
+        "schedule_utc_offset_minutes": config.schedule_utc_offset_minutes,
+        "allow_ips": config.allow_ips,
+        "deny_ips": config.deny_ips,
+        "insecure_skip_tls_verify": config.insecure_skip_tls_verify,
+        "api_timeout_ms": config.api_timeout().map_or(0, |timeout| timeout.as_millis() as u64),
+        "tcp_nodelay": config.tcp_nodelay(),
+        "origin_connect_retries": config.origin_connect_retries(),
+        "origin_connect_retry_ms": config.origin_connect_retry_ms(),
+        "event_log_capacity": config.event_log_capacity(),
+    })
+}
+
+fn addr_json<T: ToString>(addr: Option<T>) -> Value {
+    addr.map(|v| Value::String(v.to_string()))
+        .unwrap_or(Value::Null)
+}
+
+fn control_json(control: &ControlDiagnostics) -> Value {
+    json!({
+        "control_addr": addr_json(control.control_addr),
+        "tunnel_addr": addr_json(control.tunnel_addr),
+        "client_addr": addr_json(control.client_addr),
+        "data_center_id": control.data_center_id,
+        "session_expire_at": control.session_expire_at,
+        "expired": control.expired,
+        "last_authenticated": control.last_authenticated,
+        "last_keep_alive": control.last_keep_alive,
+        "last_ping": control.last_ping,
+        "last_pong": control.last_pong,
+        "local_addrs": control.local_addrs.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+        "state": control_state_code(control.state),
+    })
+}
+
+/* PLAYIT_CONTROL_* in the header */
+const CONTROL_DISCONNECTED: i32 = 0;
+const CONTROL_CONNECTING: i32 = 1;
+const CONTROL_CONNECTED: i32 = 2;
+
+fn control_state_code(state: ControlState) -> i32 {
+    match state {
+        ControlState::Disconnected => CONTROL_DISCONNECTED,
+        ControlState::Connecting => CONTROL_CONNECTING,
+        ControlState::Connected => CONTROL_CONNECTED,
+    }
+}
+
+/// State of the control link to playit on its own, regardless of tunnels. Connecting
+/// while the agent is being set up, disconnected when not running.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_get_control_state() -> i32 {
+    let lock = state().lock().expect("state lock poisoned");
+    if !lock.running || STATUS_CODE.load(Ordering::Acquire) == PlayitStatusCode::Idle as i32 {
+        return CONTROL_DISCONNECTED;
+    }
+
+    match &lock.diagnostics {
+        Some(diagnostics) => {
+            control_state_code(diagnostics.lock().expect("diagnostics lock poisoned").state)
+        }
+        None => CONTROL_CONNECTING,
+    }
+}
+
+fn collect() -> Value {
+    let lock = state().lock().expect("state lock poisoned");
+    let status = lock.status.lock().expect("status lock poisoned").clone();
+
+    let control = lock
+        .diagnostics
+        .as_ref()
+        .map(|v| control_json(&v.lock().expect("diagnostics lock poisoned")));
+    let observed_addr = lock
+        .observed_addr
+        .as_ref()
+        .and_then(|addr| *addr.lock().expect("observed addr lock poisoned"));
+    let reconnect_attempts = lock
+        .reconnect_attempts
+        .as_ref()
+        .map(|v| v.load(Ordering::SeqCst));
+
+    json!({
+        "library_version": env!("CARGO_PKG_VERSION"),
+        "now_ms": clock().now_ms(),
+        "running": lock.running,
+        "status": STATUS_CODE.load(Ordering::Acquire),
+        "status_revision": STATUS_REVISION.load(Ordering::Acquire),
+        "last_address": status.last_address.map(|v| v.to_string_lossy().into_owned()),
+        "last_error": status.last_error.map(|v| v.to_string_lossy().into_owned()),
+        "next_poll_ms": next_poll_ms(NEXT_POLL_AT.load(Ordering::Acquire), clock()),
+        "poll_errors": POLL_ERRORS.load(Ordering::Relaxed),
+        "reconnect_attempts": reconnect_attempts,
+        "observed_addr": addr_json(observed_addr),
+        "config": lock.config.as_ref().map(config_json),
+        "control": control,
+        "tunnels": status.tunnels,
+    })
+}
+
+/// Writes a JSON dump of the agent internals for support tickets. Callable at any time,
+/// `control` is null while no session exists. Same return value as the other buffer
+/// functions.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_diagnostics_json(buf: *mut c_char, len: usize) -> i32 {
+    let json = collect().to_string();
+    unsafe { write_c_buffer(&json, buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+
+    use super::config_json;
+    use crate::parse_config_json;
+
+    #[test]
+    fn config_is_redacted() {
+        let json = CString::new(r#"{"secret_key": "hunter2", "agent_name": "iPad"}"#).unwrap();
+        let config = unsafe { parse_config_json(json.as_ptr()) }.unwrap();
+
+        let dump = config_json(&config);
+        assert_eq!(dump["secret_key"], "<redacted>");
+        assert_eq!(dump["agent_name"], "iPad");
+        assert!(!dump.to_string().contains("hunter2"));
+    }
+
+    #[test]
+    fn config_lists_every_field() {
+        let json = CString::new(r#"{"secret_key": "hunter2"}"#).unwrap();
+        let config = unsafe { parse_config_json(json.as_ptr()) }.unwrap();
+
+        let dump = config_json(&config);
+        let fields = serde_json::to_value(&config).unwrap();
+        for field in fields.as_object().unwrap().keys() {
+            assert!(dump.get(field).is_some(), "config_json is missing {}", field);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+use serde_json::{Value, json};
+
+use crate::{clock, write_c_buffer};
+
+pub(crate) const DEFAULT_CAPACITY: usize = 100;
+
+struct EventLog {
+    capacity: usize,
+    events: VecDeque<Value>,
+}
+
+impl EventLog {
+    const fn new() -> Self {
+        EventLog {
+            capacity: DEFAULT_CAPACITY,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, event: Value) {
+        self.events.push_back(event);
+        self.trim();
+    }
+
+    /// Drops the oldest events past capacity
+    fn trim(&mut self) {
+        while self.capacity < self.events.len() {
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Status changes, errors, reconnects and address changes since the last start, oldest
+/// first
+static EVENTS: Mutex<EventLog> = Mutex::new(EventLog::new());
+
+/// Appends an event, `kind` is its "type" and `details` an object describing it
+pub(crate) fn record(kind: &str, details: Value) {
+    let event = json!({
+        "timestamp_ms": clock().now_ms(),
+        "type": kind,
+        "details": details,
+    });
+    EVENTS.lock().expect("event log lock poisoned").push(event);
+}
+
+pub(crate) fn set_capacity(capacity: usize) {
+    let mut log = EVENTS.lock().expect("event log lock poisoned");
+    log.capacity = capacity;
+    log.trim();
+}
+
+/// Empties the log for a new start, keeping the capacity
+pub(crate) fn start() {
+    EVENTS
+        .lock()
+        .expect("event log lock poisoned")
+        .events
+        .clear();
+}
+
+pub(crate) fn clear() {
+    *EVENTS.lock().expect("event log lock poisoned") = EventLog::new();
+}
+
+/// JSON array of the events since the last `playit_start`, oldest first
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_event_log_json(buf: *mut c_char, len: usize) -> i32 {
+    let json = {
+        let log = EVENTS.lock().expect("event log lock poisoned");
+        Value::from_iter(log.events.iter().cloned()).to_string()
+    };
+    unsafe { write_c_buffer(&json, buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::EventLog;
+
+    #[test]
+    fn keeps_newest_events() {
+        let mut log = EventLog::new();
+        log.capacity = 2;
+        for id in 0..3 {
+            log.push(json!({ "id": id }));
+        }
+        assert_eq!(
+            Vec::from(log.events.clone()),
+            [json!({"id": 1}), json!({"id": 2})]
+        );
+
+        log.capacity = 1;
+        log.trim();
+        assert_eq!(Vec::from(log.events.clone()), [json!({"id": 2})]);
+
+        log.capacity = 0;
+        log.push(json!({ "id": 3 }));
+        assert!(log.events.is_empty());
+    }
+}
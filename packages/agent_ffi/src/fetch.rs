@@ -0,0 +1,68 @@
+use std::os::raw::c_char;
+
+use playit_api_client::api::{ApiErrorNoFail, ApiResponseError};
+use playit_api_client::http_client::HttpClientError;
+
+use crate::{ensure_logging, parse_config_json, primary_address, scope_tunnels, write_c_buffer};
+
+/* -1 to -4 are config errors shared with playit_init */
+const FETCH_ERR_RUNTIME: i32 = -5;
+const FETCH_ERR_AUTH: i32 = -6;
+const FETCH_ERR_NETWORK: i32 = -7;
+const FETCH_ERR_API: i32 = -8;
+const FETCH_ERR_NO_ADDRESS: i32 = -9;
+
+/// Loads rundata once and writes the primary tunnel address into `buf`.
+///
+/// Runs on its own short-lived runtime and never touches the global agent state, so it
+/// is safe to call while an agent started through `playit_start` is running.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_fetch_address(
+    config_json: *const c_char,
+    buf: *mut c_char,
+    len: usize,
+) -> i32 {
+    ensure_logging();
+    let config = match unsafe { parse_config_json(config_json) } {
+        Ok(v) => v,
+        Err(code) => return code,
+    };
+
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(error) => {
+            tracing::error!(?error, "failed to create runtime for address fetch");
+            return FETCH_ERR_RUNTIME;
+        }
+    };
+
+    let api = config.create_api();
+    let result = runtime.block_on(api.v1_agents_rundata());
+
+    let data = match result {
+        Ok(data) => data,
+        Err(error) => {
+            tracing::error!(?error, "failed to fetch run data");
+            return api_error_code(&error);
+        }
+    };
+
+    /* only tunnels a scoped agent would serve */
+    let data = scope_tunnels(data, &config.allowed_tunnel_ids);
+    match primary_address(&data, config.required_tunnel_id) {
+        Some(address) => unsafe { write_c_buffer(&address, buf, len) },
+        None => FETCH_ERR_NO_ADDRESS,
+    }
+}
+
+fn api_error_code(error: &ApiErrorNoFail<HttpClientError>) -> i32 {
+    match error {
+        ApiErrorNoFail::ApiError(ApiResponseError::Auth(_)) => FETCH_ERR_AUTH,
+        ApiErrorNoFail::ApiError(_) => FETCH_ERR_API,
+        ApiErrorNoFail::ClientError(HttpClientError::RequestError(_)) => FETCH_ERR_NETWORK,
+        ApiErrorNoFail::ClientError(_) => FETCH_ERR_API,
+    }
+}
@@ -0,0 +1,84 @@
+use std::os::raw::c_char;
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::diagnostics::config_json;
+use crate::{FfiConfig, state, write_c_buffer};
+
+/// Fixed so the same key hashes the same on every device, while the fingerprint
+/// can't be checked against a plain SHA-256 of a guessed key
+const SECRET_KEY_SALT: &str = "playit-config-fingerprint-v1:";
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// SHA-256 over the effective config as JSON (keys sorted), with the secret key
+/// replaced by its salted hash. `config_json` lists every field, its test checks that.
+pub(crate) fn fingerprint(config: &FfiConfig) -> String {
+    let mut json = config_json(config);
+    let secret = format!("{}{}", SECRET_KEY_SALT, config.secret_key);
+    json["secret_key"] = Value::String(sha256_hex(secret.as_bytes()));
+    sha256_hex(json.to_string().as_bytes())
+}
+
+/// Writes the fingerprint of the loaded config as 64 hex chars, -1 if no config is
+/// loaded. Same on every device with the same config, without revealing the key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_config_fingerprint(buf: *mut c_char, len: usize) -> i32 {
+    let value = {
+        let lock = state().lock().expect("state lock poisoned");
+        match &lock.config {
+            Some(config) => fingerprint(config),
+            None => return -1,
+        }
+    };
+    unsafe { write_c_buffer(&value, buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+
+    use super::fingerprint;
+    use crate::parse_config_json;
+
+    fn config(json: &str) -> crate::FfiConfig {
+        let json = CString::new(json).unwrap();
+        unsafe { parse_config_json(json.as_ptr()) }.unwrap()
+    }
+
+    #[test]
+    fn stable_and_secret_free() {
+        let a = fingerprint(&config(
+            r#"{"secret_key": "hunter2", "agent_name": "iPad"}"#,
+        ));
+        let b = fingerprint(&config(
+            r#"{"agent_name": "iPad", "secret_key": "hunter2"}"#,
+        ));
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 64);
+
+        /* defaults are filled in, so spelling them out doesn't change it */
+        let c = fingerprint(&config(
+            r#"{"secret_key": "hunter2", "agent_name": "iPad", "poll_interval_ms": 3000}"#,
+        ));
+        assert_eq!(a, c);
+
+        let other_key = fingerprint(&config(
+            r#"{"secret_key": "hunter3", "agent_name": "iPad"}"#,
+        ));
+        assert_ne!(a, other_key);
+
+        /* settings that only matter locally still make a different config */
+        let log_level = fingerprint(&config(
+            r#"{"secret_key": "hunter2", "agent_name": "iPad", "log_level": "warn"}"#,
+        ));
+        assert_ne!(a, log_level);
+        let stop_wait = fingerprint(&config(
+            r#"{"secret_key": "hunter2", "agent_name": "iPad", "stop_wait_ms": 500}"#,
+        ));
+        assert_ne!(a, stop_wait);
+    }
+}
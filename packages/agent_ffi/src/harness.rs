@@ -0,0 +1,142 @@
+//! Drives the agent's TCP data path against a loopback echo origin and a fake tunnel
+//! server, using the settings this crate builds from its config. No playit backend needed.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use playit_agent_core::network::origin_lookup::{OriginLookup, OriginResource, OriginTarget};
+use playit_agent_core::network::tcp::tcp_clients::TcpClients;
+use playit_agent_core::stats::AgentStats;
+use playit_agent_proto::PortProto;
+use playit_agent_proto::control_feed::{ClaimInstructions, NewClient};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::{FfiConfig, agent_settings};
+
+const TUNNEL_ID: u64 = 7;
+const CLAIM_TOKEN: &[u8] = b"loopback-claim-token";
+
+fn test_config() -> FfiConfig {
+    serde_json::from_str(r#"{"secret_key": "harness"}"#).unwrap()
+}
+
+async fn start_echo_origin() -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut rx, mut tx) = stream.split();
+                let _ = tokio::io::copy(&mut rx, &mut tx).await;
+            });
+        }
+    });
+
+    addr
+}
+
+async fn echo_lookup(origin: SocketAddr) -> Arc<OriginLookup> {
+    let lookup = Arc::new(OriginLookup::default());
+    lookup
+        .update(std::iter::once(OriginResource {
+            tunnel_id: TUNNEL_ID,
+            proto: PortProto::Tcp,
+            target: OriginTarget::Port {
+                ip: origin.ip(),
+                port: origin.port(),
+            },
+            port_count: 1,
+            proxy_protocol: None,
+        }))
+        .await;
+    lookup
+}
+
+async fn wait_for<F: Fn() -> bool>(check: F) -> bool {
+    for _ in 0..100 {
+        if check() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn tcp_round_trip_through_agent() {
+    let settings = agent_settings(&test_config());
+    let origin = start_echo_origin().await;
+    let lookup = echo_lookup(origin).await;
+    let stats = AgentStats::new();
+    let clients = TcpClients::new(settings.tcp_settings, lookup, stats.clone());
+
+    let tunnel_server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let claim_addr = tunnel_server.local_addr().unwrap();
+
+    clients
+        .handle_new_client(NewClient {
+            connect_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(147, 185, 221, 1)), 25565),
+            peer_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9)), 50000),
+            data_center_id: 1,
+            tunnel_id: TUNNEL_ID,
+            port_offset: 0,
+            claim_instructions: ClaimInstructions {
+                address: claim_addr,
+                token: CLAIM_TOKEN.to_vec(),
+            },
+        })
+        .await;
+
+    let (mut tunnel, _) = tokio::time::timeout(Duration::from_secs(5), tunnel_server.accept())
+        .await
+        .expect("agent did not connect to claim address")
+        .unwrap();
+
+    let mut token = vec![0u8; CLAIM_TOKEN.len()];
+    tunnel.read_exact(&mut token).await.unwrap();
+    assert_eq!(token, CLAIM_TOKEN);
+    tunnel.write_all(&[0u8; 8]).await.unwrap();
+
+    /* tunnel server now acts as the remote player */
+    let payload = b"hello through the tunnel";
+    tunnel.write_all(payload).await.unwrap();
+
+    let mut echoed = vec![0u8; payload.len()];
+    tokio::time::timeout(Duration::from_secs(5), tunnel.read_exact(&mut echoed))
+        .await
+        .expect("no echo from origin")
+        .unwrap();
+    assert_eq!(&echoed, payload);
+
+    let len = payload.len() as u64;
+    assert!(
+        wait_for(|| {
+            let snapshot = stats.snapshot();
+            snapshot.bytes_in == len && snapshot.bytes_out == len && snapshot.active_tcp == 1
+        })
+        .await,
+        "unexpected stats: {:?}",
+        stats.snapshot()
+    );
+
+    let tunnel_stats = stats.tunnel_snapshot(TUNNEL_ID).expect("no per tunnel stats");
+    assert_eq!(tunnel_stats.bytes_in, len);
+    assert_eq!(tunnel_stats.bytes_out, len);
+    assert_eq!(tunnel_stats.active_tcp, 1);
+}
+
+#[test]
+fn settings_follow_config() {
+    let mut config = test_config();
+    config.bind_address = Some("127.0.0.1".to_string());
+    let settings = agent_settings(&config);
+
+    let bind = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    assert_eq!(settings.tcp_settings.bind_address, bind);
+    assert_eq!(settings.udp_settings.bind_address, bind);
+    assert_eq!(settings.secret_key, "harness");
+    assert_eq!(settings.api_url, crate::DEFAULT_API_URL);
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use playit_agent_core::stats::{AgentStats, StatsSnapshot};
+use tokio::sync::watch;
+
+use crate::{clock, force_reconnect, until_stopped};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks when traffic last moved. Open connections count as activity even while idle
+/// so a quiet but connected player never triggers a reconnect.
+struct IdleTracker {
+    last_activity: u64,
+    last_bytes: u64,
+}
+
+impl IdleTracker {
+    fn new(now_ms: u64, snapshot: &StatsSnapshot) -> Self {
+        IdleTracker {
+            last_activity: now_ms,
+            last_bytes: snapshot.bytes_in + snapshot.bytes_out,
+        }
+    }
+
+    /// True once nothing happened for `idle_ms`, the idle period then starts over
+    fn observe(&mut self, now_ms: u64, snapshot: &StatsSnapshot, idle_ms: u64) -> bool {
+        let bytes = snapshot.bytes_in + snapshot.bytes_out;
+        if bytes != self.last_bytes || 0 < snapshot.active_tcp + snapshot.active_udp {
+            self.last_activity = now_ms;
+            self.last_bytes = bytes;
+            return false;
+        }
+
+        if now_ms.saturating_sub(self.last_activity) < idle_ms {
+            return false;
+        }
+
+        self.last_activity = now_ms;
+        true
+    }
+}
+
+/// Re-establishes the tunnel session after `idle_ms` without traffic, for carriers that
+/// silently drop idle NAT mappings even with keep-alives going.
+pub(crate) async fn run(stats: AgentStats, idle_ms: u64, mut stop_rx: watch::Receiver<bool>) {
+    let mut tracker = IdleTracker::new(clock().now_ms(), &stats.snapshot());
+
+    loop {
+        let wait = tokio::time::sleep(CHECK_INTERVAL);
+        if until_stopped(&mut stop_rx, wait).await.is_none() {
+            break;
+        }
+
+        if tracker.observe(clock().now_ms(), &stats.snapshot(), idle_ms) {
+            tracing::info!(idle_ms, "no tunnel traffic, refreshing the tunnel session");
+            force_reconnect("idle");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_agent_core::stats::StatsSnapshot;
+
+    use super::IdleTracker;
+
+    #[test]
+    fn triggers_after_idle_period() {
+        let mut idle = StatsSnapshot::default();
+        let mut tracker = IdleTracker::new(0, &idle);
+
+        assert!(!tracker.observe(5_000, &idle, 10_000));
+        assert!(tracker.observe(10_000, &idle, 10_000));
+        /* starts over after triggering */
+        assert!(!tracker.observe(15_000, &idle, 10_000));
+        assert!(tracker.observe(20_000, &idle, 10_000));
+
+        /* bytes moving resets the timer */
+        idle.bytes_in = 100;
+        assert!(!tracker.observe(29_000, &idle, 10_000));
+        assert!(!tracker.observe(38_000, &idle, 10_000));
+        assert!(tracker.observe(39_000, &idle, 10_000));
+
+        /* as does an open connection, even without traffic */
+        idle.active_tcp = 1;
+        assert!(!tracker.observe(60_000, &idle, 10_000));
+        idle.active_tcp = 0;
+        assert!(!tracker.observe(65_000, &idle, 10_000));
+        assert!(tracker.observe(70_000, &idle, 10_000));
+    }
+}
@@ -0,0 +1,53 @@
+use serde_json::{Map, Value, json};
+use tracing::Level;
+
+/// One log event as a single line JSON object, without the trailing newline
+pub(crate) fn line(
+    timestamp_ms: u64,
+    level: Level,
+    target: &str,
+    message: &str,
+    fields: &Map<String, Value>,
+) -> String {
+    let time = chrono::DateTime::from_timestamp_millis(timestamp_ms as i64).unwrap_or_default();
+    json!({
+        "timestamp": time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        "level": level.as_str().to_ascii_lowercase(),
+        "target": target,
+        "message": message,
+        "fields": fields,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Map, Value};
+    use tracing::Level;
+
+    use super::line;
+
+    #[test]
+    fn event_as_json_line() {
+        let mut fields = Map::new();
+        fields.insert("tunnel_id".to_string(), Value::from(7u64));
+        fields.insert("hostname".to_string(), Value::from("mc.local"));
+
+        let text = line(
+            1_700_000_000_123,
+            Level::WARN,
+            "playit_agent",
+            "a \"quoted\"\nline",
+            &fields,
+        );
+        assert!(!text.contains('\n'));
+
+        let value: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["timestamp"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(value["level"], "warn");
+        assert_eq!(value["target"], "playit_agent");
+        assert_eq!(value["message"], "a \"quoted\"\nline");
+        assert_eq!(value["fields"]["tunnel_id"], 7);
+        assert_eq!(value["fields"]["hostname"], "mc.local");
+    }
+}
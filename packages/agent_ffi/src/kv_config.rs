@@ -0,0 +1,212 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+use serde_json::{Map, Value};
+
+use crate::{FfiConfig, build_config, config_error, ensure_logging, install_config};
+
+/// Same as `playit_init` but takes the config as parallel arrays of keys and values
+/// that use the same names as the JSON config. Values are strings and are converted to
+/// the type the key expects; an unparsable value fails like invalid JSON would.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_init_kv(
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    count: usize,
+) -> i32 {
+    ensure_logging();
+    let config = match unsafe { parse_config_kv(keys, values, count) } {
+        Ok(v) => v,
+        Err(code) => return code,
+    };
+
+    install_config(config)
+}
+
+unsafe fn parse_config_kv(
+    keys: *const *const c_char,
+    values: *const *const c_char,
+    count: usize,
+) -> Result<FfiConfig, i32> {
+    if count > 0 && (keys.is_null() || values.is_null()) {
+        return Err(config_error::fail(-1, "keys or values is NULL"));
+    }
+
+    let mut object = Map::new();
+    for i in 0..count {
+        let (key, value) = unsafe { (*keys.add(i), *values.add(i)) };
+        if key.is_null() || value.is_null() {
+            return Err(config_error::fail(-1, format!("key or value {} is NULL", i)));
+        }
+
+        let not_utf8 = |_| config_error::fail(-2, format!("key or value {} is not UTF-8", i));
+        let key = unsafe { CStr::from_ptr(key) }.to_str().map_err(not_utf8)?;
+        let value = unsafe { CStr::from_ptr(value) }.to_str().map_err(not_utf8)?;
+        object.insert(key.to_string(), kv_value(key, value));
+    }
+
+    build_config(Value::Object(object))
+}
+
+/// Converts a string value to the JSON type expected for `key`. Values that don't
+/// parse are kept as strings so deserializing the config reports the error.
+fn kv_value(key: &str, value: &str) -> Value {
+    match key {
+        "poll_interval_ms" | "worker_threads" | "max_connections" | "max_connections_queue_ms"
+        | "stop_wait_ms" | "required_tunnel_id" | "setup_retries" | "setup_retry_ms"
+        | "startup_jitter_ms" | "max_buffer_memory_kb"
+        | "idle_reconnect_ms" | "disconnect_give_up_ms" | "disconnect_grace_ms"
+        | "origin_connect_retries" | "control_source_port" | "tcp_backlog"
+        | "origin_connect_retry_ms" | "api_timeout_ms" | "event_log_capacity" => {
+            value
+                .trim()
+                .parse::<u64>()
+                .map(Value::from)
+                .unwrap_or_else(|_| Value::from(value))
+        }
+        "insecure_skip_tls_verify"
+        | "lazy"
+        | "poll_rundata"
+        | "require_traffic_for_connected"
+        | "require_tunnels"
+        | "account_info"
+        | "tcp_nodelay" => match value.trim() {
+            "true" | "1" => Value::Bool(true),
+            "false" | "0" => Value::Bool(false),
+            _ => Value::from(value),
+        },
+        "allow_ips" | "deny_ips" | "runtime_drivers" => value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(Value::from)
+            .collect(),
+        "allowed_tunnel_ids" => value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<u64>().map(Value::from).unwrap_or_else(|_| Value::from(v)))
+            .collect(),
+        "schedule_utc_offset_minutes" => value
+            .trim()
+            .parse::<i64>()
+            .map(Value::from)
+            .unwrap_or_else(|_| Value::from(value)),
+        /* "HH:MM-HH:MM" windows for every day, days need the JSON config */
+        "schedule" => value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| match v.split_once('-') {
+                Some((start, end)) => serde_json::json!({ "start": start, "end": end }),
+                None => Value::from(v),
+            })
+            .collect(),
+        _ => Value::from(value),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+    use std::os::raw::c_char;
+
+    use super::parse_config_kv;
+
+    fn parse(pairs: &[(&str, &str)]) -> Result<crate::FfiConfig, i32> {
+        let keys: Vec<CString> = pairs.iter().map(|(k, _)| CString::new(*k).unwrap()).collect();
+        let values: Vec<CString> = pairs.iter().map(|(_, v)| CString::new(*v).unwrap()).collect();
+        let key_ptrs: Vec<*const c_char> = keys.iter().map(|v| v.as_ptr()).collect();
+        let value_ptrs: Vec<*const c_char> = values.iter().map(|v| v.as_ptr()).collect();
+
+        unsafe { parse_config_kv(key_ptrs.as_ptr(), value_ptrs.as_ptr(), pairs.len()) }
+    }
+
+    #[test]
+    fn kv_degenerate_inputs() {
+        let key = CString::new("secret_key").unwrap();
+        let value = CString::new("abc").unwrap();
+        let keys = [key.as_ptr()];
+        let values = [value.as_ptr()];
+        let null_values = [std::ptr::null::<c_char>()];
+
+        /* null arrays with entries to read */
+        let result = unsafe { parse_config_kv(std::ptr::null(), values.as_ptr(), 1) };
+        assert_eq!(result.err(), Some(-1));
+        let result = unsafe { parse_config_kv(keys.as_ptr(), std::ptr::null(), 1) };
+        assert_eq!(result.err(), Some(-1));
+
+        /* key without a matching value */
+        let result = unsafe { parse_config_kv(keys.as_ptr(), null_values.as_ptr(), 1) };
+        assert_eq!(result.err(), Some(-1));
+
+        /* nothing given is a config without a secret_key, same as "{}" */
+        let result = unsafe { parse_config_kv(std::ptr::null(), std::ptr::null(), 0) };
+        assert_eq!(result.err(), Some(-3));
+        let result = unsafe { parse_config_kv(keys.as_ptr(), values.as_ptr(), 0) };
+        assert_eq!(result.err(), Some(-3));
+
+        let invalid_utf8 = [0xffu8, 0];
+        let bad_values = [invalid_utf8.as_ptr() as *const c_char];
+        let result = unsafe { parse_config_kv(keys.as_ptr(), bad_values.as_ptr(), 1) };
+        assert_eq!(result.err(), Some(-2));
+
+        assert_eq!(parse(&[("secret_key", "")]).err(), Some(-3));
+        assert_eq!(
+            parse(&[("secret_key", "abc"), ("poll_interval_ms", "soon")]).err(),
+            Some(-3)
+        );
+        assert_eq!(
+            parse(&[("secret_key", "abc"), ("insecure_skip_tls_verify", "maybe")]).err(),
+            Some(-3)
+        );
+        assert_eq!(
+            parse(&[("secret_key", "abc"), ("bind_address", "wifi")]).err(),
+            Some(-4)
+        );
+        assert_eq!(
+            parse(&[("secret_key", "abc"), ("deny_ips", "10.0.0.0/8,bogus")]).err(),
+            Some(-3)
+        );
+    }
+
+    #[test]
+    fn kv_valid_config() {
+        let config = parse(&[
+            ("secret_key", "abc"),
+            ("poll_interval_ms", "5000"),
+            ("insecure_skip_tls_verify", "true"),
+            ("agent_name", "1234"),
+            ("deny_ips", "203.0.113.0/24, 198.51.100.7"),
+            ("poll_rundata", "0"),
+            ("tcp_nodelay", "false"),
+            ("origin_connect_retries", "3"),
+            ("api_timeout_ms", "0"),
+            ("require_tunnels", "1"),
+            ("control_source_port", "4500"),
+            ("tcp_backlog", "128"),
+            ("schedule", "18:00-23:30, 22:00-02:00"),
+            ("schedule_utc_offset_minutes", "-300"),
+            ("allowed_tunnel_ids", "12, 40"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.secret_key, "abc");
+        assert_eq!(config.poll_interval_ms, Some(5000));
+        assert!(config.insecure_skip_tls_verify);
+        assert_eq!(config.agent_name.as_deref(), Some("1234"));
+        assert_eq!(config.deny_ips, ["203.0.113.0/24", "198.51.100.7"]);
+        assert!(!config.acl().permits("203.0.113.50".parse().unwrap()));
+        assert!(!config.poll_rundata());
+        assert!(!config.tcp_nodelay());
+        assert_eq!(config.origin_connect_retries(), 3);
+        assert_eq!(config.origin_connect_retry_ms(), 500);
+        assert_eq!(config.api_timeout(), None);
+        assert!(config.require_tunnels);
+        assert_eq!(config.control_source_port, Some(4500));
+        assert_eq!(config.tcp_backlog(), 128);
+        assert_eq!(config.schedule.len(), 2);
+        assert_eq!(config.schedule_utc_offset_minutes, -300);
+        assert_eq!(config.allowed_tunnel_ids, [12, 40]);
+    }
+}
@@ -1,28 +1,109 @@
 #![allow(clippy::missing_safety_doc)]
 
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::VecDeque;
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_void};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex, OnceLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use playit_agent_core::network::acl::{ConnectionAcl, SharedAcl};
+use playit_agent_core::network::buffer_budget::BufferBudget;
 use playit_agent_core::network::origin_lookup::OriginLookup;
-use playit_agent_core::network::tcp::tcp_settings::TcpSettings;
+use playit_agent_core::network::tcp::tcp_settings::{ConnectionLimitMode, TcpSettings};
 use playit_agent_core::network::udp::udp_settings::UdpSettings;
 use playit_agent_core::playit_agent::{PlayitAgent, PlayitAgentSettings};
+use playit_agent_core::agent_control::maintained_control::ControlDiagnostics;
 use playit_agent_core::agent_control::version;
+use playit_agent_core::stats::AgentStats;
+use playit_agent_core::utils::clock::{Clock, SystemClock};
 use playit_api_client::PlayitApi;
+use playit_api_client::http_client::HttpClientSettings;
 use playit_api_client::api::{
-    AssignedAgentCreate, PortType, ReqTunnelsCreate, TunnelOriginCreate, TunnelType,
+    AgentRunDataV1, ApiErrorNoFail, ApiResponseError, AssignedAgentCreate, PortType, ReqAgentsRename,
+    ReqTunnelsCreate, TunnelOriginCreate, TunnelType,
 };
-use std::net::IpAddr;
+use playit_api_client::http_client::HttpClientError;
+use std::net::{IpAddr, SocketAddr};
 use serde::Deserialize;
-use tokio::sync::watch;
+use tokio::sync::{Notify, watch};
 use tracing::{Event, Level, Subscriber};
 use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
 use tracing_subscriber::registry::LookupSpan;
 
+mod account;
+mod acl;
+mod callbacks;
+mod config_error;
+mod config_watch;
+mod diagnostics;
+mod event_log;
+mod fetch;
+mod fingerprint;
+#[cfg(test)]
+mod harness;
+mod idle_reconnect;
+mod json_log;
+mod kv_config;
+#[cfg(unix)]
+mod log_fd;
+mod log_filter;
+mod log_rate;
+mod metrics;
+mod observed_ip;
+mod origin_dns;
+mod origin_map;
+mod origin_override;
+mod origin_probe;
+mod packet_flow;
+mod ping;
+mod random;
+mod reconfigure;
+mod reconnect_status;
+mod reset;
+mod schedule;
+mod stats;
+mod status_fields;
+mod status_timeline;
+mod test_connection;
+mod throughput;
+mod tunnel_priority;
+mod tunnels;
+
+const DEFAULT_API_URL: &str = "https://api.playit.gg";
+/// Tokio drivers `runtime_drivers` may name
+const RUNTIME_DRIVERS: [&str; 2] = ["io", "time"];
+/// Smallest `max_buffer_memory_kb`, room for the minimum UDP pool and a few connections
+const MIN_BUFFER_MEMORY_KB: u64 = 128;
+
+/// `worker_threads` as a count or "auto"
+#[derive(Deserialize, Clone, PartialEq)]
+#[cfg_attr(test, derive(serde::Serialize))]
+#[serde(untagged)]
+enum WorkerThreads {
+    Count(usize),
+    Named(String),
+}
+
+/// Threads for `worker_threads: "auto"`: one per CPU, capped at 1 for a max_connections of
+/// 8 or less, 2 up to 64 and 4 otherwise. Phones have few fast cores and a small setup
+/// doesn't need more than one thread moving packets.
+fn auto_worker_threads(cpus: usize, max_connections: Option<u32>) -> usize {
+    let cap = match max_connections {
+        Some(0..=8) => 1,
+        Some(9..=64) => 2,
+        _ => 4,
+    };
+    cpus.clamp(1, cap)
+}
+
 #[derive(Deserialize, Clone)]
+/* Serialize lets the diagnostics test find fields missing from config_json */
+#[cfg_attr(test, derive(serde::Serialize))]
 struct FfiConfig {
     secret_key: String,
     #[serde(default)]
@@ -31,39 +112,434 @@ struct FfiConfig {
     poll_interval_ms: Option<u64>,
     #[serde(default)]
     agent_version: Option<String>,
+    #[serde(default)]
+    insecure_skip_tls_verify: bool,
+    #[serde(default)]
+    agent_name: Option<String>,
+    #[serde(default)]
+    bind_address: Option<String>,
+    #[serde(default)]
+    control_source_port: Option<u16>,
+    #[serde(default)]
+    log_level: Option<String>,
+    #[serde(default)]
+    worker_threads: Option<WorkerThreads>,
+    #[serde(default)]
+    runtime_drivers: Option<Vec<String>>,
+    #[serde(default)]
+    max_connections: Option<u32>,
+    #[serde(default)]
+    max_connections_queue_ms: Option<u64>,
+    #[serde(default)]
+    tcp_backlog: Option<u32>,
+    #[serde(default)]
+    max_buffer_memory_kb: Option<u64>,
+    #[serde(default)]
+    lazy: bool,
+    #[serde(default)]
+    stop_wait_ms: Option<u64>,
+    #[serde(default)]
+    required_tunnel_id: Option<u64>,
+    #[serde(default)]
+    allowed_tunnel_ids: Vec<u64>,
+    #[serde(default)]
+    schedule: Vec<schedule::Window>,
+    #[serde(default)]
+    schedule_utc_offset_minutes: i32,
+    #[serde(default)]
+    allow_ips: Vec<String>,
+    #[serde(default)]
+    deny_ips: Vec<String>,
+    #[serde(default)]
+    poll_rundata: Option<bool>,
+    #[serde(default)]
+    require_traffic_for_connected: bool,
+    #[serde(default)]
+    require_tunnels: bool,
+    #[serde(default)]
+    account_info: bool,
+    #[serde(default)]
+    idle_reconnect_ms: Option<u64>,
+    #[serde(default)]
+    disconnect_give_up_ms: Option<u64>,
+    #[serde(default)]
+    disconnect_grace_ms: Option<u64>,
+    #[serde(default)]
+    tcp_nodelay: Option<bool>,
+    #[serde(default)]
+    api_timeout_ms: Option<u64>,
+    #[serde(default)]
+    origin_connect_retries: Option<u32>,
+    #[serde(default)]
+    origin_connect_retry_ms: Option<u64>,
+    #[serde(default)]
+    setup_retries: Option<u32>,
+    #[serde(default)]
+    setup_retry_ms: Option<u64>,
+    #[serde(default)]
+    startup_jitter_ms: Option<u64>,
+    #[serde(default)]
+    event_log_capacity: Option<usize>,
+}
+
+impl FfiConfig {
+    fn api_url(&self) -> String {
+        self.api_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string())
+    }
+
+    fn http_settings(&self) -> HttpClientSettings {
+        let version = self
+            .agent_version
+            .as_deref()
+            .unwrap_or(env!("CARGO_PKG_VERSION"));
+        let user_agent = match self.agent_name.as_deref() {
+            Some(name) => format!("playit-ios/{} ({})", version, name),
+            None => format!("playit-ios/{}", version),
+        };
+
+        HttpClientSettings {
+            danger_accept_invalid_certs: self.insecure_skip_tls_verify,
+            user_agent: Some(user_agent),
+            timeout: self.api_timeout(),
+        }
+    }
+
+    /// 15s unless set, a hung request shouldn't hold up the poll loop for long. 0 disables it.
+    fn api_timeout(&self) -> Option<Duration> {
+        match self.api_timeout_ms.unwrap_or(15_000) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    fn poll_interval_ms(&self) -> u64 {
+        self.poll_interval_ms.unwrap_or(3_000)
+    }
+
+    /// Nagle off by default, game traffic is mostly small latency sensitive packets
+    fn tcp_nodelay(&self) -> bool {
+        self.tcp_nodelay.unwrap_or(true)
+    }
+
+    /// No retries by default, a refused origin drops the connection right away
+    fn origin_connect_retries(&self) -> u32 {
+        self.origin_connect_retries.unwrap_or(0)
+    }
+
+    fn origin_connect_retry_ms(&self) -> u64 {
+        self.origin_connect_retry_ms.unwrap_or(500)
+    }
+
+    fn event_log_capacity(&self) -> usize {
+        self.event_log_capacity.unwrap_or(event_log::DEFAULT_CAPACITY)
+    }
+
+    /// Off for static setups, rundata is then only loaded once at start
+    fn poll_rundata(&self) -> bool {
+        self.poll_rundata.unwrap_or(true)
+    }
+
+    /// Longest random wait before the initial rundata load, 0 to load right away
+    fn startup_jitter(&self) -> Duration {
+        Duration::from_millis(self.startup_jitter_ms.unwrap_or(300))
+    }
+
+    /// Wait before setup retry `attempt` (from 0), doubling each time up to 30s
+    fn setup_retry_delay(&self, attempt: u32) -> Duration {
+        let base = self.setup_retry_ms.unwrap_or(1_000);
+        Duration::from_millis(base.saturating_mul(1 << attempt.min(16)).min(30_000))
+    }
+
+    /// Multi-thread runtime with the configured drivers, all of them unless set
+    fn worker_threads(&self) -> usize {
+        match &self.worker_threads {
+            None => 2,
+            Some(WorkerThreads::Count(count)) => *count,
+            Some(WorkerThreads::Named(_)) => {
+                let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+                auto_worker_threads(cpus, self.max_connections)
+            }
+        }
+    }
+
+    fn runtime_builder(&self) -> tokio::runtime::Builder {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.worker_threads(self.worker_threads());
+        match &self.runtime_drivers {
+            None => {
+                builder.enable_all();
+            }
+            Some(drivers) => {
+                for driver in drivers {
+                    match driver.as_str() {
+                        "io" => builder.enable_io(),
+                        "time" => builder.enable_time(),
+                        _ => &mut builder,
+                    };
+                }
+            }
+        }
+        builder
+    }
+
+    /// How long `playit_stop` waits for the agent thread to confirm it stopped
+    fn stop_wait(&self) -> Duration {
+        Duration::from_millis(self.stop_wait_ms.unwrap_or(2_000))
+    }
+
+    /// Lowest level forwarded to the log callback, everything if not set
+    fn log_level_code(&self) -> i32 {
+        self.log_level
+            .as_deref()
+            .and_then(log_level_code)
+            .unwrap_or(-1)
+    }
+
+    fn buffer_budget(&self) -> BufferBudget {
+        match self.max_buffer_memory_kb {
+            Some(kb) => BufferBudget::new((kb as usize).saturating_mul(1024)),
+            None => BufferBudget::default(),
+        }
+    }
+
+    fn tcp_backlog(&self) -> u32 {
+        self.tcp_backlog.unwrap_or(TcpSettings::default().new_client_ratelimit_burst)
+    }
+
+    fn connection_limit_mode(&self) -> ConnectionLimitMode {
+        match self.max_connections_queue_ms {
+            Some(ms) => ConnectionLimitMode::Queue {
+                timeout: Duration::from_millis(ms),
+            },
+            None => ConnectionLimitMode::Reject,
+        }
+    }
+
+    /// Ranges are checked by `build_config`, anything unparsable here was never accepted
+    fn acl(&self) -> ConnectionAcl {
+        acl::parse_acl(&self.allow_ips, &self.deny_ips).unwrap_or_default()
+    }
+
+    fn bind_address(&self) -> Option<IpAddr> {
+        self.bind_address.as_deref()?.parse().ok()
+    }
+
+    fn create_api(&self) -> PlayitApi {
+        PlayitApi::create_with_settings(
+            self.api_url(),
+            Some(self.secret_key.clone()),
+            &self.http_settings(),
+        )
+    }
+}
+
+/// Layout version of the repr(C) structs handed to the host, bumped whenever one of them
+/// changes. Each struct starts with it and its own size so mismatches can be detected.
+const STRUCT_ABI_VERSION: u32 = 2;
+
+/// Writes `value`, a struct starting with `struct_version` and `struct_size`, to a host
+/// struct of the size the host put in `struct_size`. Only that much is written (at least
+/// the header, at most all of `value`) and `struct_size` is set to what was filled, so an
+/// older host's smaller struct is never overrun.
+unsafe fn write_versioned<T: Copy>(out: *mut T, value: T) {
+    const HEADER: usize = 2 * size_of::<u32>();
+    let size_field = out.cast::<u8>().wrapping_add(size_of::<u32>()).cast::<u32>();
+    let host_size = unsafe { size_field.read_unaligned() } as usize;
+    let filled = host_size.clamp(HEADER, size_of::<T>());
+    let bytes = (&value as *const T).cast::<u8>();
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes, out.cast::<u8>(), filled);
+        size_field.write_unaligned(filled as u32);
+    }
 }
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct PlayitStatus {
+    pub struct_version: u32,
+    pub struct_size: u32,
     pub code: i32,
     pub last_address: *const c_char,
     pub last_error: *const c_char,
+    pub reconnect_attempts: u32,
 }
 
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum PlayitStatusCode {
     Stopped = 0,
     Connecting = 1,
     Connected = 2,
     Disconnected = 3,
     Error = 4,
+    AuthFailed = 5,
+    /// Started with `lazy`, waiting for `playit_activate` before connecting
+    Idle = 6,
+    /// Tunnel is up but the local server behind a TCP tunnel refused the probe connection
+    OriginUnreachable = 7,
+    /// The API answered 429, the agent holds off and retries by itself
+    RateLimited = 8,
+    /// Tunnel is up but a hostname set as local origin doesn't resolve
+    OriginResolveFailed = 9,
+    /// Disconnected past `disconnect_give_up_ms`, the agent stopped until the next start
+    GaveUp = 10,
+    /// Started with `require_tunnels` on an account without tunnels, the agent stopped
+    NoTunnels = 11,
+    /// Was Connected, the tunnel session dropped and is being re-established
+    Reconnecting = 12,
+    /// Outside every `schedule` window, the agent starts again at the next one
+    ScheduledOff = 13,
+}
+
+impl PlayitStatusCode {
+    fn is_error(self) -> bool {
+        matches!(
+            self,
+            PlayitStatusCode::Error
+                | PlayitStatusCode::AuthFailed
+                | PlayitStatusCode::OriginUnreachable
+                | PlayitStatusCode::OriginResolveFailed
+                | PlayitStatusCode::NoTunnels
+        )
+    }
+
+    fn from_api_error(error: &ApiErrorNoFail<HttpClientError>) -> Self {
+        match error {
+            ApiErrorNoFail::ApiError(ApiResponseError::Auth(_)) => PlayitStatusCode::AuthFailed,
+            ApiErrorNoFail::ClientError(HttpClientError::TooManyRequests { .. }) => {
+                PlayitStatusCode::RateLimited
+            }
+            _ => PlayitStatusCode::Error,
+        }
+    }
 }
 
 type LogCallback = extern "C" fn(level: i32, message: *const c_char, user_data: *mut c_void);
+/// Same as `LogCallback` plus the tracing target as category (ex. "playit_agent_core::...")
+type LogEventCallback = extern "C" fn(
+    level: i32,
+    category: *const c_char,
+    message: *const c_char,
+    user_data: *mut c_void,
+);
 
 struct LogCallbackState {
-    callback: Option<LogCallback>,
-    user_data: *mut c_void,
+    /// (handle, callback, user data) in registration order
+    callbacks: Vec<(u64, LogCallback, *mut c_void)>,
+    next_handle: u64,
+    event_callback: Option<LogEventCallback>,
+    event_user_data: *mut c_void,
+    /// Gets each event as a JSON object instead of the flattened line
+    json_callback: Option<LogCallback>,
+    json_user_data: *mut c_void,
+    /// (level, target, message, JSON line) logged while no callback was set, flushed to the
+    /// next one registered
+    pending: VecDeque<(i32, String, String, Option<String>)>,
+}
+
+/// Enough for everything init and a first start log
+const MAX_PENDING_LOGS: usize = 200;
+
+thread_local! {
+    /// Set while this thread is inside a log callback, which holds the log lock
+    static IN_LOG_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Handles removed from inside a log callback, applied once the fan-out finishes
+static DEFERRED_LOG_REMOVALS: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+impl LogCallbackState {
+    fn new() -> Self {
+        LogCallbackState {
+            callbacks: Vec::new(),
+            next_handle: 1,
+            event_callback: None,
+            event_user_data: std::ptr::null_mut(),
+            json_callback: None,
+            json_user_data: std::ptr::null_mut(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn has_receiver(&self) -> bool {
+        !self.callbacks.is_empty() || self.event_callback.is_some() || self.json_callback.is_some()
+    }
+
+    /// Also true while buffering, a JSON callback may be the one that flushes it
+    fn wants_json(&self) -> bool {
+        self.json_callback.is_some() || !self.has_receiver()
+    }
+
+    fn add(&mut self, callback: LogCallback, user_data: *mut c_void) -> u64 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.callbacks.push((handle, callback, user_data));
+        handle
+    }
+
+    fn deliver(&mut self, level_code: i32, target: &str, message: &str, json: Option<&str>) {
+        if !self.has_receiver() {
+            if self.pending.len() == MAX_PENDING_LOGS {
+                self.pending.pop_front();
+            }
+            self.pending.push_back((
+                level_code,
+                target.to_string(),
+                message.to_string(),
+                json.map(str::to_string),
+            ));
+            return;
+        }
+
+        let c_message = cstring_sanitize(message);
+        IN_LOG_CALLBACK.set(true);
+        for (handle, callback, user_data) in &self.callbacks {
+            /* removed by an earlier callback of this same fan-out */
+            let deferred = DEFERRED_LOG_REMOVALS.lock().expect("log removals lock poisoned");
+            if deferred.contains(handle) {
+                continue;
+            }
+            drop(deferred);
+
+            callback(level_code, c_message.as_ptr(), *user_data);
+        }
+        if let Some(callback) = self.event_callback {
+            let c_target = cstring_sanitize(target);
+            callback(level_code, c_target.as_ptr(), c_message.as_ptr(), self.event_user_data);
+        }
+        if let (Some(callback), Some(json)) = (self.json_callback, json) {
+            let c_json = cstring_sanitize(json);
+            callback(level_code, c_json.as_ptr(), self.json_user_data);
+        }
+        IN_LOG_CALLBACK.set(false);
+
+        let removed = std::mem::take(
+            &mut *DEFERRED_LOG_REMOVALS.lock().expect("log removals lock poisoned"),
+        );
+        self.callbacks.retain(|(handle, ..)| !removed.contains(handle));
+    }
+
+    fn flush_pending(&mut self) {
+        if !self.has_receiver() {
+            return;
+        }
+        for (level_code, target, message, json) in std::mem::take(&mut self.pending) {
+            self.deliver(level_code, &target, &message, json.as_deref());
+        }
+    }
 }
 
 unsafe impl Send for LogCallbackState {}
 unsafe impl Sync for LogCallbackState {}
 
+#[derive(Clone, PartialEq)]
 struct StatusSnapshot {
     code: PlayitStatusCode,
     last_address: Option<CString>,
     last_error: Option<CString>,
+    tunnels: Vec<tunnels::TunnelInfo>,
 }
 
 struct GlobalState {
@@ -72,36 +548,159 @@ struct GlobalState {
     running: bool,
     stop_tx: Option<watch::Sender<bool>>,
     stopped_rx: Option<std::sync::mpsc::Receiver<()>>,
+    /// Holds false while a lazy start waits for activation
+    activate_tx: Option<watch::Sender<bool>>,
     keep_running: Option<Arc<AtomicBool>>,
+    force_reconnect: Option<Arc<AtomicBool>>,
+    device_model: Option<String>,
+    reconnect_attempts: Option<Arc<AtomicU32>>,
+    observed_addr: Option<Arc<Mutex<Option<SocketAddr>>>>,
+    diagnostics: Option<Arc<Mutex<ControlDiagnostics>>>,
+    /// Origins of the running agent, for `playit_set_tunnel_origin`
+    lookup: Option<Arc<OriginLookup>>,
+    /// Live handle to the running agent's ACL
+    acl: Option<SharedAcl>,
+    /// Buffer memory of the running agent, for `playit_get_all_metrics`
+    buffer_budget: Option<BufferBudget>,
+    stats: Option<AgentStats>,
+    /// (secret key, agent id) from the latest initial rundata load, kept after a stop
+    agent_id: Option<(String, String)>,
+    /// Fingerprint of the config the agent was last started with
+    running_fingerprint: Option<String>,
+    /// The config the agent was last started with, what `playit_reconfigure` compares to
+    running_config: Option<FfiConfig>,
+    /// Bumped by every `playit_start`, so a thread that outlives its stop can tell its run
+    /// was replaced
+    run: u64,
 }
 
-static STATE: OnceLock<Mutex<GlobalState>> = OnceLock::new();
-static LOG_CALLBACK: OnceLock<Mutex<LogCallbackState>> = OnceLock::new();
-static LOG_INIT: OnceLock<()> = OnceLock::new();
-
-fn state() -> &'static Mutex<GlobalState> {
-    STATE.get_or_init(|| {
-        Mutex::new(GlobalState {
+impl GlobalState {
+    fn new() -> Self {
+        GlobalState {
             config: None,
             status: Arc::new(Mutex::new(StatusSnapshot {
                 code: PlayitStatusCode::Stopped,
                 last_address: None,
                 last_error: None,
+                tunnels: Vec::new(),
             })),
             running: false,
             stop_tx: None,
             stopped_rx: None,
+            activate_tx: None,
             keep_running: None,
-        })
-    })
+            force_reconnect: None,
+            device_model: None,
+            reconnect_attempts: None,
+            observed_addr: None,
+            diagnostics: None,
+            lookup: None,
+            acl: None,
+            buffer_budget: None,
+            stats: None,
+            agent_id: None,
+            running_fingerprint: None,
+            running_config: None,
+            run: 0,
+        }
+    }
+
+    /// The loaded config as `playit_start` would run it, with the device model fallback
+    fn start_config(&self) -> Option<FfiConfig> {
+        Some(self.with_fallbacks(self.config.clone()?))
+    }
+
+    fn with_fallbacks(&self, mut config: FfiConfig) -> FfiConfig {
+        if config.agent_name.is_none() {
+            config.agent_name = self.device_model.clone();
+        }
+        config
+    }
+
+    /// Ends `run` if no later `playit_start` replaced it, false otherwise
+    fn end_run(&mut self, run: u64) -> bool {
+        if self.run != run {
+            return false;
+        }
+        self.running = false;
+        NEXT_POLL_AT.store(0, Ordering::Release);
+        self.clear_run_state();
+        true
+    }
+
+    /// Stopped, but the agent thread hasn't finished yet
+    fn stopping(&self) -> bool {
+        self.running && self.stop_tx.is_none()
+    }
+
+    /// Drops every handle into the current run of the agent
+    fn clear_run_state(&mut self) {
+        self.stop_tx = None;
+        self.stopped_rx = None;
+        self.activate_tx = None;
+        self.keep_running = None;
+        self.force_reconnect = None;
+        self.reconnect_attempts = None;
+        self.lookup = None;
+        self.diagnostics = None;
+        self.observed_addr = None;
+        self.acl = None;
+        self.buffer_budget = None;
+        self.stats = None;
+    }
+}
+
+static STATE: OnceLock<Mutex<GlobalState>> = OnceLock::new();
+static LOG_CALLBACK: OnceLock<Mutex<LogCallbackState>> = OnceLock::new();
+/// Whether our subscriber became the global default, false if another one was already set
+static LOG_INIT: OnceLock<bool> = OnceLock::new();
+/// Set by `playit_restart(quiet)` until the restarted agent settles
+static QUIET_RESTART: AtomicBool = AtomicBool::new(false);
+/// Copy of the current status code for lock free reads, written by `update_status`
+static STATUS_CODE: AtomicI32 = AtomicI32::new(PlayitStatusCode::Stopped as i32);
+/// Bumped by `update_status` whenever code, address, error or tunnels change
+static STATUS_REVISION: AtomicU64 = AtomicU64::new(1);
+/// Unix ms the poll loop next wakes at, 0 while the agent isn't running
+static NEXT_POLL_AT: AtomicU64 = AtomicU64::new(0);
+/// Read by the poll loop each iteration so it can be changed by `playit_reconfigure`
+static POLL_INTERVAL_MS: AtomicU64 = AtomicU64::new(3_000);
+static LOG_LEVEL: AtomicI32 = AtomicI32::new(-1);
+/// Longest log message in bytes passed on, 0 for no limit
+static MAX_LOG_LENGTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_LOG_LENGTH);
+const DEFAULT_MAX_LOG_LENGTH: usize = 4_096;
+/// Failed rundata polls over the lifetime of the library, for `playit_metrics_text`
+static POLL_ERRORS: AtomicU64 = AtomicU64::new(0);
+/// 1 if the latest rundata load succeeded, 0 if it failed, -1 before the first one of a start
+static LAST_POLL_OK: AtomicI32 = AtomicI32::new(-1);
+/// Bumped every `LIVENESS_INTERVAL` by a task on the agent runtime, see `playit_liveness`
+static LIVENESS: AtomicU64 = AtomicU64::new(0);
+const LIVENESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Unix ms of the last transition to Connected, 0 while not connected
+static CONNECTED_SINCE: AtomicU64 = AtomicU64::new(0);
+/// Unix ms since when rundata has had no tunnel address, 0 while it has one
+static NO_ADDRESS_SINCE: AtomicU64 = AtomicU64::new(0);
+/// Last status address seen, kept through disconnects and restarts to log changes
+static LAST_SEEN_ADDRESS: Mutex<Option<String>> = Mutex::new(None);
+/// Wakes the poll loop early after `playit_notify_resumed`
+static RESUMED: Notify = Notify::const_new();
+/// A poll that wakes this much later than planned means the process was suspended
+const SUSPEND_GAP_MS: u64 = 10_000;
+/// How often `run_agent` checks whether the core cleared `keep_running` by itself
+const KEEP_RUNNING_CHECK: Duration = Duration::from_millis(250);
+
+/// Time source for timestamps kept by this crate, logic that depends on it takes a
+/// `&dyn Clock` so tests can pass a `ManualClock`
+fn clock() -> &'static dyn Clock {
+    &SystemClock
+}
+
+fn state() -> &'static Mutex<GlobalState> {
+    STATE.get_or_init(|| Mutex::new(GlobalState::new()))
 }
 
 fn log_state() -> &'static Mutex<LogCallbackState> {
     LOG_CALLBACK.get_or_init(|| {
-        Mutex::new(LogCallbackState {
-            callback: None,
-            user_data: std::ptr::null_mut(),
-        })
+        Mutex::new(LogCallbackState::new())
     })
 }
 
@@ -111,23 +710,121 @@ fn set_status(code: PlayitStatusCode, address: Option<String>, error: Option<Str
         .expect("state lock poisoned")
         .status
         .clone();
-    let mut lock = status.lock().expect("status lock poisoned");
-    lock.code = code;
-    lock.last_address = address.and_then(|v| cstring_sanitize(v).ok());
-    lock.last_error = error.and_then(|v| cstring_sanitize(v).ok());
+    update_status(&status, |lock| {
+        lock.code = code;
+        lock.last_address = address.map(cstring_sanitize);
+        lock.last_error = error.map(cstring_sanitize);
+    });
+}
+
+/// All status writes go through here so host callbacks see every code change exactly
+/// once. Callbacks run after the status lock is released.
+fn update_status(status: &Arc<Mutex<StatusSnapshot>>, update: impl FnOnce(&mut StatusSnapshot)) {
+    let changed = {
+        let mut lock = status.lock().expect("status lock poisoned");
+        let before = lock.clone();
+        update(&mut lock);
+        STATUS_CODE.store(lock.code as i32, Ordering::Release);
+        if *lock != before {
+            STATUS_REVISION.fetch_add(1, Ordering::AcqRel);
+        }
+
+        if lock.code != before.code {
+            let since = match lock.code {
+                PlayitStatusCode::Connected => clock().now_ms().max(1),
+                _ => 0,
+            };
+            CONNECTED_SINCE.store(since, Ordering::Release);
+            status_timeline::entered(lock.code, clock().now_ms());
+            Some((lock.code, lock.last_error.clone()))
+        } else {
+            None
+        }
+    };
+
+    if let Some((code, error)) = changed {
+        let kind = if code.is_error() { "error" } else { "status" };
+        let message = error.as_ref().map(|error| error.to_string_lossy());
+        event_log::record(kind, serde_json::json!({ "code": code as i32, "error": message }));
+        if !quiet_restart_hides(&QUIET_RESTART, code) {
+            callbacks::status_changed(code, error.as_ref());
+        }
+    }
+}
+
+/// Whether a quiet restart is underway and `code` is one it passes through on the way to
+/// the settled state. The first other code ends the quiet restart.
+fn quiet_restart_hides(quiet: &AtomicBool, code: PlayitStatusCode) -> bool {
+    if !quiet.load(Ordering::Acquire) {
+        return false;
+    }
+    match code {
+        /* any other state is where the restart settled, Disconnected and RateLimited too */
+        PlayitStatusCode::Stopped | PlayitStatusCode::Connecting => true,
+        _ => {
+            quiet.store(false, Ordering::Release);
+            false
+        }
+    }
+}
+
+/// Ends a quiet restart that didn't get to settle, the host hears the status it ended on
+fn end_quiet_restart() {
+    if !QUIET_RESTART.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    let status = state().lock().expect("state lock poisoned").status.clone();
+    let (code, error) = {
+        let lock = status.lock().expect("status lock poisoned");
+        (lock.code, lock.last_error.clone())
+    };
+    callbacks::status_changed(code, error.as_ref());
+}
+
+fn set_status_error(status: &Arc<Mutex<StatusSnapshot>>, code: PlayitStatusCode, error: String) {
+    update_status(status, |lock| {
+        lock.code = code;
+        lock.last_error = Some(cstring_sanitize(error));
+    });
+}
+
+struct RunError {
+    code: PlayitStatusCode,
+    message: String,
+}
+
+impl From<String> for RunError {
+    fn from(message: String) -> Self {
+        RunError {
+            code: PlayitStatusCode::Error,
+            message,
+        }
+    }
 }
 
-fn cstring_sanitize(value: String) -> Result<CString, std::ffi::NulError> {
-    let cleaned = value.replace('\0', "");
-    CString::new(cleaned)
+/// Builds a C string that can always be handed to the host: invalid UTF-8 sequences are
+/// replaced with U+FFFD and nul bytes are dropped, so nothing is lost to an edge case.
+fn cstring_sanitize(value: impl AsRef<[u8]>) -> CString {
+    let cleaned: Vec<u8> = String::from_utf8_lossy(value.as_ref())
+        .bytes()
+        .filter(|&b| b != 0)
+        .collect();
+    CString::new(cleaned).expect("nul bytes removed")
 }
 
-fn ensure_logging() {
-    LOG_INIT.get_or_init(|| {
+fn ensure_logging() -> bool {
+    *LOG_INIT.get_or_init(|| {
         let layer = CallbackLayer;
         let subscriber = tracing_subscriber::registry().with(layer);
-        let _ = tracing::subscriber::set_global_default(subscriber);
-    });
+        tracing::subscriber::set_global_default(subscriber).is_ok()
+    })
+}
+
+/// False if the process already had a global tracing subscriber, in which case nothing
+/// reaches the log callback.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_logging_active() -> bool {
+    ensure_logging()
 }
 
 struct CallbackLayer;
@@ -137,6 +834,9 @@ where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
     fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if !log_filter::allows(event.metadata().target()) {
+            return;
+        }
         let level = *event.metadata().level();
         let mut visitor = LogVisitor::default();
         event.record(&mut visitor);
@@ -167,7 +867,7 @@ where
             }
         }
 
-        send_log(level, &message);
+        send_log(level, event.metadata().target(), &message, &visitor);
     }
 }
 
@@ -175,6 +875,24 @@ where
 struct LogVisitor {
     message: Option<String>,
     fields: Vec<(String, String)>,
+    /// Same fields keeping their type where tracing passes one, for JSON logs
+    json_fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl LogVisitor {
+    fn record_value(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &dyn std::fmt::Debug,
+        json: serde_json::Value,
+    ) {
+        if field.name() == "message" {
+            tracing::field::Visit::record_debug(self, field, value);
+            return;
+        }
+        self.fields.push((field.name().to_string(), format!("{:?}", value)));
+        self.json_fields.insert(field.name().to_string(), json);
+    }
 }
 
 impl tracing::field::Visit for LogVisitor {
@@ -182,18 +900,35 @@ impl tracing::field::Visit for LogVisitor {
         if field.name() == "message" {
             self.message = Some(format!("{:?}", value).trim_matches('"').to_string());
         } else {
-            self.fields
-                .push((field.name().to_string(), format!("{:?}", value)));
+            let value = format!("{:?}", value);
+            self.json_fields
+                .insert(field.name().to_string(), serde_json::Value::from(value.as_str()));
+            self.fields.push((field.name().to_string(), value));
         }
     }
-}
 
-fn send_log(level: Level, message: &str) {
-    let lock = log_state().lock().expect("log callback lock poisoned");
-    let Some(callback) = lock.callback else {
-        return;
-    };
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.record_value(field, &value, value.into());
+    }
+
+    fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+        self.record_value(field, &value, value.into());
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        self.record_value(field, &value, value.into());
+    }
 
+    fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
+        self.record_value(field, &value, value.into());
+    }
+
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        self.record_value(field, &value, value.into());
+    }
+}
+
+fn send_log(level: Level, target: &str, message: &str, event: &LogVisitor) {
     let level_code = match level {
         Level::ERROR => 3,
         Level::WARN => 2,
@@ -202,161 +937,664 @@ fn send_log(level: Level, message: &str) {
         Level::TRACE => -1,
     };
 
-    let cleaned = message.replace('\0', "");
-    let Ok(c_message) = CString::new(cleaned) else {
+    if level_code < LOG_LEVEL.load(Ordering::Relaxed) {
         return;
-    };
+    }
+    if !log_rate::allow(clock().now_ms()) {
+        return;
+    }
 
-    callback(level_code, c_message.as_ptr(), lock.user_data);
-}
+    let max_length = MAX_LOG_LENGTH.load(Ordering::Relaxed);
+    let message = truncate_log(message, max_length);
+
+    #[cfg(unix)]
+    let fd_error = log_fd::write_log(level, &message);
 
-#[unsafe(no_mangle)]
-pub extern "C" fn playit_set_log_callback(callback: Option<LogCallback>, user_data: *mut c_void) {
-    ensure_logging();
     let mut lock = log_state().lock().expect("log callback lock poisoned");
-    lock.callback = callback;
-    lock.user_data = user_data;
+    let json = lock.wants_json().then(|| {
+        let bare = truncate_log(event.message.as_deref().unwrap_or(""), max_length);
+        json_log::line(clock().now_ms(), level, target, &bare, &event.json_fields)
+    });
+    lock.deliver(level_code, target, &message, json.as_deref());
+
+    #[cfg(unix)]
+    if let Some(error) = fd_error {
+        let message = format!("log fd write failed, fd logging disabled: {}", error);
+        let json = lock.wants_json().then(|| {
+            let fields = serde_json::Map::new();
+            json_log::line(clock().now_ms(), Level::WARN, module_path!(), &message, &fields)
+        });
+        lock.deliver(2, module_path!(), &message, json.as_deref());
+    }
 }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn playit_init(config_json: *const c_char) -> i32 {
-    ensure_logging();
-    if config_json.is_null() {
-        return -1;
+/// Cuts `message` to at most `max` bytes on a char boundary, ending in an ellipsis
+fn truncate_log(message: &str, max: usize) -> Cow<'_, str> {
+    const ELLIPSIS: &str = "…";
+
+    if max == 0 || message.len() <= max {
+        return Cow::Borrowed(message);
     }
 
-    let c_str = unsafe { CStr::from_ptr(config_json) };
-    let json = match c_str.to_str() {
-        Ok(v) => v,
-        Err(_) => return -2,
-    };
+    let mut end = max.saturating_sub(ELLIPSIS.len());
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!("{}{}", &message[..end], ELLIPSIS))
+}
 
-    let config: FfiConfig = match serde_json::from_str(json) {
-        Ok(v) => v,
-        Err(_) => return -3,
-    };
+/// Longest log message in bytes (default 4096), longer ones are cut and end in "…". 0
+/// removes the limit.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_max_log_length(bytes: u32) {
+    MAX_LOG_LENGTH.store(bytes as usize, Ordering::Relaxed);
+}
 
-    {
-        let mut lock = state().lock().expect("state lock poisoned");
-        lock.config = Some(config);
-        lock.keep_running = None;
-        lock.running = false;
-        lock.stop_tx = None;
-        lock.stopped_rx = None;
+fn log_level_code(level: &str) -> Option<i32> {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "trace" => Some(-1),
+        "debug" => Some(0),
+        "info" => Some(1),
+        "warn" => Some(2),
+        "error" => Some(3),
+        _ => None,
     }
-    set_status(PlayitStatusCode::Stopped, None, None);
-    0
 }
 
+/// Replaces every callback added so far with `callback`, NULL removes them all
 #[unsafe(no_mangle)]
-pub extern "C" fn playit_start() -> i32 {
+pub extern "C" fn playit_set_log_callback(callback: Option<LogCallback>, user_data: *mut c_void) {
     ensure_logging();
-    let (config, status) = {
+    let mut lock = log_state().lock().expect("log callback lock poisoned");
+    lock.callbacks.clear();
+    if let Some(callback) = callback {
+        lock.add(callback, user_data);
+    }
+    lock.flush_pending();
+}
+
+/// Registers another log callback next to the existing ones. Returns its handle for
+/// `playit_remove_log_callback`, 0 for a NULL callback.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_add_log_callback(
+    callback: Option<LogCallback>,
+    user_data: *mut c_void,
+) -> u64 {
+    ensure_logging();
+    let Some(callback) = callback else {
+        return 0;
+    };
+
+    let mut lock = log_state().lock().expect("log callback lock poisoned");
+    let handle = lock.add(callback, user_data);
+    lock.flush_pending();
+    handle
+}
+
+/// Once this returns the callback isn't invoked again. From inside a log callback the
+/// removal is applied when the current line has been delivered.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_remove_log_callback(handle: u64) {
+    if IN_LOG_CALLBACK.get() {
+        /* the fan-out on this thread holds the log lock */
+        DEFERRED_LOG_REMOVALS
+            .lock()
+            .expect("log removals lock poisoned")
+            .push(handle);
+        return;
+    }
+
+    let mut lock = log_state().lock().expect("log callback lock poisoned");
+    lock.callbacks.retain(|(id, ..)| *id != handle);
+}
+
+/// Alternative to `playit_set_log_callback` that also passes the tracing target, for hosts
+/// that map it to a log category. Both can be set, each line then goes to both.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_log_event_callback(
+    callback: Option<LogEventCallback>,
+    user_data: *mut c_void,
+) {
+    ensure_logging();
+    let mut lock = log_state().lock().expect("log callback lock poisoned");
+    lock.event_callback = callback;
+    lock.event_user_data = user_data;
+    lock.flush_pending();
+}
+
+/// Gets each log event as one line of JSON with timestamp, level, target, message and
+/// fields kept apart, for hosts that forward logs to an aggregator. Can be set next to the
+/// other log callbacks.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_json_log_callback(
+    callback: Option<LogCallback>,
+    user_data: *mut c_void,
+) {
+    ensure_logging();
+    let mut lock = log_state().lock().expect("log callback lock poisoned");
+    lock.json_callback = callback;
+    lock.json_user_data = user_data;
+    lock.flush_pending();
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_init(config_json: *const c_char) -> i32 {
+    ensure_logging();
+    let config = match unsafe { parse_config_json(config_json) } {
+        Ok(v) => v,
+        Err(code) => return code,
+    };
+
+    install_config(config)
+}
+
+/// Same as `playit_init` with the config as exactly `len` bytes of UTF-8 JSON, no nul
+/// terminator needed
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_init_buf(config_json: *const u8, len: usize) -> i32 {
+    ensure_logging();
+    let config = match unsafe { parse_config_buf(config_json, len) } {
+        Ok(v) => v,
+        Err(code) => return code,
+    };
+
+    install_config(config)
+}
+
+/// Drops a bind_address that isn't on any interface (ex. WiFi is off) so default routing
+/// is used instead, returns why for last_error
+fn check_bind_address(config: &mut FfiConfig) -> Option<String> {
+    let ip = config.bind_address()?;
+    let error = std::net::UdpSocket::bind((ip, 0)).err()?;
+    let message = format!(
+        "bind_address {} is not available ({}), using default interface",
+        ip, error
+    );
+    tracing::warn!("{}", message);
+    config.bind_address = None;
+    Some(message)
+}
+
+fn install_config(mut config: FfiConfig) -> i32 {
+    let bind_error = check_bind_address(&mut config);
+
+    LOG_LEVEL.store(config.log_level_code(), Ordering::Relaxed);
+    event_log::set_capacity(config.event_log_capacity());
+
+    {
         let mut lock = state().lock().expect("state lock poisoned");
+        lock.config = Some(config);
+        lock.running = false;
+        lock.clear_run_state();
+    }
+    set_status(PlayitStatusCode::Stopped, None, bind_error);
+    0
+}
+
+unsafe fn parse_config_json(config_json: *const c_char) -> Result<FfiConfig, i32> {
+    if config_json.is_null() {
+        return Err(config_error::fail(-1, "config_json is NULL"));
+    }
+
+    let c_str = unsafe { CStr::from_ptr(config_json) };
+    parse_config_bytes(c_str.to_bytes())
+}
+
+unsafe fn parse_config_buf(config_json: *const u8, len: usize) -> Result<FfiConfig, i32> {
+    if config_json.is_null() && len != 0 {
+        return Err(config_error::fail(-1, "config_json is NULL"));
+    }
+    if len == 0 {
+        return parse_config_bytes(&[]);
+    }
+    parse_config_bytes(unsafe { std::slice::from_raw_parts(config_json, len) })
+}
+
+fn parse_config_bytes(bytes: &[u8]) -> Result<FfiConfig, i32> {
+    let json = match std::str::from_utf8(bytes) {
+        Ok(v) => v,
+        Err(_) => return Err(config_error::fail(-2, "config_json is not valid UTF-8")),
+    };
+
+    let value = serde_json::from_str(json)
+        .map_err(|error| config_error::fail(-3, config_error::syntax_error(json, &error)))?;
+    build_config(value)
+}
+
+/// Every init path converts its input to a JSON value and funnels through here, so
+/// validation and error codes can't diverge between them.
+/// -3 for a missing/empty secret_key or wrongly typed field, -4 for a bad bind_address.
+/// The reason is kept for `playit_last_init_error`.
+fn build_config(value: serde_json::Value) -> Result<FfiConfig, i32> {
+    /* serde would also accept the fields as an array, in declaration order */
+    if !value.is_object() {
+        return Err(config_error::fail(-3, config_error::expected_object(&value)));
+    }
+    let config: FfiConfig = serde_json::from_value(value.clone())
+        .map_err(|error| config_error::fail(-3, config_error::shape_error(&value, &error)))?;
+
+    if config.secret_key.trim().is_empty() {
+        return Err(config_error::fail(-3, "secret_key is empty"));
+    }
+
+    if let Some(bind_address) = config.bind_address.as_deref()
+        && bind_address.parse::<IpAddr>().is_err()
+    {
+        let message = format!("bind_address \"{}\" is not an IP address", bind_address);
+        return Err(config_error::fail(-4, message));
+    }
+    if config.control_source_port == Some(0) {
+        return Err(config_error::fail(-3, "control_source_port must be 1 to 65535"));
+    }
+
+    if let Some(level) = config.log_level.as_deref()
+        && log_level_code(level).is_none()
+    {
+        let message = format!(
+            "log_level \"{}\" is not one of trace, debug, info, warn, error",
+            level
+        );
+        return Err(config_error::fail(-3, message));
+    }
+
+    match &config.worker_threads {
+        Some(WorkerThreads::Count(0)) => {
+            return Err(config_error::fail(-3, "worker_threads must be at least 1"));
+        }
+        Some(WorkerThreads::Named(name)) if name != "auto" => {
+            let message = format!("worker_threads \"{}\" is not a number or \"auto\"", name);
+            return Err(config_error::fail(-3, message));
+        }
+        _ => {}
+    }
+    if let Some(drivers) = &config.runtime_drivers {
+        if let Some(unknown) = drivers.iter().find(|d| !RUNTIME_DRIVERS.contains(&d.as_str())) {
+            let message = format!("runtime_drivers \"{}\" is not one of io, time", unknown);
+            return Err(config_error::fail(-3, message));
+        }
+        /* every driver is needed today: sockets need io, timeouts and polling need time */
+        if let Some(missing) = RUNTIME_DRIVERS.iter().find(|d| !drivers.iter().any(|v| v == *d)) {
+            let message = format!("runtime_drivers must include {}, the agent needs it", missing);
+            return Err(config_error::fail(-3, message));
+        }
+    }
+    if config.max_connections == Some(0) {
+        return Err(config_error::fail(-3, "max_connections must be at least 1"));
+    }
+    if config.tcp_backlog == Some(0) {
+        return Err(config_error::fail(-3, "tcp_backlog must be at least 1"));
+    }
+    if let Err(message) = schedule::validate(&config.schedule) {
+        return Err(config_error::fail(-3, message));
+    }
+    if !schedule::UTC_OFFSET_RANGE.contains(&config.schedule_utc_offset_minutes) {
+        let message = "schedule_utc_offset_minutes must be -720 to 840";
+        return Err(config_error::fail(-3, message));
+    }
+    if config.max_buffer_memory_kb.is_some_and(|kb| kb < MIN_BUFFER_MEMORY_KB) {
+        let message = format!("max_buffer_memory_kb must be at least {}", MIN_BUFFER_MEMORY_KB);
+        return Err(config_error::fail(-3, message));
+    }
+
+    if let Err(error) = acl::parse_acl(&config.allow_ips, &config.deny_ips) {
+        return Err(config_error::fail(-3, format!("allow_ips/deny_ips: {}", error)));
+    }
+
+    config_error::clear();
+    Ok(config)
+}
+
+/// Copies `value` into `buf` as a nul-terminated string, truncating on a char boundary
+/// if it doesn't fit. Returns the full length of `value` (like `snprintf`), so a result
+/// `>= len` means the output was truncated.
+unsafe fn write_c_buffer(value: &str, buf: *mut c_char, len: usize) -> i32 {
+    let value = value.replace('\0', "");
+
+    if !buf.is_null() && len > 0 {
+        let mut end = value.len().min(len - 1);
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(value.as_ptr(), buf as *mut u8, end);
+            *buf.add(end) = 0;
+        }
+    }
+
+    value.len().min(i32::MAX as usize) as i32
+}
+
+/// Id the dashboard shows for the agent, from the initial rundata load of the last start.
+/// -1 if it hasn't loaded yet for the configured secret key.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_agent_id(buf: *mut c_char, len: usize) -> i32 {
+    let agent_id = {
+        let lock = state().lock().expect("state lock poisoned");
+        match (&lock.agent_id, &lock.config) {
+            (Some((key, agent_id)), Some(config)) if *key == config.secret_key => agent_id.clone(),
+            _ => return -1,
+        }
+    };
+    unsafe { write_c_buffer(&agent_id, buf, len) }
+}
+
+/// `api_url` of the loaded config with the default applied, -1 if no config is loaded
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_api_url(buf: *mut c_char, len: usize) -> i32 {
+    let api_url = match &state().lock().expect("state lock poisoned").config {
+        Some(config) => config.api_url(),
+        None => return -1,
+    };
+    unsafe { write_c_buffer(&api_url, buf, len) }
+}
+
+/// Name used for the agent when the config doesn't set `agent_name`, ex. the device
+/// model. Takes effect on the next `playit_start`; pass null to clear it.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_set_device_model(model: *const c_char) -> i32 {
+    let model = if model.is_null() {
+        None
+    } else {
+        match unsafe { CStr::from_ptr(model) }.to_str() {
+            Ok(v) => Some(v.to_string()),
+            Err(_) => return -2,
+        }
+    };
+
+    state().lock().expect("state lock poisoned").device_model = model;
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_start() -> i32 {
+    ensure_logging();
+    let (stop_tx, mut stop_rx) = watch::channel(false);
+    let (stopped_tx, stopped_rx) = std::sync::mpsc::channel();
+    let (config, status, run) = {
+        let mut lock = state().lock().expect("state lock poisoned");
+        /* also while the thread of a timed out stop is finishing */
         if lock.running {
             return -2;
         }
 
-        let config = match lock.config.clone() {
-            Some(v) => v,
-            None => return -1,
+        let Some(config) = lock.start_config() else {
+            return -1;
         };
 
         lock.running = true;
+        lock.run += 1;
+        lock.running_fingerprint = Some(fingerprint::fingerprint(&config));
+        lock.running_config = Some(config.clone());
+        if !config.lazy {
+            NEXT_POLL_AT.store(clock().now_ms(), Ordering::Release);
+        }
         let status = lock.status.clone();
-        lock.keep_running = None;
-        (config, status)
+        lock.clear_run_state();
+        /* stoppable from here on, even before the thread exists */
+        lock.stop_tx = Some(stop_tx);
+        lock.stopped_rx = Some(stopped_rx);
+        (config, status, lock.run)
     };
-    set_status(PlayitStatusCode::Connecting, None, None);
-
-    let (stop_tx, stop_rx) = watch::channel(false);
-    let (stopped_tx, stopped_rx) = std::sync::mpsc::channel();
+    event_log::start();
+    LAST_POLL_OK.store(-1, Ordering::Relaxed);
+    if config.lazy {
+        set_status(PlayitStatusCode::Idle, None, None);
+    } else {
+        set_status(PlayitStatusCode::Connecting, None, None);
+    }
 
+    let (activate_tx, mut activate_rx) = watch::channel(!config.lazy);
     {
         let mut lock = state().lock().expect("state lock poisoned");
-        lock.stop_tx = Some(stop_tx);
-        lock.stopped_rx = Some(stopped_rx);
+        if lock.run == run && !lock.stopping() {
+            lock.activate_tx = Some(activate_tx);
+        }
     }
 
-    std::thread::spawn(move || {
-        let runtime = match tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .worker_threads(2)
-            .build()
-        {
+    callbacks::start_pending(true);
+    callbacks::ready_pending();
+    let exit_rx = stop_rx.clone();
+    let spawned = std::thread::Builder::new().name("playit-agent".to_string()).spawn(move || {
+        let runtime = match config.runtime_builder().build() {
             Ok(rt) => rt,
             Err(error) => {
-                let mut status_lock = status.lock().expect("status lock poisoned");
-                status_lock.code = PlayitStatusCode::Error;
-                status_lock.last_error =
-                    cstring_sanitize(format!("failed to create runtime: {}", error)).ok();
+                set_status_error(
+                    &status,
+                    PlayitStatusCode::Error,
+                    format!("failed to create runtime: {}", error),
+                );
+                state().lock().expect("state lock poisoned").end_run(run);
+                finish_start(&status);
                 let _ = stopped_tx.send(());
                 return;
             }
         };
 
+        let agent_status = status.clone();
         runtime.block_on(async move {
-            if let Err(error) = run_agent(config, status.clone(), stop_rx).await {
-                let mut status_lock = status.lock().expect("status lock poisoned");
-                status_lock.code = PlayitStatusCode::Error;
-                status_lock.last_error = cstring_sanitize(error).ok();
+            tokio::spawn(liveness_ticker(stop_rx.clone()));
+            tokio::spawn(config_watch::run(stop_rx.clone()));
+
+            /* no network until activated, a stop while idle ends here */
+            let activated = until_stopped(&mut stop_rx, activate_rx.wait_for(|active| *active))
+                .await
+                .is_some_and(|res| res.is_ok());
+            if !activated {
+                return;
+            }
+
+            if let Err(error) = schedule::run(config, agent_status.clone(), stop_rx).await {
+                set_status_error(&agent_status, error.code, error.message);
             }
         });
 
-        {
-            let mut lock = state().lock().expect("state lock poisoned");
-            lock.running = false;
-            lock.keep_running = None;
-            lock.stop_tx = None;
-            lock.stopped_rx = None;
+        if !state().lock().expect("state lock poisoned").end_run(run) {
+            /* stopped and started again while this thread was still finishing */
+            let _ = stopped_tx.send(());
+            return;
         }
 
+        /* ending without a stop is where a quiet restart settled, even on a hidden Stopped */
+        if !*exit_rx.borrow() {
+            end_quiet_restart();
+        }
+        finish_start(&status);
         let _ = stopped_tx.send(());
     });
 
+    if let Err(error) = spawned {
+        tracing::error!(%error, "failed to spawn agent thread");
+        callbacks::start_pending(false);
+        state().lock().expect("state lock poisoned").end_run(run);
+        set_status(PlayitStatusCode::Stopped, None, None);
+        return -3;
+    }
+
     0
 }
 
+/* playit_start_with modes */
+const START_STRICT: i32 = 0;
+const START_IDEMPOTENT: i32 = 1;
+const START_RESTART_ON_CHANGE: i32 = 2;
+
+/// `playit_start` with a choice of what happens when the agent is already running:
+/// strict returns -2, idempotent returns 0 if it runs the loaded config (-2 if not) and
+/// restart on change restarts it when the loaded config differs. -4 for an unknown mode,
+/// -6 if the restart failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_start_with(mode: i32) -> i32 {
+    if !matches!(mode, START_STRICT | START_IDEMPOTENT | START_RESTART_ON_CHANGE) {
+        return -4;
+    }
+
+    let changed = {
+        let lock = state().lock().expect("state lock poisoned");
+        if !lock.running || mode == START_STRICT || lock.stopping() {
+            None
+        } else {
+            let loaded = lock.start_config().map(|config| fingerprint::fingerprint(&config));
+            Some(loaded != lock.running_fingerprint)
+        }
+    };
+
+    match changed {
+        None => playit_start(),
+        Some(false) => 0,
+        Some(true) if mode == START_IDEMPOTENT => -2,
+        Some(true) => {
+            tracing::info!("config changed since the agent started, restarting");
+            match reconfigure::playit_restart(false) {
+                0 => 0,
+                /* stopped by the host in the meantime */
+                -1 => playit_start(),
+                _ => -6,
+            }
+        }
+    }
+}
+
+/// The agent thread is done, report the start as failed if it never connected
+fn finish_start(status: &Arc<Mutex<StatusSnapshot>>) {
+    let (code, error) = {
+        let lock = status.lock().expect("status lock poisoned");
+        (lock.code, lock.last_error.clone())
+    };
+    let code = if code.is_error() || code == PlayitStatusCode::GaveUp {
+        code
+    } else {
+        PlayitStatusCode::Stopped
+    };
+    callbacks::start_finished(code, error.as_ref());
+}
+
+/// 0 once the agent thread confirmed it stopped (or nothing was running), 1 if
+/// `stop_wait_ms` expired first and the thread may still be cleaning up.
 #[unsafe(no_mangle)]
 pub extern "C" fn playit_stop() -> i32 {
-    let (stop_tx, stopped_rx, keep_running) = {
+    /* a stop the host asked for is never hidden */
+    QUIET_RESTART.store(false, Ordering::Release);
+    stop_agent()
+}
+
+fn stop_agent() -> i32 {
+    let (stopped_rx, stop_wait, run) = {
         let mut lock = state().lock().expect("state lock poisoned");
         if !lock.running {
             return 0;
         }
-        lock.running = false;
-        (
-            lock.stop_tx.take(),
-            lock.stopped_rx.take(),
-            lock.keep_running.take(),
-        )
+        /* running stays true until the thread exits, see GlobalState::end_run */
+        NEXT_POLL_AT.store(0, Ordering::Release);
+        let stop_wait = lock.config
+            .as_ref()
+            .map(|config| config.stop_wait())
+            .unwrap_or(Duration::from_secs(2));
+        /* under the lock, so the run can't install new handles after this */
+        if let Some(keep_running) = &lock.keep_running {
+            keep_running.store(false, Ordering::SeqCst);
+        }
+        if let Some(stop_tx) = &lock.stop_tx {
+            let _ = stop_tx.send(true);
+        }
+        let stopped_rx = lock.stopped_rx.take();
+        lock.clear_run_state();
+        (stopped_rx, stop_wait, lock.run)
     };
 
-    if let Some(keep_running) = keep_running {
-        keep_running.store(false, Ordering::SeqCst);
+    /* None if another stop is already waiting for this thread */
+    let mut result = 1;
+    if let Some(stopped_rx) = stopped_rx {
+        match stopped_rx.recv_timeout(stop_wait) {
+            Err(RecvTimeoutError::Timeout) => {
+                tracing::warn!(?stop_wait, "agent thread did not confirm stop in time");
+                /* so the next playit_stop waits for the same thread again */
+                let mut lock = state().lock().expect("state lock poisoned");
+                if lock.run == run && lock.running {
+                    lock.stopped_rx = Some(stopped_rx);
+                }
+            }
+            _ => result = 0,
+        }
     }
 
-    if let Some(stop_tx) = stop_tx {
-        let _ = stop_tx.send(true);
+    set_status(PlayitStatusCode::Stopped, None, None);
+    result
+}
+
+/// Starts connecting an agent that was started with `lazy`. Returns 0 once connecting
+/// (also if it already was), -1 if the agent isn't running.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_activate() -> i32 {
+    {
+        let lock = state().lock().expect("state lock poisoned");
+        match &lock.activate_tx {
+            Some(activate_tx) if lock.running => {
+                if *activate_tx.borrow() {
+                    return 0;
+                }
+            }
+            _ => return -1,
+        }
     }
 
-    if let Some(stopped_rx) = stopped_rx {
-        let _ = stopped_rx.recv_timeout(Duration::from_secs(2));
+    /* status first so Connected from the agent can't be overwritten */
+    set_status(PlayitStatusCode::Connecting, None, None);
+
+    let activated = {
+        let lock = state().lock().expect("state lock poisoned");
+        let activate_tx = lock.activate_tx.as_ref().filter(|_| lock.running);
+        if let Some(activate_tx) = activate_tx {
+            NEXT_POLL_AT.store(clock().now_ms(), Ordering::Release);
+            activate_tx.send_replace(true);
+        }
+        activate_tx.is_some()
+    };
+    if !activated {
+        /* stopped in between, the stop may already have set Stopped before our Connecting */
+        set_status(PlayitStatusCode::Stopped, None, None);
+        return -1;
     }
+    0
+}
 
-    set_status(PlayitStatusCode::Stopped, None, None);
+/// Re-establishes the tunnel session and polls rundata right away. For the host to call
+/// when the app becomes active again after being suspended. -1 if not running.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_notify_resumed() -> i32 {
+    if !state().lock().expect("state lock poisoned").running {
+        return -1;
+    }
+
+    tracing::info!("host resumed, reconnecting");
+    force_reconnect("resumed");
+    RESUMED.notify_one();
     0
 }
 
+/// `reason` goes into the event log
+fn force_reconnect(reason: &str) {
+    event_log::record("reconnect", serde_json::json!({ "reason": reason }));
+    let lock = state().lock().expect("state lock poisoned");
+    if let Some(force_reconnect) = &lock.force_reconnect {
+        force_reconnect.store(true, Ordering::Release);
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn playit_get_status() -> PlayitStatus {
-    let state_lock = state().lock().expect("state lock poisoned");
+    current_status(&state().lock().expect("state lock poisoned"))
+}
+
+fn current_status(state_lock: &GlobalState) -> PlayitStatus {
     let status = state_lock
         .status
         .lock()
         .expect("status lock poisoned");
 
     PlayitStatus {
+        struct_version: STRUCT_ABI_VERSION,
+        struct_size: size_of::<PlayitStatus>() as u32,
         code: status.code as i32,
         last_address: status
             .last_address
@@ -368,93 +1606,371 @@ pub extern "C" fn playit_get_status() -> PlayitStatus {
             .as_ref()
             .map(|v| v.as_ptr())
             .unwrap_or(std::ptr::null()),
+        reconnect_attempts: state_lock
+            .reconnect_attempts
+            .as_ref()
+            .map(|v| v.load(Ordering::SeqCst))
+            .unwrap_or(0),
+    }
+}
+
+/// Estimated ms until the next rundata poll, 0 while a poll is in flight or -1 if the
+/// agent isn't running.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_get_next_poll_ms() -> i64 {
+    next_poll_ms(NEXT_POLL_AT.load(Ordering::Acquire), clock())
+}
+
+/// Whether the latest rundata poll (or the initial load) succeeded: 1 yes, 0 no, -1 if
+/// none has completed since `playit_start`. Doesn't affect the status.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_last_poll_ok() -> i32 {
+    LAST_POLL_OK.load(Ordering::Relaxed)
+}
+
+fn next_poll_ms(next_poll_at: u64, clock: &dyn Clock) -> i64 {
+    if next_poll_at == 0 {
+        return -1;
+    }
+
+    next_poll_at.saturating_sub(clock.now_ms()) as i64
+}
+
+/// Compare with the version the host was built against before reading struct fields
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_status_abi_version() -> u32 {
+    STRUCT_ABI_VERSION
+}
+
+async fn liveness_ticker(mut stop_rx: watch::Receiver<bool>) {
+    loop {
+        LIVENESS.fetch_add(1, Ordering::Relaxed);
+        let tick = tokio::time::sleep(LIVENESS_INTERVAL);
+        if until_stopped(&mut stop_rx, tick).await.is_none() {
+            return;
+        }
+    }
+}
+
+/// Resolves once the core clears `keep_running` on its own. `playit_stop` clears it as
+/// well, that case never resolves here and is left to `until_stopped`.
+async fn core_stopped(keep_running: Arc<AtomicBool>, stop_rx: watch::Receiver<bool>) {
+    loop {
+        tokio::time::sleep(KEEP_RUNNING_CHECK).await;
+        if !keep_running.load(Ordering::SeqCst) && !*stop_rx.borrow() {
+            return;
+        }
+    }
+}
+
+/// Counter the agent runtime bumps about once a second while running. A watchdog that
+/// sees it stall while `playit_is_running` is true knows the runtime is stuck.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_liveness() -> u64 {
+    LIVENESS.load(Ordering::Relaxed)
+}
+
+/// Time since the status last became Connected, -1 while it isn't
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_get_connected_duration_ms() -> i64 {
+    connected_duration_ms(CONNECTED_SINCE.load(Ordering::Acquire), clock())
+}
+
+fn connected_duration_ms(since: u64, clock: &dyn Clock) -> i64 {
+    match since {
+        0 => -1,
+        since => clock.now_ms().saturating_sub(since) as i64,
     }
 }
 
+/// True from `playit_start` until the agent thread has exited, whatever the status says
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_is_running() -> bool {
+    state().lock().expect("state lock poisoned").running
+}
+
+/// Whether some caller has claimed the process wide agent: a config is loaded or it is
+/// running. For modules sharing the library to check before `playit_init`/`playit_start`.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_instance_active() -> bool {
+    let lock = state().lock().expect("state lock poisoned");
+    lock.running || lock.config.is_some()
+}
+
+/// Only the status code, without locking or allocating. Cheap enough to call every frame.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_get_status_code() -> i32 {
+    STATUS_CODE.load(Ordering::Acquire)
+}
+
+/// Dirty check for the full status, see `STATUS_REVISION`
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_get_status_revision() -> u64 {
+    STATUS_REVISION.load(Ordering::Acquire)
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn playit_get_status_out(out_status: *mut PlayitStatus) {
     if out_status.is_null() {
         return;
     }
-    unsafe {
-        *out_status = playit_get_status();
-    }
+    unsafe { write_versioned(out_status, playit_get_status()) }
 }
 
 async fn run_agent(
     config: FfiConfig,
     status: Arc<Mutex<StatusSnapshot>>,
     mut stop_rx: watch::Receiver<bool>,
-) -> Result<(), String> {
+) -> Result<(), RunError> {
     if let Some(ver) = config.agent_version.as_deref() {
         version::help_register_version(ver, "308943e8-faef-4835-a2ba-270351f72aa3");
     }
 
-    let api_url = config
-        .api_url
-        .unwrap_or_else(|| "https://api.playit.gg".to_string());
-    let poll_interval = Duration::from_millis(config.poll_interval_ms.unwrap_or(3_000));
+    POLL_INTERVAL_MS.store(config.poll_interval_ms(), Ordering::Relaxed);
 
-    let api = PlayitApi::create(api_url.clone(), Some(config.secret_key.clone()));
+    let api = config.create_api();
     let lookup = Arc::new(OriginLookup::default());
+    origin_override::apply_all(&lookup);
+    origin_map::restore(&lookup).await;
+    if !install_run_handles(&stop_rx, |lock| lock.lookup = Some(lookup.clone())) {
+        return Ok(());
+    }
 
-    let initial_data = api
-        .v1_agents_rundata()
-        .await
-        .map_err(|e| format!("failed to load run data: {}", e))?;
+    /* hosts started together (ex. by the same push) shouldn't hit the API together */
+    let startup_jitter = random::up_to(config.startup_jitter());
+    if !startup_jitter.is_zero() {
+        tracing::debug!(?startup_jitter, "delaying the initial rundata load");
+        let wait = tokio::time::sleep(startup_jitter);
+        if until_stopped(&mut stop_rx, wait).await.is_none() {
+            return Ok(());
+        }
+    }
+
+    let initial_data = loop {
+        let Some(result) = until_stopped(&mut stop_rx, load_rundata(&api)).await else {
+            return Ok(());
+        };
+
+        let error = match result {
+            Ok(data) => break data,
+            Err(error) => error,
+        };
+        LAST_POLL_OK.store(0, Ordering::Relaxed);
+        let Some(delay) = rate_limit_delay(&status, &error) else {
+            return Err(RunError {
+                code: PlayitStatusCode::from_api_error(&error),
+                message: format!("failed to load run data: {}", error),
+            });
+        };
+        if until_stopped(&mut stop_rx, tokio::time::sleep(delay)).await.is_none() {
+            return Ok(());
+        }
+    };
+
+    LAST_POLL_OK.store(1, Ordering::Relaxed);
 
     if initial_data.tunnels.is_empty() && initial_data.pending.is_empty() {
-        if let Err(error) = ensure_default_tunnel(&api, &initial_data).await {
-            tracing::error!(?error, "failed to create default tunnel");
-        } else {
-            tracing::info!("created default tunnel");
+        /* polling again won't create one, setup has to happen in the app or on the website */
+        if config.require_tunnels {
+            return Err(RunError {
+                code: PlayitStatusCode::NoTunnels,
+                message: "the account has no tunnels".to_string(),
+            });
         }
+        match until_stopped(&mut stop_rx, ensure_default_tunnel(&api, &initial_data)).await {
+            None => return Ok(()),
+            Some(Err(error)) => tracing::error!(?error, "failed to create default tunnel"),
+            Some(Ok(())) => tracing::info!("created default tunnel"),
+        }
+    }
+    if config.account_info {
+        account::remember(&config.secret_key, &initial_data);
     }
+    let initial_data = scope_tunnels(initial_data, &config.allowed_tunnel_ids);
     lookup.update_from_run_data(&initial_data).await;
+    origin_map::remember(&lookup).await;
+    state().lock().expect("state lock poisoned").agent_id =
+        Some((config.secret_key.clone(), initial_data.agent_id.to_string()));
+    origin_dns::clear();
+    tokio::spawn(origin_dns::run(lookup.clone(), stop_rx.clone()));
+
+    if let Some(name) = config.agent_name.clone() {
+        let req = ReqAgentsRename {
+            agent_id: initial_data.agent_id,
+            name,
+        };
+        match until_stopped(&mut stop_rx, api.agents_rename(req)).await {
+            None => return Ok(()),
+            Some(Err(error)) => tracing::warn!(?error, "failed to set agent name"),
+            Some(Ok(())) => {}
+        }
+    }
 
-    update_status_from_rundata(&status, &initial_data);
-
-    let settings = PlayitAgentSettings {
-        udp_settings: UdpSettings::default(),
-        tcp_settings: TcpSettings::default(),
-        api_url,
-        secret_key: config.secret_key.clone(),
+    let Some(origin_error) =
+        until_stopped(&mut stop_rx, origin_probe::find_unreachable_origin(&initial_data, &lookup)).await
+    else {
+        return Ok(());
     };
 
-    let agent = PlayitAgent::new(settings, lookup.clone())
-        .await
-        .map_err(|e| format!("failed to setup agent: {:?}", e))?;
+    let mut origin_down = origin_error.is_some();
 
-    {
-        let mut state_lock = state().lock().expect("state lock poisoned");
+    let settings = agent_settings(&config);
+    let settings_acl = settings.tcp_settings.acl.clone();
+    let settings_budget = settings.tcp_settings.buffer_budget.clone();
+
+    let mut attempt = 0;
+    let agent = loop {
+        let setup = async {
+            match packet_flow::HostPacketIo::create() {
+                Some(udp_io) => {
+                    tracing::info!("using host packet flow for tunneled UDP");
+                    PlayitAgent::new_with_udp_io(settings.clone(), lookup.clone(), udp_io).await
+                }
+                None => PlayitAgent::new(settings.clone(), lookup.clone()).await,
+            }
+        };
+        let Some(result) = until_stopped(&mut stop_rx, setup).await else {
+            return Ok(());
+        };
+
+        let error = match result {
+            Ok(agent) => break agent,
+            Err(error) => error,
+        };
+        if !error.is_transient() || config.setup_retries.unwrap_or(3) <= attempt {
+            return Err(format!("failed to setup agent: {:?}", error).into());
+        }
+
+        let delay = random::jitter(config.setup_retry_delay(attempt));
+        attempt += 1;
+        tracing::warn!(?error, attempt, ?delay, "agent setup failed, retrying");
+        if until_stopped(&mut stop_rx, tokio::time::sleep(delay)).await.is_none() {
+            return Ok(());
+        }
+    };
+
+    let installed = install_run_handles(&stop_rx, |state_lock| {
         state_lock.keep_running = Some(agent.keep_running());
+        state_lock.reconnect_attempts = Some(agent.reconnect_attempts());
+        state_lock.observed_addr = Some(agent.observed_addr());
+        state_lock.diagnostics = Some(agent.diagnostics());
+        state_lock.acl = Some(settings_acl);
+        state_lock.buffer_budget = Some(settings_budget);
+        state_lock.force_reconnect = Some(agent.force_reconnect());
+        state_lock.stats = Some(agent.stats());
+    });
+    if !installed {
+        return Ok(());
     }
 
+    /* Connecting through setup retries, tunnels now are the tunnel state callback baseline */
+    update_status_from_rundata(&status, &initial_data, &config, false, origin_error);
+
+    tokio::spawn(throughput::run_sampler(agent.stats(), stop_rx.clone()));
+    tokio::spawn(reconnect_status::run(status.clone(), agent.diagnostics(), stop_rx.clone()));
+    if config.require_traffic_for_connected {
+        tokio::spawn(wait_for_traffic(status.clone(), agent.stats(), stop_rx.clone()));
+    }
+    if let Some(idle_ms) = config.idle_reconnect_ms.filter(|ms| 0 < *ms) {
+        tokio::spawn(idle_reconnect::run(agent.stats(), idle_ms, stop_rx.clone()));
+    }
+    let mut core_stopped = pin!(core_stopped(agent.keep_running(), stop_rx.clone()));
     tokio::spawn(agent.run());
 
+    if !config.poll_rundata() {
+        /* the agent maintains its own session, tunnel changes need a restart */
+        NEXT_POLL_AT.store(0, Ordering::Release);
+        if until_stopped(&mut stop_rx, core_stopped).await.is_some() {
+            report_core_stopped(&status);
+        }
+        return Ok(());
+    }
+
+    /* set after a 429 so the next poll waits at least as long as the server asked */
+    let mut hold_off: Option<Duration> = None;
+    /* unix ms since when the status has been Disconnected or Error */
+    let mut disconnected_since: Option<u64> = None;
+
     loop {
-        if *stop_rx.borrow() {
+        if let Some(give_up_ms) = config.disconnect_give_up_ms.filter(|ms| 0 < *ms) {
+            let code = STATUS_CODE.load(Ordering::Acquire);
+            if gave_up(code, &mut disconnected_since, clock().now_ms(), give_up_ms) {
+                tracing::warn!(give_up_ms, "disconnected for too long, giving up");
+                return Err(RunError {
+                    code: PlayitStatusCode::GaveUp,
+                    message: format!(
+                        "disconnected for over {}s, start again to retry",
+                        give_up_ms / 1_000
+                    ),
+                });
+            }
+        }
+
+        let hold_off_ms = hold_off.take().map_or(0, |v| v.as_millis() as u64);
+        let poll_interval_ms = POLL_INTERVAL_MS.load(Ordering::Relaxed).max(hold_off_ms);
+        let wake_at = clock().now_ms() + poll_interval_ms;
+        NEXT_POLL_AT.store(wake_at, Ordering::Release);
+        let poll_wait = async {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(poll_interval_ms)) => Some(false),
+                _ = RESUMED.notified() => Some(true),
+                _ = &mut core_stopped => None,
+            }
+        };
+        let Some(resumed) = until_stopped(&mut stop_rx, poll_wait).await else {
             break;
+        };
+        let Some(resumed) = resumed else {
+            report_core_stopped(&status);
+            break;
+        };
+
+        /* the sleep timer doesn't advance while suspended but wall time does */
+        let overslept_ms = clock().now_ms().saturating_sub(wake_at);
+        if !resumed && SUSPEND_GAP_MS < overslept_ms {
+            tracing::info!(overslept_ms, "poll woke late, assuming suspend and reconnecting");
+            force_reconnect("suspended");
         }
-        tokio::select! {
-            _ = stop_rx.changed() => {
-                if *stop_rx.borrow() {
-                    break;
+
+        let poll_started = Instant::now();
+        let Some(result) = until_stopped(&mut stop_rx, load_rundata(&api)).await else {
+            break;
+        };
+
+        match result {
+            Ok(data) => {
+                LAST_POLL_OK.store(1, Ordering::Relaxed);
+                if config.account_info {
+                    account::remember(&config.secret_key, &data);
                 }
+                let data = scope_tunnels(data, &config.allowed_tunnel_ids);
+                lookup.update_from_run_data(&data).await;
+                origin_map::remember(&lookup).await;
+                /* only re-probe to notice the origin coming back */
+                let origin_error = if origin_down {
+                    let probe = origin_probe::find_unreachable_origin(&data, &lookup);
+                    let Some(origin_error) = until_stopped(&mut stop_rx, probe).await else {
+                        break;
+                    };
+                    origin_down = origin_error.is_some();
+                    origin_error
+                } else {
+                    None
+                };
+                update_status_from_rundata(&status, &data, &config, true, origin_error);
+                callbacks::poll_finished(poll_started.elapsed(), None);
             }
-            _ = tokio::time::sleep(poll_interval) => {
-                match api.v1_agents_rundata().await {
-                    Ok(data) => {
-                        lookup.update_from_run_data(&data).await;
-                        update_status_from_rundata(&status, &data);
-                    }
-                    Err(error) => {
-                        let mut status_lock = status.lock().expect("status lock poisoned");
-                        status_lock.code = PlayitStatusCode::Error;
-                        status_lock.last_error =
-                            cstring_sanitize(format!("failed to poll run data: {}", error)).ok();
-                    }
+            Err(error) => {
+                POLL_ERRORS.fetch_add(1, Ordering::Relaxed);
+                LAST_POLL_OK.store(0, Ordering::Relaxed);
+                let code = PlayitStatusCode::from_api_error(&error);
+                hold_off = rate_limit_delay(&status, &error);
+                if hold_off.is_some() {
+                    callbacks::poll_finished(poll_started.elapsed(), Some(code));
+                    continue;
                 }
+                set_status_error(&status, code, format!("failed to poll run data: {}", error));
+                callbacks::poll_finished(poll_started.elapsed(), Some(code));
             }
         }
     }
@@ -462,6 +1978,124 @@ async fn run_agent(
     Ok(())
 }
 
+/// The core shut itself down, move to Stopped now rather than at the next poll
+fn report_core_stopped(status: &Arc<Mutex<StatusSnapshot>>) {
+    tracing::warn!("agent stopped by the core, start again to resume");
+    update_status(status, |lock| {
+        lock.code = PlayitStatusCode::Stopped;
+        lock.last_error = Some(cstring_sanitize("the agent stopped itself"));
+    });
+}
+
+/// Tracks how long `code` has been Disconnected without a break, true once that's
+/// `give_up_ms`. Any other code, a failed poll's Error included, starts over.
+fn gave_up(code: i32, since: &mut Option<u64>, now_ms: u64, give_up_ms: u64) -> bool {
+    if code != PlayitStatusCode::Disconnected as i32 {
+        *since = None;
+        return false;
+    }
+
+    let since = *since.get_or_insert(now_ms);
+    give_up_ms <= now_ms.saturating_sub(since)
+}
+
+/// For a 429 from the API: moves the status to RateLimited, tells the host and returns how
+/// long to wait before the next request. `None` for any other error.
+fn rate_limit_delay(
+    status: &Arc<Mutex<StatusSnapshot>>,
+    error: &ApiErrorNoFail<HttpClientError>,
+) -> Option<Duration> {
+    let ApiErrorNoFail::ClientError(HttpClientError::TooManyRequests { retry_after }) = error
+    else {
+        return None;
+    };
+
+    let delay = retry_after.unwrap_or_else(|| {
+        random::jitter(Duration::from_millis(POLL_INTERVAL_MS.load(Ordering::Relaxed)))
+    });
+    tracing::warn!(?retry_after, "rate limited by the API");
+
+    set_status_error(
+        status,
+        PlayitStatusCode::RateLimited,
+        format!("rate limited, retrying in {}s", delay.as_secs().max(1)),
+    );
+    callbacks::rate_limited(*retry_after);
+
+    Some(delay)
+}
+
+/// Only keeps the raw JSON around when the host asked for it
+async fn load_rundata(api: &PlayitApi) -> Result<AgentRunDataV1, ApiErrorNoFail<HttpClientError>> {
+    if !callbacks::raw_rundata_enabled() {
+        return api.v1_agents_rundata().await;
+    }
+
+    let (data, raw) = api.v1_agents_rundata_raw().await?;
+    callbacks::rundata_received(&raw);
+    Ok(data)
+}
+
+/// Runs `future` unless a stop is requested first, in which case it's dropped (aborting
+/// any in-flight request) and `None` is returned.
+/// Hands this run's handles to the FFI calls unless it was stopped already. Checked under
+/// the state lock, which `stop_agent` signals under, so a thread finishing after its stop
+/// can't overwrite the handles of a newer run.
+fn install_run_handles(
+    stop_rx: &watch::Receiver<bool>,
+    install: impl FnOnce(&mut GlobalState),
+) -> bool {
+    let mut lock = state().lock().expect("state lock poisoned");
+    /* a dropped sender (state was reset) counts as a stop too */
+    if *stop_rx.borrow() || stop_rx.has_changed().is_err() {
+        return false;
+    }
+    install(&mut lock);
+    true
+}
+
+async fn until_stopped<F: Future>(
+    stop_rx: &mut watch::Receiver<bool>,
+    future: F,
+) -> Option<F::Output> {
+    tokio::select! {
+        output = future => Some(output),
+        /* a dropped sender (state was reset) counts as a stop too */
+        _ = stop_rx.wait_for(|stop| *stop) => None,
+    }
+}
+
+fn agent_settings(config: &FfiConfig) -> PlayitAgentSettings {
+    let bind_address = config.bind_address();
+    let acl = SharedAcl::new(config.acl());
+    let buffer_budget = config.buffer_budget();
+
+    PlayitAgentSettings {
+        udp_settings: UdpSettings {
+            bind_address,
+            control_source_port: config.control_source_port,
+            acl: acl.clone(),
+            buffer_budget: buffer_budget.clone(),
+            ..UdpSettings::default()
+        },
+        tcp_settings: TcpSettings {
+            bind_address,
+            tcp_no_delay: config.tcp_nodelay(),
+            origin_connect_retries: config.origin_connect_retries(),
+            origin_connect_retry_delay: Duration::from_millis(config.origin_connect_retry_ms()),
+            new_client_ratelimit_burst: config.tcp_backlog(),
+            max_connections: config.max_connections,
+            connection_limit_mode: config.connection_limit_mode(),
+            acl,
+            buffer_budget,
+            ..TcpSettings::default()
+        },
+        http_settings: config.http_settings(),
+        api_url: config.api_url(),
+        secret_key: config.secret_key.clone(),
+    }
+}
+
 async fn ensure_default_tunnel(
     api: &PlayitApi,
     data: &playit_api_client::api::AgentRunDataV1,
@@ -491,20 +2125,666 @@ async fn ensure_default_tunnel(
 fn update_status_from_rundata(
     status: &Arc<Mutex<StatusSnapshot>>,
     data: &playit_api_client::api::AgentRunDataV1,
+    config: &FfiConfig,
+    report_tunnel_changes: bool,
+    origin_error: Option<String>,
 ) {
-    let address = data
+    let address = primary_address(data, config.required_tunnel_id);
+    if let Some(address) = &address {
+        let mut last_seen = LAST_SEEN_ADDRESS.lock().expect("address lock poisoned");
+        if let Some(previous) = address_changed(&mut last_seen, address) {
+            tracing::info!(%previous, %address, "tunnel address changed");
+            event_log::record(
+                "address",
+                serde_json::json!({ "previous": previous, "address": address }),
+            );
+        }
+    }
+    /* an unresolved hostname also fails the probe, report the cause */
+    let origin_error = match origin_dns::failure() {
+        Some(error) => Some((PlayitStatusCode::OriginResolveFailed, error)),
+        None => origin_error.map(|error| (PlayitStatusCode::OriginUnreachable, error)),
+    };
+    let now_ms = clock().now_ms();
+    let missing_since = match &address {
+        Some(_) => {
+            NO_ADDRESS_SINCE.store(0, Ordering::Release);
+            0
+        }
+        None => NO_ADDRESS_SINCE
+            .compare_exchange(0, now_ms, Ordering::AcqRel, Ordering::Acquire)
+            .map_or_else(|since| since, |_| now_ms),
+    };
+    let grace_ms = config.disconnect_grace_ms.unwrap_or(0);
+    let mut tunnel_changes = Vec::new();
+    let connected_code = if !config.require_traffic_for_connected || traffic_seen() {
+        PlayitStatusCode::Connected
+    } else {
+        PlayitStatusCode::Connecting
+    };
+
+    update_status(status, |status_lock| {
+        let tunnels: Vec<_> = data
+            .tunnels
+            .iter()
+            .map(tunnels::TunnelInfo::from_tunnel)
+            .collect();
+        if report_tunnel_changes {
+            tunnel_changes = tunnels::state_changes(&status_lock.tunnels, &tunnels);
+        }
+        status_lock.tunnels = tunnels;
+        status_lock.last_error = None;
+        if let Some(address) = &address {
+            /* the tunnel session reports being back, not rundata */
+            if status_lock.code != PlayitStatusCode::Reconnecting
+                || connected_code != PlayitStatusCode::Connected
+            {
+                status_lock.code = connected_code;
+            }
+            status_lock.last_address = Some(cstring_sanitize(address));
+
+            if let Some((code, error)) = origin_error {
+                status_lock.code = code;
+                status_lock.last_error = Some(cstring_sanitize(error));
+            }
+        } else if hold_connected(status_lock.code, missing_since, now_ms, grace_ms) {
+            tracing::debug!(grace_ms, "no tunnel address, still within disconnect grace");
+        } else {
+            status_lock.code = PlayitStatusCode::Disconnected;
+            status_lock.last_address = None;
+            if data.tunnels.is_empty() && !config.allowed_tunnel_ids.is_empty() {
+                status_lock.last_error = Some(cstring_sanitize(NO_ALLOWED_TUNNELS));
+            }
+        }
+    });
+
+    for change in tunnel_changes {
+        callbacks::tunnel_state_changed(&change);
+    }
+    callbacks::provisioned(address.as_deref());
+}
+
+/// Whether a Connected status is kept although rundata has had no tunnel address since
+/// `missing_since`, for `disconnect_grace_ms`
+fn hold_connected(code: PlayitStatusCode, missing_since: u64, now_ms: u64, grace_ms: u64) -> bool {
+    code == PlayitStatusCode::Connected && now_ms.saturating_sub(missing_since) < grace_ms
+}
+
+/// Stores `address` as the last seen one, returns the one it replaced if that differs
+fn address_changed(last_seen: &mut Option<String>, address: &str) -> Option<String> {
+    match last_seen.replace(address.to_string()) {
+        Some(previous) if previous != address => Some(previous),
+        _ => None,
+    }
+}
+
+/// Whether data has come back from a local origin since the agent started
+fn traffic_seen() -> bool {
+    let lock = state().lock().expect("state lock poisoned");
+    lock.stats.as_ref().is_some_and(|stats| 0 < stats.bytes_out())
+}
+
+/// For `require_traffic_for_connected`: flips Connecting to Connected as soon as the
+/// first bytes flow instead of waiting for the next poll.
+async fn wait_for_traffic(
+    status: Arc<Mutex<StatusSnapshot>>,
+    stats: AgentStats,
+    mut stop_rx: watch::Receiver<bool>,
+) {
+    while stats.bytes_out() == 0 {
+        let wait = tokio::time::sleep(Duration::from_millis(250));
+        if until_stopped(&mut stop_rx, wait).await.is_none() {
+            return;
+        }
+    }
+
+    tracing::info!("first traffic through the tunnel");
+    update_status(&status, |lock| {
+        if lock.code == PlayitStatusCode::Connecting && lock.last_address.is_some() {
+            lock.code = PlayitStatusCode::Connected;
+        }
+    });
+}
+
+const NO_ALLOWED_TUNNELS: &str = "none of the account's tunnels are in allowed_tunnel_ids";
+
+/// Rundata with only the tunnels in `allowed`, all of them if it is empty. Everything
+/// after the load (origins, status, tunnel list) only sees the kept ones.
+fn scope_tunnels(mut data: AgentRunDataV1, allowed: &[u64]) -> AgentRunDataV1 {
+    if !allowed.is_empty() {
+        data.tunnels.retain(|tunnel| allowed.contains(&tunnel.internal_id));
+    }
+    data
+}
+
+/// Address of the enabled tunnel with the highest priority (the first one on a tie), or
+/// only of `required_tunnel_id` when set so other tunnels being up doesn't count as
+/// connected.
+fn primary_address(
+    data: &playit_api_client::api::AgentRunDataV1,
+    required_tunnel_id: Option<u64>,
+) -> Option<String> {
+    let enabled = data
         .tunnels
         .iter()
-        .find(|t| t.disabled_reason.is_none())
-        .map(|t| t.display_address.clone());
+        .filter(|t| required_tunnel_id.is_none_or(|id| t.internal_id == id))
+        .filter(|t| t.disabled_reason.is_none());
+    tunnel_priority::pick(enabled, |t| t.internal_id).map(|t| t.display_address.clone())
+}
 
-    let mut status_lock = status.lock().expect("status lock poisoned");
-    if let Some(address) = address {
-        status_lock.code = PlayitStatusCode::Connected;
-        status_lock.last_address = cstring_sanitize(address).ok();
-    } else {
-        status_lock.code = PlayitStatusCode::Disconnected;
-        status_lock.last_address = None;
+#[cfg(test)]
+mod test {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_void};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::time::Duration;
+
+    use playit_agent_core::utils::clock::{Clock, ManualClock};
+
+    use super::{
+        GlobalState, LogCallbackState, MAX_PENDING_LOGS, PlayitStatusCode, address_changed,
+        agent_settings, auto_worker_threads, connected_duration_ms, core_stopped,
+        cstring_sanitize, gave_up, hold_connected, next_poll_ms, parse_config_buf,
+        parse_config_json, playit_remove_log_callback, playit_start_with, quiet_restart_hides,
+        scope_tunnels, truncate_log,
+    };
+
+    fn parse(json: &str) -> Result<super::FfiConfig, i32> {
+        let json = CString::new(json).unwrap();
+        unsafe { parse_config_json(json.as_ptr()) }
+    }
+
+    #[test]
+    fn logs_before_callback_are_flushed() {
+        static RECEIVED: Mutex<Vec<(i32, String)>> = Mutex::new(Vec::new());
+        extern "C" fn record(level: i32, message: *const c_char, _: *mut c_void) {
+            let message = unsafe { CStr::from_ptr(message) };
+            let entry = (level, message.to_string_lossy().into_owned());
+            RECEIVED.lock().unwrap().push(entry);
+        }
+
+        let mut state = LogCallbackState::new();
+        for i in 0..MAX_PENDING_LOGS + 5 {
+            state.deliver(1, "test", &format!("early {}", i), None);
+        }
+        assert_eq!(state.pending.len(), MAX_PENDING_LOGS);
+
+        state.add(record, std::ptr::null_mut());
+        state.flush_pending();
+        state.deliver(3, "test", "late", None);
+        assert!(state.pending.is_empty());
+
+        let received = RECEIVED.lock().unwrap();
+        assert_eq!(received.len(), MAX_PENDING_LOGS + 1);
+        /* oldest lines are the ones dropped */
+        assert_eq!(received[0], (1, "early 5".to_string()));
+        assert_eq!(received.last().unwrap(), &(3, "late".to_string()));
+    }
+
+    #[test]
+    fn give_up_after_disconnected() {
+        let disconnected = PlayitStatusCode::Disconnected as i32;
+        let mut since = None;
+
+        assert!(!gave_up(disconnected, &mut since, 1_000, 5_000));
+        assert!(!gave_up(disconnected, &mut since, 5_999, 5_000));
+        assert!(gave_up(disconnected, &mut since, 6_000, 5_000));
+
+        /* connecting again resets the timer */
+        assert!(!gave_up(PlayitStatusCode::Connected as i32, &mut since, 7_000, 5_000));
+        assert_eq!(since, None);
+        assert!(!gave_up(disconnected, &mut since, 8_000, 5_000));
+        assert!(!gave_up(disconnected, &mut since, 12_999, 5_000));
+    }
+
+    #[test]
+    fn poll_errors_never_give_up() {
+        let (disconnected, error) =
+            (PlayitStatusCode::Disconnected as i32, PlayitStatusCode::Error as i32);
+        let mut since = None;
+
+        /* a run of failed polls doesn't start the timer */
+        assert!(!gave_up(error, &mut since, 1_000, 5_000));
+        assert!(!gave_up(error, &mut since, 9_000, 5_000));
+        assert_eq!(since, None);
+
+        /* flapping between Disconnected and Error starts over on every Error */
+        assert!(!gave_up(disconnected, &mut since, 10_000, 5_000));
+        assert!(!gave_up(error, &mut since, 14_000, 5_000));
+        assert!(!gave_up(disconnected, &mut since, 16_000, 5_000));
+        assert_eq!(since, Some(16_000));
+        assert!(gave_up(disconnected, &mut since, 21_000, 5_000));
+    }
+
+    #[test]
+    fn long_logs_are_truncated() {
+        assert_eq!(truncate_log("short", 4_096), "short");
+        assert_eq!(truncate_log("abcdefghij", 0), "abcdefghij");
+        assert_eq!(truncate_log("abcdefghij", 8), "abcde…");
+        assert_eq!(truncate_log("abcdefghij", 8).len(), 8);
+
+        /* "é" is two bytes, the cut must not split it */
+        let cut = truncate_log("aéééé", 6);
+        assert_eq!(cut, "aé…");
+        assert!(cut.len() <= 6);
+        assert_eq!(truncate_log("éé", 1), "…");
+    }
+
+    #[test]
+    fn log_callbacks_fan_out() {
+        static RECEIVED: Mutex<Vec<(usize, String)>> = Mutex::new(Vec::new());
+        static FIRST_HANDLE: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn record(_: i32, message: *const c_char, user_data: *mut c_void) {
+            let message = unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned();
+            RECEIVED.lock().unwrap().push((user_data as usize, message));
+        }
+        extern "C" fn remove_first(_: i32, _: *const c_char, _: *mut c_void) {
+            playit_remove_log_callback(FIRST_HANDLE.load(Ordering::SeqCst));
+        }
+
+        let mut state = LogCallbackState::new();
+        let first = state.add(record, std::ptr::without_provenance_mut(1));
+        state.add(record, std::ptr::without_provenance_mut(2));
+        state.deliver(1, "test", "both", None);
+
+        /* removing from inside a callback takes effect after the current line */
+        FIRST_HANDLE.store(first, Ordering::SeqCst);
+        state.callbacks.insert(0, (99, remove_first, std::ptr::null_mut()));
+        state.deliver(1, "test", "removed", None);
+        state.deliver(1, "test", "second only", None);
+
+        let received = RECEIVED.lock().unwrap();
+        assert_eq!(
+            *received,
+            vec![
+                (1, "both".to_string()),
+                (2, "both".to_string()),
+                (2, "removed".to_string()),
+                (2, "second only".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn log_event_carries_category() {
+        static RECEIVED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+        extern "C" fn record(
+            _: i32,
+            category: *const c_char,
+            message: *const c_char,
+            _: *mut c_void,
+        ) {
+            let category = unsafe { CStr::from_ptr(category) }.to_string_lossy().into_owned();
+            let message = unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned();
+            RECEIVED.lock().unwrap().push((category, message));
+        }
+
+        let mut state = LogCallbackState::new();
+        state.deliver(1, "playit_agent_core::network", "early", None);
+        state.event_callback = Some(record);
+        state.flush_pending();
+        state.deliver(2, "playit_agent", "late", None);
+
+        let received = RECEIVED.lock().unwrap();
+        assert_eq!(
+            *received,
+            vec![
+                ("playit_agent_core::network".to_string(), "early".to_string()),
+                ("playit_agent".to_string(), "late".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_log_gets_structured_line() {
+        static RECEIVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        extern "C" fn record(_: i32, line: *const c_char, _: *mut c_void) {
+            let line = unsafe { CStr::from_ptr(line) }.to_string_lossy().into_owned();
+            RECEIVED.lock().unwrap().push(line);
+        }
+
+        let mut state = LogCallbackState::new();
+        assert!(state.wants_json());
+        state.deliver(1, "test", "early id=1", Some(r#"{"message":"early"}"#));
+        state.json_callback = Some(record);
+        state.flush_pending();
+        state.deliver(1, "test", "plain only", None);
+
+        assert_eq!(*RECEIVED.lock().unwrap(), [r#"{"message":"early"}"#]);
+    }
+
+    #[test]
+    fn structs_carry_version_header() {
+        let status = super::current_status(&super::GlobalState::new());
+        assert_eq!(status.code, super::PlayitStatusCode::Stopped as i32);
+        assert_eq!(status.struct_version, super::playit_status_abi_version());
+        assert_eq!(status.struct_size as usize, size_of::<super::PlayitStatus>());
+
+        let stats = crate::stats::PlayitStats::default();
+        assert_eq!(stats.struct_version, super::STRUCT_ABI_VERSION);
+        assert_eq!(stats.struct_size as usize, size_of::<crate::stats::PlayitStats>());
+        assert_eq!(stats.bytes_in, 0);
+    }
+
+    #[test]
+    fn versioned_writes_stop_at_host_size() {
+        let value = crate::stats::PlayitStats {
+            bytes_in: 1,
+            bytes_out: 2,
+            ..Default::default()
+        };
+
+        /* an older host whose struct ends after bytes_in */
+        let mut host = [u64::MAX; 8];
+        let out = host.as_mut_ptr().cast::<crate::stats::PlayitStats>();
+        host[0] = 16 << 32;
+        unsafe { super::write_versioned(out, value) };
+        assert_eq!(host[0], (16 << 32) | u64::from(super::STRUCT_ABI_VERSION));
+        assert_eq!(host[1], 1);
+        assert!(host[2..].iter().all(|word| *word == u64::MAX));
+
+        /* unset size: only the header, which reports the size filled */
+        host = [u64::MAX; 8];
+        host[0] = 0;
+        unsafe { super::write_versioned(out, value) };
+        assert_eq!(host[0], (8 << 32) | u64::from(super::STRUCT_ABI_VERSION));
+        assert_eq!(host[1], u64::MAX);
+
+        /* a newer host's bigger struct gets everything this library has */
+        host[0] = 64 << 32;
+        unsafe { super::write_versioned(out, value) };
+        let full = size_of::<crate::stats::PlayitStats>() as u64;
+        assert_eq!(host[0] >> 32, full);
+        assert!(host[full as usize / 8..].iter().all(|word| *word == u64::MAX));
+    }
+
+    #[test]
+    fn sanitize_never_drops_strings() {
+        assert_eq!(cstring_sanitize("tunnel.ply.gg:1234").to_str(), Ok("tunnel.ply.gg:1234"));
+        assert_eq!(cstring_sanitize("a\0b").to_str(), Ok("ab"));
+        assert_eq!(
+            cstring_sanitize([b'o', b'k', 0xff, 0, b'!']).to_str(),
+            Ok("ok\u{fffd}!")
+        );
+        assert_eq!(cstring_sanitize("").to_str(), Ok(""));
+    }
+
+    #[test]
+    fn json_degenerate_inputs() {
+        assert_eq!(unsafe { parse_config_json(std::ptr::null()) }.err(), Some(-1));
+
+        let invalid_utf8 = [b'{', 0xff, b'}', 0];
+        let result = unsafe { parse_config_json(invalid_utf8.as_ptr() as *const _) };
+        assert_eq!(result.err(), Some(-2));
+
+        assert_eq!(parse("").err(), Some(-3));
+        assert_eq!(parse("null").err(), Some(-3));
+        assert_eq!(parse("[]").err(), Some(-3));
+        assert_eq!(parse("{}").err(), Some(-3));
+        assert_eq!(parse(r#"{"secret_key": ""}"#).err(), Some(-3));
+        assert_eq!(parse(r#"{"secret_key": "  "}"#).err(), Some(-3));
+
+        /* explicit length: no terminator, nothing past len is read */
+        let json = br#"{"secret_key": "abc"}trailing"#;
+        let config = unsafe { parse_config_buf(json.as_ptr(), json.len() - 8) }.unwrap();
+        assert_eq!(config.secret_key, "abc");
+        assert_eq!(unsafe { parse_config_buf(std::ptr::null(), 4) }.err(), Some(-1));
+        assert_eq!(unsafe { parse_config_buf(std::ptr::null(), 0) }.err(), Some(-3));
+        assert_eq!(unsafe { parse_config_buf(invalid_utf8.as_ptr(), 3) }.err(), Some(-2));
+        let nul = b"{\"secret_key\": \"a\0b\"}";
+        assert_eq!(unsafe { parse_config_buf(nul.as_ptr(), nul.len()) }.err(), Some(-3));
+        assert_eq!(parse(r#"{"secret_key": 5}"#).err(), Some(-3));
+        assert_eq!(
+            parse(r#"{"secret_key": "abc", "poll_interval_ms": "soon"}"#).err(),
+            Some(-3)
+        );
+        assert_eq!(
+            parse(r#"{"secret_key": "abc", "bind_address": "wifi"}"#).err(),
+            Some(-4)
+        );
+        assert_eq!(
+            parse(r#"{"secret_key": "abc", "control_source_port": 0}"#).err(),
+            Some(-3)
+        );
+        assert_eq!(parse(r#"{"secret_key": "abc", "tcp_backlog": 0}"#).err(), Some(-3));
+        let schedule = r#"{"secret_key": "abc", "schedule": [{"start": "18:00", "end": "6pm"}]}"#;
+        assert_eq!(parse(schedule).err(), Some(-3));
+        assert_eq!(
+            parse(r#"{"secret_key": "abc", "schedule_utc_offset_minutes": 900}"#).err(),
+            Some(-3)
+        );
+        assert_eq!(
+            parse(r#"{"secret_key": "abc", "control_source_port": 70000}"#).err(),
+            Some(-3)
+        );
+    }
+
+    #[test]
+    fn json_valid_config() {
+        let config = parse(r#"{"secret_key": "abc", "bind_address": "10.0.0.2"}"#).unwrap();
+        assert_eq!(config.secret_key, "abc");
+        assert_eq!(config.bind_address(), Some("10.0.0.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn runtime_drivers_validated() {
+        assert_eq!(parse(r#"{"secret_key": "abc", "runtime_drivers": ["io"]}"#).err(), Some(-3));
+        assert_eq!(
+            parse(r#"{"secret_key": "abc", "runtime_drivers": ["io", "time", "net"]}"#).err(),
+            Some(-3)
+        );
+
+        let config = parse(r#"{"secret_key": "abc", "runtime_drivers": ["time", "io"]}"#).unwrap();
+        let runtime = config.runtime_builder().build().unwrap();
+        runtime.block_on(async { tokio::time::sleep(Duration::from_millis(1)).await });
+    }
+
+    #[test]
+    fn allowed_tunnels_only() {
+        let tunnel = |id: u64| {
+            serde_json::json!({
+                "id": format!("00000000-0000-0000-0000-{:012}", id),
+                "internal_id": id,
+                "name": "",
+                "display_address": format!("t{}.playit.gg", id),
+                "port_type": "tcp",
+                "port_count": 1,
+                "tunnel_type": null,
+                "tunnel_type_display": "",
+                "agent_config": {"fields": []},
+                "disabled_reason": null
+            })
+        };
+        let data = serde_json::json!({
+            "agent_id": "00000000-0000-0000-0000-000000000001",
+            "tunnels": [tunnel(1), tunnel(2), tunnel(3)],
+            "pending": [],
+            "notices": [],
+            "permissions": {
+                "is_self_managed": false,
+                "has_premium": false,
+                "account_status": "verified"
+            }
+        });
+        let data: super::AgentRunDataV1 = serde_json::from_value(data).unwrap();
+        let ids = |allowed: &[u64]| -> Vec<u64> {
+            let scoped = scope_tunnels(data.clone(), allowed);
+            scoped.tunnels.iter().map(|t| t.internal_id).collect()
+        };
+
+        assert_eq!(ids(&[]), [1, 2, 3]);
+        assert_eq!(ids(&[3, 1]), [1, 3]);
+        assert!(ids(&[7]).is_empty());
+    }
+
+    #[test]
+    fn buffer_memory_limit() {
+        assert_eq!(parse(r#"{"secret_key": "abc", "max_buffer_memory_kb": 64}"#).err(), Some(-3));
+
+        let config = parse(r#"{"secret_key": "abc", "max_buffer_memory_kb": 2048}"#).unwrap();
+        let settings = agent_settings(&config);
+        assert_eq!(settings.tcp_settings.buffer_budget.limit(), Some(2048 * 1024));
+
+        /* one budget for both paths */
+        let _lease = settings.udp_settings.buffer_budget.try_acquire(4096).unwrap();
+        assert_eq!(settings.tcp_settings.buffer_budget.in_use(), 4096);
+        let unlimited = parse(r#"{"secret_key": "abc"}"#).unwrap();
+        assert_eq!(agent_settings(&unlimited).tcp_settings.buffer_budget.limit(), None);
+    }
+
+    #[test]
+    fn tcp_backlog_sets_new_client_burst() {
+        let config = parse(r#"{"secret_key": "abc", "tcp_backlog": 256}"#).unwrap();
+        assert_eq!(agent_settings(&config).tcp_settings.new_client_ratelimit_burst, 256);
+        let config = parse(r#"{"secret_key": "abc"}"#).unwrap();
+        assert_eq!(agent_settings(&config).tcp_settings.new_client_ratelimit_burst, 32);
+    }
+
+    #[test]
+    fn worker_threads_auto() {
+        assert_eq!(auto_worker_threads(8, None), 4);
+        assert_eq!(auto_worker_threads(8, Some(32)), 2);
+        assert_eq!(auto_worker_threads(8, Some(4)), 1);
+        assert_eq!(auto_worker_threads(1, Some(1_000)), 1);
+        assert_eq!(auto_worker_threads(0, None), 1);
+
+        let config = parse(r#"{"secret_key": "abc", "worker_threads": "auto"}"#).unwrap();
+        assert!((1..=4).contains(&config.worker_threads()));
+        let config = parse(r#"{"secret_key": "abc", "worker_threads": 3}"#).unwrap();
+        assert_eq!(config.worker_threads(), 3);
+        assert_eq!(parse(r#"{"secret_key": "abc", "worker_threads": "max"}"#).err(), Some(-3));
+        assert_eq!(parse(r#"{"secret_key": "abc", "worker_threads": 0}"#).err(), Some(-3));
+    }
+
+    #[test]
+    fn setup_retry_backoff() {
+        let config = parse(r#"{"secret_key": "abc", "setup_retry_ms": 500}"#).unwrap();
+        assert_eq!(config.setup_retry_delay(0), Duration::from_millis(500));
+        assert_eq!(config.setup_retry_delay(2), Duration::from_millis(2_000));
+        assert_eq!(config.setup_retry_delay(40), Duration::from_secs(30));
+
+        let config = parse(r#"{"secret_key": "abc"}"#).unwrap();
+        assert_eq!(config.setup_retry_delay(0), Duration::from_secs(1));
+        assert_eq!(config.startup_jitter(), Duration::from_millis(300));
+        let config = parse(r#"{"secret_key": "abc", "startup_jitter_ms": 0}"#).unwrap();
+        assert_eq!(config.startup_jitter(), Duration::ZERO);
+    }
+
+    #[test]
+    fn next_poll_countdown() {
+        let clock = ManualClock::new(10_000);
+        let next_poll_at = clock.now_ms() + 3_000;
+
+        assert_eq!(next_poll_ms(0, &clock), -1);
+        assert_eq!(next_poll_ms(next_poll_at, &clock), 3_000);
+        clock.advance(2_500);
+        assert_eq!(next_poll_ms(next_poll_at, &clock), 500);
+
+        /* poll overdue (in flight) */
+        clock.advance(1_000);
+        assert_eq!(next_poll_ms(next_poll_at, &clock), 0);
+    }
+
+    #[tokio::test]
+    async fn core_stop_is_noticed() {
+        let keep_running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+        let wait = tokio::time::timeout(
+            Duration::from_secs(2),
+            core_stopped(keep_running.clone(), stop_rx.clone()),
+        );
+        assert!(wait.await.is_ok());
+
+        /* cleared by playit_stop, not the core */
+        stop_tx.send(true).unwrap();
+        let wait = tokio::time::timeout(Duration::from_millis(600), core_stopped(keep_running, stop_rx));
+        assert!(wait.await.is_err());
+    }
+
+    #[test]
+    fn connected_duration() {
+        let clock = ManualClock::new(10_000);
+        assert_eq!(connected_duration_ms(0, &clock), -1);
+        assert_eq!(connected_duration_ms(10_000, &clock), 0);
+        clock.advance(7_500);
+        assert_eq!(connected_duration_ms(10_000, &clock), 7_500);
+    }
+
+    #[test]
+    fn address_change_detected() {
+        let mut last_seen = None;
+        assert_eq!(address_changed(&mut last_seen, "a.ply.gg:1"), None);
+        assert_eq!(address_changed(&mut last_seen, "a.ply.gg:1"), None);
+        assert_eq!(address_changed(&mut last_seen, "b.ply.gg:2").as_deref(), Some("a.ply.gg:1"));
+        assert_eq!(last_seen.as_deref(), Some("b.ply.gg:2"));
+    }
+
+    #[test]
+    fn disconnect_grace() {
+        let connected = PlayitStatusCode::Connected;
+        assert!(hold_connected(connected, 10_000, 10_000, 5_000));
+        assert!(hold_connected(connected, 10_000, 14_999, 5_000));
+        assert!(!hold_connected(connected, 10_000, 15_000, 5_000));
+        assert!(!hold_connected(connected, 10_000, 10_000, 0));
+        assert!(!hold_connected(PlayitStatusCode::Error, 10_000, 10_000, 5_000));
+        assert!(!hold_connected(PlayitStatusCode::Connecting, 10_000, 10_000, 5_000));
+    }
+
+    #[test]
+    fn start_with_rejects_unknown_mode() {
+        assert_eq!(playit_start_with(3), -4);
+        assert_eq!(playit_start_with(-1), -4);
+    }
+
+    #[test]
+    fn quiet_restart_hides_until_settled() {
+        let quiet = AtomicBool::new(false);
+        assert!(!quiet_restart_hides(&quiet, PlayitStatusCode::Stopped));
+
+        quiet.store(true, Ordering::Release);
+        assert!(quiet_restart_hides(&quiet, PlayitStatusCode::Stopped));
+        assert!(quiet_restart_hides(&quiet, PlayitStatusCode::Connecting));
+        assert!(!quiet_restart_hides(&quiet, PlayitStatusCode::Connected));
+        assert!(!quiet_restart_hides(&quiet, PlayitStatusCode::Disconnected));
+
+        quiet.store(true, Ordering::Release);
+        assert!(!quiet_restart_hides(&quiet, PlayitStatusCode::RateLimited));
+        assert!(!quiet_restart_hides(&quiet, PlayitStatusCode::Disconnected));
+
+        quiet.store(true, Ordering::Release);
+        assert!(!quiet_restart_hides(&quiet, PlayitStatusCode::AuthFailed));
+        assert!(!quiet.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn late_thread_exit_keeps_newer_run() {
+        let mut state = GlobalState::new();
+        let (stop_tx, _stop_rx) = tokio::sync::watch::channel(false);
+        state.running = true;
+        state.run = 2;
+        state.stop_tx = Some(stop_tx);
+
+        assert!(!state.end_run(1));
+        assert!(state.running && state.stop_tx.is_some());
+
+        assert!(state.end_run(2));
+        assert!(!state.running && state.stop_tx.is_none());
+    }
+
+    #[test]
+    fn running_until_stopped_thread_exits() {
+        let mut state = GlobalState::new();
+        let (stop_tx, _stop_rx) = tokio::sync::watch::channel(false);
+        state.running = true;
+        state.stop_tx = Some(stop_tx);
+        assert!(!state.stopping());
+
+        /* what stop_agent leaves behind when the thread didn't confirm in time */
+        state.clear_run_state();
+        assert!(state.running && state.stopping());
+
+        assert!(state.end_run(0));
+        assert!(!state.running && !state.stopping());
     }
-    status_lock.last_error = None;
 }
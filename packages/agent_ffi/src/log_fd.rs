@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::Write;
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+use std::sync::Mutex;
+
+use tracing::Level;
+
+use crate::{clock, ensure_logging};
+
+/// Host owned file descriptor log lines are written to. Never closed by us, the host
+/// keeps ownership and can rotate or close it after replacing the sink.
+struct FdSink {
+    file: ManuallyDrop<File>,
+}
+
+impl FdSink {
+    /// # Safety
+    /// `fd` must stay open while the sink is installed
+    unsafe fn new(fd: i32) -> Self {
+        FdSink {
+            file: ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }),
+        }
+    }
+
+    fn write_line(&self, line: &str) -> std::io::Result<()> {
+        /* one write per line so lines from different threads don't interleave */
+        (&*self.file).write_all(line.as_bytes())
+    }
+}
+
+static LOG_FD: Mutex<Option<FdSink>> = Mutex::new(None);
+
+fn format_line(now_ms: u64, level: Level, message: &str) -> String {
+    let time = chrono::DateTime::from_timestamp_millis(now_ms as i64).unwrap_or_default();
+    format!(
+        "{} {:>5} {}\n",
+        time.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+        level,
+        message.replace('\n', " ")
+    )
+}
+
+/// Writes the line if a sink is installed. On a write error the sink is removed and the
+/// error returned so it can be reported through the log callback instead.
+pub(crate) fn write_log(level: Level, message: &str) -> Option<std::io::Error> {
+    let mut lock = LOG_FD.lock().expect("log fd lock poisoned");
+    let sink = lock.as_ref()?;
+
+    let error = sink
+        .write_line(&format_line(clock().now_ms(), level, message))
+        .err()?;
+    *lock = None;
+    Some(error)
+}
+
+/// Also write formatted log lines straight to `fd` (ex. a file the host rotates), next to
+/// the log callback. Same level filter as the callback. A negative fd removes the sink.
+/// The fd must stay open until the sink is replaced or removed; it isn't closed here.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_log_fd(fd: i32) {
+    ensure_logging();
+    let sink = (0 <= fd).then(|| unsafe { FdSink::new(fd) });
+    *LOG_FD.lock().expect("log fd lock poisoned") = sink;
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::fd::AsRawFd;
+
+    use tracing::Level;
+
+    use super::{FdSink, format_line};
+
+    #[test]
+    fn line_format() {
+        assert_eq!(
+            format_line(1_700_000_000_123, Level::WARN, "tunnel\ndown"),
+            "2023-11-14T22:13:20.123Z  WARN tunnel down\n"
+        );
+    }
+
+    #[test]
+    fn write_to_fd() {
+        let path = std::env::temp_dir().join(format!("playit-log-fd-{}", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+
+        {
+            let sink = unsafe { FdSink::new(file.as_raw_fd()) };
+            sink.write_line("hello\n").unwrap();
+        }
+
+        /* the sink must not have closed the host's fd */
+        assert!(file.sync_all().is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+
+        let read_only = std::fs::File::open(&path).unwrap();
+        let sink = unsafe { FdSink::new(read_only.as_raw_fd()) };
+        assert!(sink.write_line("nope\n").is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
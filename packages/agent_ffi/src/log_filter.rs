@@ -0,0 +1,104 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Host rules by target prefix, true forwards matching targets and false drops them
+static RULES: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
+/// Skips the lock for every event while no rules are set
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether prefix names target or one of its parent modules, "" matches every target
+fn matches(prefix: &str, target: &str) -> bool {
+    let Some(rest) = target.strip_prefix(prefix) else {
+        return false;
+    };
+    prefix.is_empty() || prefix.ends_with("::") || rest.is_empty() || rest.starts_with("::")
+}
+
+/// The longest matching prefix decides. Targets no rule matches are forwarded unless some
+/// rule allows a target, then only allowed targets are.
+fn allows_with(rules: &[(String, bool)], target: &str) -> bool {
+    let decided = rules
+        .iter()
+        .filter(|(prefix, _)| matches(prefix, target))
+        .max_by_key(|(prefix, _)| prefix.len());
+    match decided {
+        Some((_, enabled)) => *enabled,
+        None => !rules.iter().any(|(_, enabled)| *enabled),
+    }
+}
+
+pub(crate) fn allows(target: &str) -> bool {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return true;
+    }
+    allows_with(&RULES.lock().expect("log filter lock poisoned"), target)
+}
+
+pub(crate) fn clear() {
+    let mut rules = RULES.lock().expect("log filter lock poisoned");
+    rules.clear();
+    ACTIVE.store(false, Ordering::Release);
+}
+
+/// Forward (enabled) or drop log events whose target is prefix or a module under it, a
+/// second call for the same prefix replaces its rule. NULL removes all rules. 0 ok, -2
+/// invalid UTF-8.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_set_log_target_filter(prefix: *const c_char, enabled: bool) -> i32 {
+    if prefix.is_null() {
+        clear();
+        return 0;
+    }
+    let Ok(prefix) = unsafe { CStr::from_ptr(prefix) }.to_str() else {
+        return -2;
+    };
+
+    let mut rules = RULES.lock().expect("log filter lock poisoned");
+    match rules.iter_mut().find(|(existing, _)| existing == prefix) {
+        Some(rule) => rule.1 = enabled,
+        None => rules.push((prefix.to_string(), enabled)),
+    }
+    ACTIVE.store(true, Ordering::Release);
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::allows_with;
+
+    #[test]
+    fn longest_prefix_wins() {
+        let tcp = "playit_agent_core::network::tcp::tcp_clients";
+        let udp = "playit_agent_core::network::udp::udp_clients";
+        let rule = |prefix: &str, enabled| (prefix.to_string(), enabled);
+
+        assert!(allows_with(&[], tcp));
+
+        let only_tcp = [rule("playit_agent_core::network::tcp", true)];
+        assert!(allows_with(&only_tcp, tcp));
+        assert!(!allows_with(&only_tcp, udp));
+        assert!(!allows_with(&only_tcp, "playit_agent"));
+
+        let quiet_udp = [rule("playit_agent_core::network::udp", false)];
+        assert!(allows_with(&quiet_udp, tcp));
+        assert!(!allows_with(&quiet_udp, udp));
+
+        let nested = [
+            rule("playit_agent_core", false),
+            rule("playit_agent_core::network::tcp", true),
+        ];
+        assert!(allows_with(&nested, tcp));
+        assert!(!allows_with(&nested, udp));
+        assert!(!allows_with(&nested, "playit_agent"));
+
+        let nested_default_on = [nested[0].clone(), nested[1].clone(), rule("", true)];
+        assert!(allows_with(&nested_default_on, "playit_agent"));
+        assert!(!allows_with(&nested_default_on, udp));
+
+        let crate_name = [rule("playit_agent", false)];
+        assert!(!allows_with(&crate_name, "playit_agent"));
+        assert!(allows_with(&crate_name, tcp));
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Cost of one log line in bucket units, the bucket refills `per_second` lines a second
+const LINE: u64 = 1_000;
+
+/// Lines a second let through, 0 while there's no limit
+static LIMIT: AtomicU32 = AtomicU32::new(0);
+static BUCKET: Mutex<TokenBucket> = Mutex::new(TokenBucket::new(0));
+/// Lines dropped by the limit since the library was loaded
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Allows bursts of up to one second worth of lines, then `per_second` lines a second
+struct TokenBucket {
+    per_second: u32,
+    tokens: u64,
+    last_ms: u64,
+}
+
+impl TokenBucket {
+    const fn new(per_second: u32) -> Self {
+        TokenBucket {
+            per_second,
+            tokens: per_second as u64 * LINE,
+            last_ms: 0,
+        }
+    }
+
+    fn take(&mut self, now_ms: u64) -> bool {
+        let elapsed_ms = now_ms.saturating_sub(self.last_ms);
+        self.last_ms = self.last_ms.max(now_ms);
+
+        let capacity = self.per_second as u64 * LINE;
+        let refill = elapsed_ms.saturating_mul(self.per_second as u64);
+        self.tokens = self.tokens.saturating_add(refill).min(capacity);
+
+        if self.tokens < LINE {
+            return false;
+        }
+        self.tokens -= LINE;
+        true
+    }
+}
+
+/// Whether a log line may go out now, counting it as dropped if not
+pub(crate) fn allow(now_ms: u64) -> bool {
+    if LIMIT.load(Ordering::Relaxed) == 0 {
+        return true;
+    }
+    let allowed = BUCKET.lock().expect("log rate lock poisoned").take(now_ms);
+    if !allowed {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    allowed
+}
+
+pub(crate) fn clear() {
+    playit_set_log_rate_limit(0);
+    DROPPED.store(0, Ordering::Relaxed);
+}
+
+/// Most log lines a second passed to the log callbacks and log fd, lines over it are
+/// dropped and counted. 0 (the default) removes the limit.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_log_rate_limit(per_second: u32) {
+    let mut bucket = BUCKET.lock().expect("log rate lock poisoned");
+    *bucket = TokenBucket::new(per_second);
+    LIMIT.store(per_second, Ordering::Relaxed);
+}
+
+/// Log lines dropped by `playit_set_log_rate_limit`, since the library was loaded
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_get_dropped_log_count() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::TokenBucket;
+
+    #[test]
+    fn bucket_refills_per_second() {
+        let mut bucket = TokenBucket::new(2);
+        bucket.last_ms = 10_000;
+
+        assert!(bucket.take(10_000));
+        assert!(bucket.take(10_000));
+        assert!(!bucket.take(10_000));
+
+        assert!(!bucket.take(10_400));
+        assert!(bucket.take(10_500));
+        assert!(!bucket.take(10_500));
+
+        /* a long pause refills no more than a second's worth */
+        assert!(bucket.take(60_000));
+        assert!(bucket.take(60_000));
+        assert!(!bucket.take(60_000));
+    }
+}
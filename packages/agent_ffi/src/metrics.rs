@@ -0,0 +1,242 @@
+use std::fmt::Write;
+use std::os::raw::c_char;
+use std::sync::atomic::Ordering;
+
+use playit_agent_core::stats::StatsSnapshot;
+
+use crate::{POLL_ERRORS, STATUS_CODE, state, write_c_buffer};
+
+/// Everything a scrape reports, collected under the state lock and rendered after
+#[derive(Default)]
+struct MetricsInput {
+    running: bool,
+    status_code: i32,
+    reconnect_attempts: u32,
+    poll_errors: u64,
+    totals: StatsSnapshot,
+    /// (tunnel id, stats) for tunnels in the latest rundata
+    tunnels: Vec<(u64, StatsSnapshot)>,
+}
+
+fn collect() -> MetricsInput {
+    let lock = state().lock().expect("state lock poisoned");
+
+    let mut input = MetricsInput {
+        running: lock.running,
+        status_code: STATUS_CODE.load(Ordering::Acquire),
+        reconnect_attempts: lock
+            .reconnect_attempts
+            .as_ref()
+            .map(|v| v.load(Ordering::SeqCst))
+            .unwrap_or(0),
+        poll_errors: POLL_ERRORS.load(Ordering::Relaxed),
+        ..Default::default()
+    };
+
+    if let Some(stats) = &lock.stats {
+        input.totals = stats.snapshot();
+
+        let status = lock.status.lock().expect("status lock poisoned");
+        input.tunnels = status
+            .tunnels
+            .iter()
+            .map(|tunnel| (tunnel.id, stats.tunnel_snapshot(tunnel.id).unwrap_or_default()))
+            .collect();
+    }
+
+    input
+}
+
+/// Writes one metric family. Each sample is (labels, value); labels are already
+/// formatted as `key="value",...` and only contain tunnel ids and fixed strings.
+fn family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    if name.ends_with("_bytes") {
+        let _ = writeln!(out, "# UNIT {} bytes", name);
+    }
+
+    let sample_name = if kind == "counter" {
+        format!("{}_total", name)
+    } else {
+        name.to_string()
+    };
+
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", sample_name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", sample_name, labels, value);
+        }
+    }
+}
+
+fn render(input: &MetricsInput) -> String {
+    let mut out = String::new();
+
+    /* agent totals first, then one labelled series per tunnel */
+    let per_tunnel = |value: fn(&StatsSnapshot) -> u64, total: u64| {
+        let mut samples = vec![(String::new(), total)];
+        samples.extend(
+            input
+                .tunnels
+                .iter()
+                .map(|(id, snapshot)| (format!("tunnel_id=\"{}\"", id), value(snapshot))),
+        );
+        samples
+    };
+
+    family(
+        &mut out,
+        "playit_agent_running",
+        "gauge",
+        "1 while the agent is started",
+        &[(String::new(), input.running as u64)],
+    );
+    family(
+        &mut out,
+        "playit_agent_status",
+        "gauge",
+        "Current playit_status_code",
+        &[(String::new(), input.status_code.max(0) as u64)],
+    );
+    family(
+        &mut out,
+        "playit_tunnel_in_bytes",
+        "counter",
+        "Bytes received from tunnel clients since start",
+        &per_tunnel(|s| s.bytes_in, input.totals.bytes_in),
+    );
+    family(
+        &mut out,
+        "playit_tunnel_out_bytes",
+        "counter",
+        "Bytes sent to tunnel clients since start",
+        &per_tunnel(|s| s.bytes_out, input.totals.bytes_out),
+    );
+
+    let mut active = Vec::new();
+    for (proto, value) in [
+        ("tcp", input.totals.active_tcp),
+        ("udp", input.totals.active_udp),
+    ] {
+        active.push((format!("proto=\"{}\"", proto), value as u64));
+    }
+    for (id, snapshot) in &input.tunnels {
+        for (proto, value) in [("tcp", snapshot.active_tcp), ("udp", snapshot.active_udp)] {
+            active.push((format!("tunnel_id=\"{}\",proto=\"{}\"", id, proto), value as u64));
+        }
+    }
+    family(
+        &mut out,
+        "playit_active_connections",
+        "gauge",
+        "Open TCP connections and UDP flows",
+        &active,
+    );
+
+    family(
+        &mut out,
+        "playit_rejected_tcp_connections",
+        "counter",
+        "TCP clients rejected by max_connections since start",
+        &per_tunnel(|s| s.rejected_tcp, input.totals.rejected_tcp),
+    );
+    family(
+        &mut out,
+        "playit_blocked_connections",
+        "counter",
+        "TCP clients and new UDP flows refused by the source ACL since start",
+        &per_tunnel(|s| s.blocked, input.totals.blocked),
+    );
+    family(
+        &mut out,
+        "playit_origin_refused_connections",
+        "counter",
+        "TCP clients dropped because the local server refused them since start",
+        &per_tunnel(|s| s.origin_refused, input.totals.origin_refused),
+    );
+    family(
+        &mut out,
+        "playit_reconnect_attempts",
+        "gauge",
+        "Tunnel session re-establish attempts since it was last stable",
+        &[(String::new(), input.reconnect_attempts as u64)],
+    );
+    family(
+        &mut out,
+        "playit_rundata_poll_errors",
+        "counter",
+        "Failed rundata polls since the library was loaded",
+        &[(String::new(), input.poll_errors)],
+    );
+
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Writes an OpenMetrics text snapshot of the agent counters into `buf`. Same return
+/// value as the other buffer functions.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_metrics_text(buf: *mut c_char, len: usize) -> i32 {
+    let text = render(&collect());
+    unsafe { write_c_buffer(&text, buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_agent_core::stats::StatsSnapshot;
+
+    use super::{MetricsInput, render};
+
+    #[test]
+    fn renders_openmetrics() {
+        let input = MetricsInput {
+            running: true,
+            status_code: 2,
+            reconnect_attempts: 1,
+            poll_errors: 3,
+            totals: StatsSnapshot {
+                bytes_in: 100,
+                bytes_out: 200,
+                active_tcp: 2,
+                active_udp: 1,
+                rejected_tcp: 4,
+                blocked: 5,
+                origin_refused: 6,
+            },
+            tunnels: vec![(
+                7,
+                StatsSnapshot {
+                    bytes_in: 100,
+                    bytes_out: 200,
+                    active_tcp: 2,
+                    active_udp: 1,
+                    rejected_tcp: 4,
+                    blocked: 5,
+                    origin_refused: 6,
+                },
+            )],
+        };
+
+        let text = render(&input);
+        assert!(text.ends_with("# EOF\n"));
+        assert!(text.contains("# UNIT playit_tunnel_in_bytes bytes\n"));
+        assert!(text.contains("\nplayit_tunnel_in_bytes_total 100\n"));
+        assert!(text.contains("\nplayit_tunnel_out_bytes_total{tunnel_id=\"7\"} 200\n"));
+        assert!(text.contains("\nplayit_active_connections{tunnel_id=\"7\",proto=\"udp\"} 1\n"));
+        assert!(text.contains("\nplayit_rundata_poll_errors_total 3\n"));
+        assert!(text.contains("\nplayit_origin_refused_connections_total{tunnel_id=\"7\"} 6\n"));
+        assert!(text.contains("\nplayit_agent_status 2\n"));
+
+        /* every sample belongs to the family declared before it */
+        let mut family = "";
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                family = rest.split(' ').next().unwrap();
+            } else if !line.starts_with('#') {
+                assert!(line.starts_with(family), "{} outside {}", line, family);
+            }
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::os::raw::c_char;
+
+use crate::{state, write_c_buffer};
+
+/// Writes the public IP the playit control server sees the agent connecting from, as
+/// reported in its latest pong. -1 if not running or no session has been established yet,
+/// otherwise the same return value as the other buffer functions.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_observed_ip(buf: *mut c_char, len: usize) -> i32 {
+    let addr = {
+        let lock = state().lock().expect("state lock poisoned");
+        lock.observed_addr
+            .as_ref()
+            .and_then(|addr| *addr.lock().expect("observed addr lock poisoned"))
+    };
+
+    match addr {
+        Some(addr) => unsafe { write_c_buffer(&addr.ip().to_string(), buf, len) },
+        None => -1,
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use playit_agent_core::network::origin_lookup::OriginLookup;
+use tokio::sync::{Notify, watch};
+
+use crate::{state, until_stopped};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+static PREWARM: Notify = Notify::const_new();
+
+/// Message for the last round of hostname origins that failed to resolve, if any did
+static FAILURE: Mutex<Option<String>> = Mutex::new(None);
+
+pub(crate) fn failure() -> Option<String> {
+    FAILURE.lock().expect("origin dns lock poisoned").clone()
+}
+
+pub(crate) fn clear() {
+    *FAILURE.lock().expect("origin dns lock poisoned") = None;
+}
+
+async fn refresh(lookup: &OriginLookup) {
+    let errors = lookup.resolve_hostnames().await;
+    for error in &errors {
+        tracing::warn!(
+            tunnel_id = error.tunnel_id,
+            hostname = %error.hostname,
+            error = %error.error,
+            "failed to resolve origin hostname"
+        );
+    }
+
+    let message = errors.first().map(|error| {
+        format!(
+            "local server hostname \"{}\" could not be resolved ({})",
+            error.hostname, error.error
+        )
+    });
+    *FAILURE.lock().expect("origin dns lock poisoned") = message;
+}
+
+/// Keeps hostname origins resolved ahead of connections, refreshed every minute or right
+/// away on `playit_prewarm_origin`.
+pub(crate) async fn run(lookup: Arc<OriginLookup>, mut stop_rx: watch::Receiver<bool>) {
+    loop {
+        refresh(&lookup).await;
+
+        let wait = async {
+            tokio::select! {
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+                _ = PREWARM.notified() => {}
+            }
+        };
+        if until_stopped(&mut stop_rx, wait).await.is_none() {
+            return;
+        }
+    }
+}
+
+/// Resolve hostname origins again now, ex. after a network change. Returns 0 if queued,
+/// -1 if the agent isn't running.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_prewarm_origin() -> i32 {
+    if !state().lock().expect("state lock poisoned").running {
+        return -1;
+    }
+    PREWARM.notify_one();
+    0
+}
@@ -0,0 +1,87 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+use playit_agent_core::network::origin_lookup::{OriginLookup, OriginResource};
+
+use crate::write_c_buffer;
+
+/// Origins from the latest rundata load, what `playit_export_origin_map` hands out
+static LATEST: Mutex<Option<Vec<OriginResource>>> = Mutex::new(None);
+/// Set by `playit_import_origin_map`, loaded into each new agent's `OriginLookup` before
+/// its first rundata poll
+static IMPORTED: Mutex<Option<Vec<OriginResource>>> = Mutex::new(None);
+
+pub(crate) async fn remember(lookup: &OriginLookup) {
+    let resources = lookup.resources().await;
+    *LATEST.lock().expect("origin map lock poisoned") = Some(resources);
+}
+
+pub(crate) async fn restore(lookup: &OriginLookup) {
+    let imported = IMPORTED.lock().expect("origin map lock poisoned").clone();
+    if let Some(resources) = imported {
+        tracing::info!(count = resources.len(), "restoring imported origin map");
+        lookup.update(resources.into_iter()).await;
+    }
+}
+
+pub(crate) fn clear() {
+    *LATEST.lock().expect("origin map lock poisoned") = None;
+    *IMPORTED.lock().expect("origin map lock poisoned") = None;
+}
+
+/// JSON of the tunnel to origin mapping from the latest rundata, kept after a stop. -1 if
+/// rundata hasn't loaded since the library was loaded.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_export_origin_map(buf: *mut c_char, len: usize) -> i32 {
+    let json = match &*LATEST.lock().expect("origin map lock poisoned") {
+        Some(resources) => serde_json::to_string(resources).expect("origin map serializes"),
+        None => return -1,
+    };
+    unsafe { write_c_buffer(&json, buf, len) }
+}
+
+/// Mapping from `playit_export_origin_map` to serve connections with before the first
+/// rundata poll of the next start, NULL to drop it. 0 ok, -2 invalid UTF-8, -3 invalid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_import_origin_map(json: *const c_char) -> i32 {
+    let resources = if json.is_null() {
+        None
+    } else {
+        let Ok(value) = unsafe { CStr::from_ptr(json) }.to_str() else {
+            return -2;
+        };
+        match serde_json::from_str::<Vec<OriginResource>>(value) {
+            Ok(resources) => Some(resources),
+            Err(error) => {
+                tracing::warn!(%error, "invalid origin map");
+                return -3;
+            }
+        }
+    };
+
+    *IMPORTED.lock().expect("origin map lock poisoned") = resources;
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+
+    use super::{IMPORTED, playit_import_origin_map};
+
+    #[test]
+    fn import_rejects_invalid_map() {
+        let invalid = CString::new(r#"[{"tunnel_id": 1}]"#).unwrap();
+        assert_eq!(unsafe { playit_import_origin_map(invalid.as_ptr()) }, -3);
+
+        let map = r#"[{"tunnel_id": 1, "proto": "tcp", "port_count": 1, "proxy_protocol": null,
+            "target": {"type": "port", "ip": "127.0.0.1", "port": 25565}}]"#;
+        let map = CString::new(map).unwrap();
+        assert_eq!(unsafe { playit_import_origin_map(map.as_ptr()) }, 0);
+        assert_eq!(IMPORTED.lock().unwrap().as_ref().map(Vec::len), Some(1));
+
+        assert_eq!(unsafe { playit_import_origin_map(std::ptr::null()) }, 0);
+        assert!(IMPORTED.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::net::SocketAddr;
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+use playit_agent_core::network::origin_lookup::OriginLookup;
+
+use crate::state;
+
+/// Host set origins by tunnel id. Kept across restarts and handed to each new agent's
+/// `OriginLookup`.
+static OVERRIDES: Mutex<Option<HashMap<u64, SocketAddr>>> = Mutex::new(None);
+
+pub(crate) fn get(tunnel_id: u64) -> Option<SocketAddr> {
+    let lock = OVERRIDES.lock().expect("origin overrides lock poisoned");
+    lock.as_ref()?.get(&tunnel_id).copied()
+}
+
+pub(crate) fn apply_all(lookup: &OriginLookup) {
+    let lock = OVERRIDES.lock().expect("origin overrides lock poisoned");
+    for (tunnel_id, origin) in lock.iter().flatten() {
+        lookup.set_override(*tunnel_id, Some(*origin));
+    }
+}
+
+pub(crate) fn clear() {
+    *OVERRIDES.lock().expect("origin overrides lock poisoned") = None;
+}
+
+/// Route a tunnel's connections to `origin_addr` ("ip:port") instead of the local address
+/// from rundata, NULL to go back to rundata. Takes effect for new connections right away
+/// when running and is kept for later starts.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_set_tunnel_origin(
+    tunnel_id: u64,
+    origin_addr: *const c_char,
+) -> i32 {
+    let origin = if origin_addr.is_null() {
+        None
+    } else {
+        let Ok(value) = unsafe { CStr::from_ptr(origin_addr) }.to_str() else {
+            return -2;
+        };
+        match value.trim().parse::<SocketAddr>() {
+            Ok(addr) => Some(addr),
+            Err(_) => return -3,
+        }
+    };
+
+    {
+        let mut lock = OVERRIDES.lock().expect("origin overrides lock poisoned");
+        let overrides = lock.get_or_insert_with(HashMap::new);
+        match origin {
+            Some(origin) => overrides.insert(tunnel_id, origin),
+            None => overrides.remove(&tunnel_id),
+        };
+    }
+
+    if let Some(lookup) = &state().lock().expect("state lock poisoned").lookup {
+        lookup.set_override(tunnel_id, origin);
+    }
+
+    tracing::info!(tunnel_id, ?origin, "tunnel origin override");
+    0
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use playit_agent_core::network::origin_lookup::OriginLookup;
+use playit_api_client::api::{AgentRunDataV1, PortType};
+use tokio::net::TcpStream;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Opens (and immediately closes) a TCP connection to the local origin of every enabled
+/// TCP tunnel. Returns a message naming the first origin that can't be reached, ex. because
+/// the game server isn't running. UDP origins can't be probed and are skipped, as are
+/// hostname origins that don't resolve (see `origin_dns`).
+pub(crate) async fn find_unreachable_origin(
+    data: &AgentRunDataV1,
+    lookup: &OriginLookup,
+) -> Option<String> {
+    for tunnel in &data.tunnels {
+        if tunnel.disabled_reason.is_some() || tunnel.port_type == PortType::Udp {
+            continue;
+        }
+
+        let res = lookup.lookup(tunnel.internal_id, true).await;
+        let Some(addr) = res.and_then(|res| res.resolve_local(0)) else {
+            continue;
+        };
+
+        let error = match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => continue,
+            Ok(Err(error)) => error.to_string(),
+            Err(_) => "timed out".to_string(),
+        };
+
+        tracing::warn!(%addr, tunnel = %tunnel.name, %error, "origin unreachable");
+        return Some(format!(
+            "local server {} for tunnel \"{}\" is not reachable ({}), is it running?",
+            addr, tunnel.name, error
+        ));
+    }
+
+    None
+}
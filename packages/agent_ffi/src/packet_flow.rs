@@ -0,0 +1,174 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::raw::c_void;
+use std::sync::{Mutex, OnceLock};
+
+use playit_agent_core::agent_control::PacketIO;
+use tokio::sync::mpsc;
+
+/// Inbound packets buffered between the host and the agent's UDP channel.
+const INPUT_QUEUE_SIZE: usize = 1024;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PlayitPacketAddr {
+    pub is_ipv6: u8,
+    pub ip: [u8; 16],
+    pub port: u16,
+}
+
+type PacketSendCallback = extern "C" fn(
+    data: *const u8,
+    len: usize,
+    target: *const PlayitPacketAddr,
+    user_data: *mut c_void,
+) -> i32;
+
+struct PacketFlowState {
+    send: Option<PacketSendCallback>,
+    user_data: *mut c_void,
+    input_tx: Option<mpsc::Sender<(Vec<u8>, SocketAddr)>>,
+}
+
+unsafe impl Send for PacketFlowState {}
+unsafe impl Sync for PacketFlowState {}
+
+static PACKET_FLOW: OnceLock<Mutex<PacketFlowState>> = OnceLock::new();
+
+fn packet_flow_state() -> &'static Mutex<PacketFlowState> {
+    PACKET_FLOW.get_or_init(|| {
+        Mutex::new(PacketFlowState {
+            send: None,
+            user_data: std::ptr::null_mut(),
+            input_tx: None,
+        })
+    })
+}
+
+/// Packet interface handed to the agent's UDP channel when the host has registered a
+/// packet flow. Outbound packets go to the host's send callback and inbound packets
+/// arrive through `playit_packet_flow_input`.
+pub(crate) struct HostPacketIo {
+    send: PacketSendCallback,
+    user_data: *mut c_void,
+    input_rx: tokio::sync::Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+}
+
+unsafe impl Send for HostPacketIo {}
+unsafe impl Sync for HostPacketIo {}
+
+impl HostPacketIo {
+    /// Returns the host packet interface if a packet flow is registered. Packets
+    /// delivered through `playit_packet_flow_input` are routed to the returned value.
+    pub(crate) fn create() -> Option<Self> {
+        let mut lock = packet_flow_state().lock().expect("packet flow lock poisoned");
+        let send = lock.send?;
+
+        let (input_tx, input_rx) = mpsc::channel(INPUT_QUEUE_SIZE);
+        lock.input_tx = Some(input_tx);
+
+        Some(HostPacketIo {
+            send,
+            user_data: lock.user_data,
+            input_rx: tokio::sync::Mutex::new(input_rx),
+        })
+    }
+}
+
+impl PacketIO for HostPacketIo {
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        let target = PlayitPacketAddr::from(target);
+        let result = (self.send)(buf.as_ptr(), buf.len(), &target, self.user_data);
+
+        if result != 0 {
+            return Err(std::io::Error::other(format!(
+                "host packet flow rejected packet: {}",
+                result
+            )));
+        }
+
+        Ok(buf.len())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        let mut input_rx = self.input_rx.lock().await;
+        let Some((packet, source)) = input_rx.recv().await else {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        };
+
+        /* match UDP socket behavior and truncate packets larger than the buffer */
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+
+        Ok((len, source))
+    }
+}
+
+impl From<SocketAddr> for PlayitPacketAddr {
+    fn from(addr: SocketAddr) -> Self {
+        let (is_ipv6, ip) = match addr.ip() {
+            IpAddr::V4(ip) => {
+                let mut bytes = [0u8; 16];
+                bytes[..4].copy_from_slice(&ip.octets());
+                (0, bytes)
+            }
+            IpAddr::V6(ip) => (1, ip.octets()),
+        };
+
+        PlayitPacketAddr {
+            is_ipv6,
+            ip,
+            port: addr.port(),
+        }
+    }
+}
+
+impl From<&PlayitPacketAddr> for SocketAddr {
+    fn from(addr: &PlayitPacketAddr) -> Self {
+        let ip = if addr.is_ipv6 != 0 {
+            IpAddr::V6(Ipv6Addr::from(addr.ip))
+        } else {
+            IpAddr::V4(Ipv4Addr::new(addr.ip[0], addr.ip[1], addr.ip[2], addr.ip[3]))
+        };
+
+        SocketAddr::new(ip, addr.port)
+    }
+}
+
+/// Route the agent's tunneled UDP traffic through the host instead of sockets the
+/// agent binds itself. Takes effect on the next `playit_start`; pass a null callback
+/// to go back to native sockets.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_packet_flow(send: Option<PacketSendCallback>, user_data: *mut c_void) {
+    let mut lock = packet_flow_state().lock().expect("packet flow lock poisoned");
+    lock.send = send;
+    lock.user_data = user_data;
+    lock.input_tx = None;
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_packet_flow_input(
+    data: *const u8,
+    len: usize,
+    source: *const PlayitPacketAddr,
+) -> i32 {
+    if data.is_null() || source.is_null() {
+        return -1;
+    }
+
+    let input_tx = {
+        let lock = packet_flow_state().lock().expect("packet flow lock poisoned");
+        match &lock.input_tx {
+            Some(tx) => tx.clone(),
+            None => return -2,
+        }
+    };
+
+    let packet = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+    let source = SocketAddr::from(unsafe { &*source });
+
+    match input_tx.try_send((packet, source)) {
+        Ok(()) => 0,
+        Err(mpsc::error::TrySendError::Full(_)) => -3,
+        Err(mpsc::error::TrySendError::Closed(_)) => -2,
+    }
+}
@@ -0,0 +1,59 @@
+use std::os::raw::c_char;
+use std::time::Duration;
+
+use playit_api_client::http_client::{HttpClient, HttpClientError};
+
+use crate::{ensure_logging, parse_config_json};
+
+/* -1 to -4 are config errors shared with playit_init */
+const PING_ERR_RUNTIME: i32 = -5;
+const PING_ERR_UNREACHABLE: i32 = -6;
+const PING_ERR_TIMEOUT: i32 = -7;
+
+const DEFAULT_PING_TIMEOUT_MS: u32 = 5_000;
+
+/// Checks that `api_url` answers HTTP on the current network without authenticating or
+/// touching the global agent state. Returns 0 if any HTTP response came back.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_ping_api(config_json: *const c_char, timeout_ms: u32) -> i32 {
+    ensure_logging();
+    let config = match unsafe { parse_config_json(config_json) } {
+        Ok(v) => v,
+        Err(code) => return code,
+    };
+
+    let timeout_ms = if timeout_ms == 0 {
+        DEFAULT_PING_TIMEOUT_MS
+    } else {
+        timeout_ms
+    };
+
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(error) => {
+            tracing::error!(?error, "failed to create runtime for api ping");
+            return PING_ERR_RUNTIME;
+        }
+    };
+
+    let client = HttpClient::with_settings(config.api_url(), None, &config.http_settings());
+    let timeout = Duration::from_millis(timeout_ms as u64);
+
+    match runtime.block_on(client.ping(timeout)) {
+        Ok(status) => {
+            tracing::info!(%status, "api reachable");
+            0
+        }
+        Err(HttpClientError::RequestError(error)) if error.is_timeout() => {
+            tracing::warn!(timeout_ms, "api ping timed out");
+            PING_ERR_TIMEOUT
+        }
+        Err(error) => {
+            tracing::warn!(?error, "api unreachable");
+            PING_ERR_UNREACHABLE
+        }
+    }
+}
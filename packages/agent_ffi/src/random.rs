@@ -0,0 +1,89 @@
+use std::os::raw::c_void;
+use std::time::Duration;
+
+use crate::callbacks::CallbackSlot;
+
+/// Fills `len` bytes at `buf`, returns 0 on success
+pub(crate) type RandomSourceCallback =
+    extern "C" fn(buf: *mut u8, len: usize, user_data: *mut c_void) -> i32;
+
+static RANDOM_SOURCE: CallbackSlot<RandomSourceCallback> = CallbackSlot::new();
+
+/// From the host's source if one is set and it succeeds, the standard RNG otherwise
+pub(crate) fn random_u64() -> u64 {
+    if let Some((callback, user_data)) = RANDOM_SOURCE.get() {
+        let mut buf = [0u8; 8];
+        if callback(buf.as_mut_ptr(), buf.len(), user_data) == 0 {
+            return u64::from_ne_bytes(buf);
+        }
+        tracing::warn!("random source callback failed, using the standard RNG");
+    }
+    rand::random()
+}
+
+/// `delay` plus up to a quarter of it, so hosts that failed together don't retry together
+pub(crate) fn jitter(delay: Duration) -> Duration {
+    jitter_with(delay, random_u64())
+}
+
+fn jitter_with(delay: Duration, random: u64) -> Duration {
+    let max_ms = delay.as_millis() as u64 / 4;
+    delay + Duration::from_millis(random % (max_ms + 1))
+}
+
+/// Anywhere from zero to `max`, ex. to spread out hosts that start together
+pub(crate) fn up_to(max: Duration) -> Duration {
+    up_to_with(max, random_u64())
+}
+
+fn up_to_with(max: Duration, random: u64) -> Duration {
+    Duration::from_millis(random % (max.as_millis() as u64 + 1))
+}
+
+/// Source for the randomness this library draws itself (backoff jitter). The core's own
+/// internal randomness isn't routed through it. NULL goes back to the standard RNG.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_random_source(
+    callback: Option<RandomSourceCallback>,
+    user_data: *mut c_void,
+) {
+    RANDOM_SOURCE.set(callback, user_data);
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::raw::c_void;
+    use std::time::Duration;
+
+    use super::{jitter_with, playit_set_random_source, random_u64, up_to_with};
+
+    #[test]
+    fn jitter_bounds() {
+        let delay = Duration::from_millis(1_000);
+        assert_eq!(jitter_with(delay, 0), delay);
+        assert_eq!(jitter_with(delay, 250), Duration::from_millis(1_250));
+        assert_eq!(jitter_with(delay, 251), Duration::from_millis(1_000));
+        assert_eq!(jitter_with(Duration::ZERO, u64::MAX), Duration::ZERO);
+    }
+
+    #[test]
+    fn up_to_bounds() {
+        let max = Duration::from_millis(300);
+        assert_eq!(up_to_with(max, 0), Duration::ZERO);
+        assert_eq!(up_to_with(max, 300), max);
+        assert_eq!(up_to_with(max, 301), Duration::ZERO);
+        assert_eq!(up_to_with(Duration::ZERO, u64::MAX), Duration::ZERO);
+    }
+
+    extern "C" fn fixed_source(buf: *mut u8, len: usize, _: *mut c_void) -> i32 {
+        unsafe { std::ptr::write_bytes(buf, 0xAB, len) };
+        0
+    }
+
+    #[test]
+    fn host_source_is_used() {
+        playit_set_random_source(Some(fixed_source), std::ptr::null_mut());
+        assert_eq!(random_u64(), 0xABAB_ABAB_ABAB_ABAB);
+        playit_set_random_source(None, std::ptr::null_mut());
+    }
+}
@@ -0,0 +1,284 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::sync::atomic::Ordering;
+
+use crate::{
+    FfiConfig, LOG_LEVEL, POLL_INTERVAL_MS, QUIET_RESTART, check_bind_address, cstring_sanitize,
+    end_quiet_restart, ensure_logging, event_log, parse_config_json, playit_start, playit_stop,
+    state, stop_agent, update_status,
+};
+
+/* bits returned by playit_reconfigure for changes that wait for the next playit_start */
+const RESTART_ACCOUNT: i32 = 1 << 0;
+const RESTART_NETWORK: i32 = 1 << 1;
+const RESTART_RUNTIME: i32 = 1 << 2;
+const RESTART_IDENTITY: i32 = 1 << 3;
+const RESTART_TUNNELS: i32 = 1 << 4;
+
+/// Replace the config without stopping the agent. Settings that can change live are
+/// applied immediately, the rest are stored for the next `playit_start`. Returns a
+/// bitmask of the groups that need a restart (0 if everything took effect), or the
+/// same negative codes as `playit_init`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_reconfigure(config_json: *const c_char) -> i32 {
+    ensure_logging();
+    let mut config = match unsafe { parse_config_json(config_json) } {
+        Ok(v) => v,
+        Err(code) => return code,
+    };
+    let bind_error = check_bind_address(&mut config);
+
+    let mut lock = state().lock().expect("state lock poisoned");
+    /* against what is running, so a change stays reported until the restart */
+    let restart = match &lock.running_config {
+        Some(running) if lock.running => {
+            restart_flags(running, &lock.with_fallbacks(config.clone()))
+        }
+        _ => 0,
+    };
+
+    LOG_LEVEL.store(config.log_level_code(), Ordering::Relaxed);
+    event_log::set_capacity(config.event_log_capacity());
+    if lock.running {
+        /* picked up when the poll loop next goes to sleep */
+        POLL_INTERVAL_MS.store(config.poll_interval_ms(), Ordering::Relaxed);
+    }
+    if let Some(acl) = &lock.acl {
+        acl.set(config.acl());
+    }
+
+    lock.config = Some(config);
+    let status = lock.status.clone();
+    drop(lock);
+
+    if let Some(message) = bind_error {
+        update_status(&status, |status| status.last_error = Some(cstring_sanitize(message)));
+    }
+    restart
+}
+
+/// Stops the agent and starts it again with the current config, ex. to apply the flags
+/// returned by `playit_reconfigure`. With `quiet` the status callbacks only hear the state
+/// the new agent settles in, not the STOPPED and CONNECTING on the way there.
+/// 0 restarted, -1 not running, -2 the stop timed out, -3 the start failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_restart(quiet: bool) -> i32 {
+    ensure_logging();
+    if !state().lock().expect("state lock poisoned").running {
+        return -1;
+    }
+
+    tracing::info!(quiet, "restarting the agent");
+    QUIET_RESTART.store(quiet, Ordering::Release);
+    let result = if stop_agent() != 0 {
+        tracing::warn!("agent did not stop in time, not restarting");
+        -2
+    } else {
+        match playit_start() {
+            0 => 0,
+            code => {
+                tracing::warn!(code, "failed to restart the agent");
+                -3
+            }
+        }
+    };
+    if result != 0 {
+        end_quiet_restart();
+    }
+    result
+}
+
+/* playit_update_secret_key results on top of the playit_init codes */
+const KEY_PENDING: i32 = 1;
+const KEY_ERR_NO_CONFIG: i32 = -5;
+const KEY_ERR_RESTART: i32 = -6;
+
+/// Replaces the secret key of the loaded config. A running agent either restarts right
+/// away with it (`reconnect`, open connections are dropped) or keeps its session and
+/// connections on the old key until the next `playit_start`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_update_secret_key(
+    secret_key: *const c_char,
+    reconnect: bool,
+) -> i32 {
+    ensure_logging();
+    if secret_key.is_null() {
+        return -1;
+    }
+    let Ok(secret_key) = unsafe { CStr::from_ptr(secret_key) }.to_str() else {
+        return -2;
+    };
+    if secret_key.trim().is_empty() {
+        return -3;
+    }
+
+    let running = {
+        let mut lock = state().lock().expect("state lock poisoned");
+        let Some(config) = lock.config.as_mut() else {
+            return KEY_ERR_NO_CONFIG;
+        };
+        if config.secret_key == secret_key {
+            return 0;
+        }
+        config.secret_key = secret_key.to_string();
+        lock.running
+    };
+
+    if !running {
+        return 0;
+    }
+    if !reconnect {
+        tracing::info!("secret key updated, used from the next start");
+        return KEY_PENDING;
+    }
+
+    tracing::info!("secret key updated, restarting the agent");
+    if playit_stop() != 0 {
+        tracing::warn!("agent did not stop in time, not restarting with the new key");
+        return KEY_ERR_RESTART;
+    }
+    match playit_start() {
+        0 => 0,
+        code => {
+            tracing::warn!(code, "failed to restart the agent with the new key");
+            KEY_ERR_RESTART
+        }
+    }
+}
+
+fn restart_flags(current: &FfiConfig, new: &FfiConfig) -> i32 {
+    let mut flags = 0;
+
+    if current.secret_key != new.secret_key
+        || current.api_url() != new.api_url()
+        || current.insecure_skip_tls_verify != new.insecure_skip_tls_verify
+        || current.api_timeout() != new.api_timeout()
+        || current.account_info != new.account_info
+    {
+        flags |= RESTART_ACCOUNT;
+    }
+
+    if current.bind_address() != new.bind_address()
+        || current.control_source_port != new.control_source_port
+        || current.max_buffer_memory_kb != new.max_buffer_memory_kb
+        || current.tcp_backlog() != new.tcp_backlog()
+        || current.tcp_nodelay() != new.tcp_nodelay()
+        || current.origin_connect_retries() != new.origin_connect_retries()
+        || current.origin_connect_retry_ms() != new.origin_connect_retry_ms()
+        || current.max_connections != new.max_connections
+        || current.connection_limit_mode() != new.connection_limit_mode()
+    {
+        flags |= RESTART_NETWORK;
+    }
+
+    if current.worker_threads != new.worker_threads
+        || current.runtime_drivers != new.runtime_drivers
+        || current.poll_rundata() != new.poll_rundata()
+        || current.schedule != new.schedule
+        || current.schedule_utc_offset_minutes != new.schedule_utc_offset_minutes
+        || current.lazy != new.lazy
+        || current.startup_jitter() != new.startup_jitter()
+        || current.setup_retries.unwrap_or(3) != new.setup_retries.unwrap_or(3)
+        || current.setup_retry_delay(0) != new.setup_retry_delay(0)
+        || current.idle_reconnect_ms != new.idle_reconnect_ms
+        || current.disconnect_give_up_ms != new.disconnect_give_up_ms
+        || current.disconnect_grace_ms != new.disconnect_grace_ms
+        || current.require_traffic_for_connected != new.require_traffic_for_connected
+    {
+        flags |= RESTART_RUNTIME;
+    }
+
+    if current.agent_name != new.agent_name || current.agent_version != new.agent_version {
+        flags |= RESTART_IDENTITY;
+    }
+
+    if current.required_tunnel_id != new.required_tunnel_id
+        || current.allowed_tunnel_ids != new.allowed_tunnel_ids
+        || current.require_tunnels != new.require_tunnels
+    {
+        flags |= RESTART_TUNNELS;
+    }
+
+    flags
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::restart_flags;
+    use crate::FfiConfig;
+
+    /// Applied by `playit_reconfigure` (or read at stop time) without a restart
+    const LIVE: [&str; 6] = [
+        "log_level",
+        "poll_interval_ms",
+        "event_log_capacity",
+        "allow_ips",
+        "deny_ips",
+        "stop_wait_ms",
+    ];
+
+    #[test]
+    fn every_field_is_live_or_flagged() {
+        let changes = json!({
+            "secret_key": "other",
+            "api_url": "https://api.example.com",
+            "poll_interval_ms": 5_000,
+            "agent_version": "2.0",
+            "insecure_skip_tls_verify": true,
+            "agent_name": "phone",
+            "bind_address": "10.0.0.2",
+            "control_source_port": 5_000,
+            "log_level": "warn",
+            "worker_threads": 4,
+            "runtime_drivers": ["io"],
+            "max_connections": 8,
+            "max_connections_queue_ms": 500,
+            "tcp_backlog": 8,
+            "max_buffer_memory_kb": 1_024,
+            "lazy": true,
+            "stop_wait_ms": 100,
+            "required_tunnel_id": 1,
+            "allowed_tunnel_ids": [1],
+            "schedule": [{ "start": "18:00", "end": "23:00" }],
+            "schedule_utc_offset_minutes": 60,
+            "allow_ips": ["10.0.0.0/8"],
+            "deny_ips": ["10.0.0.1"],
+            "poll_rundata": false,
+            "require_traffic_for_connected": true,
+            "require_tunnels": true,
+            "account_info": true,
+            "idle_reconnect_ms": 60_000,
+            "disconnect_give_up_ms": 60_000,
+            "disconnect_grace_ms": 1_000,
+            "tcp_nodelay": false,
+            "api_timeout_ms": 1_000,
+            "origin_connect_retries": 5,
+            "origin_connect_retry_ms": 100,
+            "setup_retries": 0,
+            "setup_retry_ms": 100,
+            "startup_jitter_ms": 1_000,
+            "event_log_capacity": 10,
+        });
+
+        let base: FfiConfig = serde_json::from_value(json!({ "secret_key": "key" })).unwrap();
+        let fields = serde_json::to_value(&base).unwrap();
+        for field in fields.as_object().unwrap().keys() {
+            let Some(change) = changes.get(field) else {
+                panic!("no change listed for {}", field);
+            };
+            let mut changed = fields.clone();
+            changed[field] = change.clone();
+            let changed: FfiConfig = serde_json::from_value(changed).unwrap();
+
+            let flags = restart_flags(&base, &changed);
+            if LIVE.contains(&field.as_str()) {
+                assert_eq!(flags, 0, "{} is applied live", field);
+            } else {
+                assert_ne!(flags, 0, "{} needs a restart but isn't flagged", field);
+            }
+        }
+        assert_eq!(restart_flags(&base, &base), 0);
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use playit_agent_core::agent_control::maintained_control::{ControlDiagnostics, ControlState};
+use tokio::sync::watch;
+
+use crate::{PlayitStatusCode, StatusSnapshot, until_stopped, update_status};
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Status for a control link in `control` while the status is `code`, None to keep it.
+/// Only a Connected agent goes Reconnecting, and only Reconnecting goes back.
+fn transition(code: PlayitStatusCode, control: ControlState) -> Option<PlayitStatusCode> {
+    match (code, control) {
+        (PlayitStatusCode::Connected, ControlState::Connecting | ControlState::Disconnected) => {
+            Some(PlayitStatusCode::Reconnecting)
+        }
+        (PlayitStatusCode::Reconnecting, ControlState::Connected) => {
+            Some(PlayitStatusCode::Connected)
+        }
+        _ => None,
+    }
+}
+
+/// Mirrors re-establishing the tunnel session after it was up into the status, for as
+/// long as the agent runs
+pub(crate) async fn run(
+    status: Arc<Mutex<StatusSnapshot>>,
+    diagnostics: Arc<Mutex<ControlDiagnostics>>,
+    mut stop_rx: watch::Receiver<bool>,
+) {
+    loop {
+        let control = diagnostics.lock().expect("diagnostics lock poisoned").state;
+        let code = status.lock().expect("status lock poisoned").code;
+
+        if let Some(next) = transition(code, control) {
+            update_status(&status, |lock| {
+                /* the poll loop may have moved on since the check */
+                if lock.code == code {
+                    lock.code = next;
+                }
+            });
+        }
+
+        let wait = tokio::time::sleep(CHECK_INTERVAL);
+        if until_stopped(&mut stop_rx, wait).await.is_none() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_agent_core::agent_control::maintained_control::ControlState;
+
+    use super::transition;
+    use crate::PlayitStatusCode;
+
+    #[test]
+    fn only_a_connected_agent_reconnects() {
+        use ControlState::{Connected as Up, Connecting as Retrying, Disconnected as Down};
+        use PlayitStatusCode::{Connected, Connecting, Disconnected, Reconnecting};
+
+        assert_eq!(transition(Connected, Retrying), Some(Reconnecting));
+        assert_eq!(transition(Connected, Down), Some(Reconnecting));
+        assert_eq!(transition(Connected, Up), None);
+
+        /* the first session coming up stays Connecting until rundata has an address */
+        assert_eq!(transition(Connecting, Retrying), None);
+        assert_eq!(transition(Connecting, Up), None);
+        assert_eq!(transition(Disconnected, Retrying), None);
+
+        assert_eq!(transition(Reconnecting, Up), Some(Connected));
+        assert_eq!(transition(Reconnecting, Down), None);
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::Ordering;
+
+use crate::callbacks::{
+    playit_set_error_callback, playit_set_poll_callback, playit_set_rate_limit_callback,
+    playit_set_raw_rundata_callback, playit_set_ready_callback, playit_set_start_result_callback,
+    playit_set_status_callback, playit_set_throughput_callback, playit_set_tunnel_state_callback,
+};
+use crate::packet_flow::playit_set_packet_flow;
+use crate::{
+    CONNECTED_SINCE, DEFAULT_MAX_LOG_LENGTH, LAST_POLL_OK, LAST_SEEN_ADDRESS, LOG_LEVEL,
+    MAX_LOG_LENGTH, NEXT_POLL_AT, NO_ADDRESS_SINCE, POLL_ERRORS, POLL_INTERVAL_MS,
+    PlayitStatusCode, QUIET_RESTART, log_state, playit_stop, state, update_status,
+};
+
+/// Tests and library reload only. Stops the agent and puts every piece of global state
+/// back to how it was at load, except the tracing subscriber which can't be removed.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_reset_all() {
+    playit_stop();
+
+    /* callbacks first so the reset below doesn't reach the host */
+    playit_set_status_callback(None, std::ptr::null_mut());
+    playit_set_error_callback(None, std::ptr::null_mut());
+    playit_set_raw_rundata_callback(None, std::ptr::null_mut());
+    playit_set_throughput_callback(0, None, std::ptr::null_mut());
+    playit_set_tunnel_state_callback(None, std::ptr::null_mut());
+    playit_set_rate_limit_callback(None, std::ptr::null_mut());
+    playit_set_start_result_callback(None, std::ptr::null_mut());
+    playit_set_ready_callback(None, std::ptr::null_mut());
+    playit_set_poll_callback(None, std::ptr::null_mut());
+    playit_set_packet_flow(None, std::ptr::null_mut());
+    crate::random::playit_set_random_source(None, std::ptr::null_mut());
+    {
+        let mut lock = log_state().lock().expect("log callback lock poisoned");
+        lock.callbacks.clear();
+        lock.event_callback = None;
+        lock.event_user_data = std::ptr::null_mut();
+        lock.json_callback = None;
+        lock.json_user_data = std::ptr::null_mut();
+        lock.pending.clear();
+    }
+    #[cfg(unix)]
+    crate::log_fd::playit_set_log_fd(-1);
+    crate::origin_override::clear();
+    crate::origin_dns::clear();
+    crate::origin_map::clear();
+    crate::tunnel_priority::clear();
+    crate::config_watch::clear();
+    crate::log_filter::clear();
+    crate::log_rate::clear();
+    crate::event_log::clear();
+    crate::account::clear();
+    crate::config_error::clear();
+
+    let status = {
+        let mut lock = state().lock().expect("state lock poisoned");
+        lock.config = None;
+        lock.device_model = None;
+        lock.agent_id = None;
+        lock.running_fingerprint = None;
+        lock.running_config = None;
+        lock.status.clone()
+    };
+    update_status(&status, |lock| {
+        lock.code = PlayitStatusCode::Stopped;
+        lock.last_address = None;
+        lock.last_error = None;
+        lock.tunnels.clear();
+    });
+    /* after the change to Stopped above, which would be recorded otherwise */
+    crate::status_timeline::clear();
+
+    NEXT_POLL_AT.store(0, Ordering::Release);
+    CONNECTED_SINCE.store(0, Ordering::Release);
+    QUIET_RESTART.store(false, Ordering::Release);
+    POLL_INTERVAL_MS.store(3_000, Ordering::Relaxed);
+    LOG_LEVEL.store(-1, Ordering::Relaxed);
+    MAX_LOG_LENGTH.store(DEFAULT_MAX_LOG_LENGTH, Ordering::Relaxed);
+    POLL_ERRORS.store(0, Ordering::Relaxed);
+    LAST_POLL_OK.store(-1, Ordering::Relaxed);
+    *LAST_SEEN_ADDRESS.lock().expect("address lock poisoned") = None;
+    NO_ADDRESS_SINCE.store(0, Ordering::Relaxed);
+}
@@ -0,0 +1,211 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    FfiConfig, PlayitStatusCode, RunError, StatusSnapshot, clock, run_agent, until_stopped,
+    update_status,
+};
+
+const DAY_MINUTES: u32 = 24 * 60;
+const MINUTE_MS: i64 = 60_000;
+/// -12:00 to +14:00, every offset in use
+pub(crate) const UTC_OFFSET_RANGE: std::ops::RangeInclusive<i32> = -720..=840;
+
+/// `schedule` entry: active from `start` to `end` ("HH:MM") on `days` (0 = Sunday, all
+/// days if None). An `end` before `start` runs past midnight into the next day.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub(crate) struct Window {
+    start: String,
+    end: String,
+    #[serde(default)]
+    days: Option<Vec<u8>>,
+}
+
+impl Window {
+    fn minutes(&self) -> Option<(u32, u32)> {
+        Some((parse_time(&self.start)?, parse_time(&self.end)?))
+    }
+
+    fn on_day(&self, day: u32) -> bool {
+        match &self.days {
+            Some(days) => days.iter().any(|d| u32::from(*d) == day),
+            None => true,
+        }
+    }
+
+    /// Whether `minute` of `day` (local time) is inside the window
+    fn contains(&self, day: u32, minute: u32) -> bool {
+        let Some((start, end)) = self.minutes() else {
+            return false;
+        };
+        if start < end {
+            return self.on_day(day) && start <= minute && minute < end;
+        }
+        /* past midnight, the part after it belongs to the day before */
+        (self.on_day(day) && start <= minute) || (self.on_day((day + 6) % 7) && minute < end)
+    }
+}
+
+/// Minutes since midnight for "HH:MM"
+fn parse_time(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Why `schedule` can't be used, for the config error
+pub(crate) fn validate(windows: &[Window]) -> Result<(), String> {
+    for (i, window) in windows.iter().enumerate() {
+        let invalid = |reason: &str| Err(format!("schedule entry {}: {}", i, reason));
+        let Some((start, end)) = window.minutes() else {
+            return invalid("start and end must be \"HH:MM\"");
+        };
+        if start == end {
+            return invalid("start and end are the same");
+        }
+        if let Some(days) = &window.days
+            && (days.is_empty() || days.iter().any(|d| 6 < *d))
+        {
+            return invalid("days must be 0 (Sunday) to 6");
+        }
+    }
+    Ok(())
+}
+
+/// Whether any window covers unix time `now_ms` shifted by `utc_offset_minutes`, always
+/// true without windows
+fn active_at(windows: &[Window], utc_offset_minutes: i32, now_ms: u64) -> bool {
+    if windows.is_empty() {
+        return true;
+    }
+    let local_minute = (now_ms as i64).div_euclid(MINUTE_MS) + i64::from(utc_offset_minutes);
+    let day_number = local_minute.div_euclid(i64::from(DAY_MINUTES));
+    /* 1970-01-01 was a Thursday */
+    let day = (day_number + 4).rem_euclid(7) as u32;
+    let minute = local_minute.rem_euclid(i64::from(DAY_MINUTES)) as u32;
+    windows.iter().any(|window| window.contains(day, minute))
+}
+
+fn active_now(config: &FfiConfig) -> bool {
+    let now_ms = clock().now_ms();
+    active_at(&config.schedule, config.schedule_utc_offset_minutes, now_ms)
+}
+
+/// Windows start and end on whole minutes, wake right after the next one
+fn until_next_minute() -> Duration {
+    let into_minute = clock().now_ms() % MINUTE_MS as u64;
+    Duration::from_millis(MINUTE_MS as u64 - into_minute)
+}
+
+/// Waits for the schedule to reach `active`, false if stopped first
+async fn wait_for(config: &FfiConfig, active: bool, stop_rx: &mut watch::Receiver<bool>) -> bool {
+    while active_now(config) != active {
+        let wait = tokio::time::sleep(until_next_minute());
+        if until_stopped(stop_rx, wait).await.is_none() {
+            return false;
+        }
+    }
+    true
+}
+
+/// `run_agent` during the config's schedule windows: the agent is stopped at the end of a
+/// window and started again at the next one, in ScheduledOff in between. Without a
+/// schedule this is just `run_agent`.
+pub(crate) async fn run(
+    config: FfiConfig,
+    status: Arc<Mutex<StatusSnapshot>>,
+    mut stop_rx: watch::Receiver<bool>,
+) -> Result<(), RunError> {
+    if config.schedule.is_empty() {
+        return run_agent(config, status, stop_rx).await;
+    }
+
+    loop {
+        if !active_now(&config) {
+            tracing::info!("outside the schedule, waiting for the next window");
+            update_status(&status, |lock| {
+                lock.code = PlayitStatusCode::ScheduledOff;
+                lock.last_address = None;
+                lock.last_error = None;
+            });
+            if !wait_for(&config, true, &mut stop_rx).await {
+                return Ok(());
+            }
+            tracing::info!("schedule window started, starting the agent");
+            update_status(&status, |lock| lock.code = PlayitStatusCode::Connecting);
+        }
+
+        /* a stop or the end of the window stops this run of the agent */
+        let (window_tx, window_rx) = watch::channel(false);
+        let window_end = {
+            let (config, mut stop_rx) = (config.clone(), stop_rx.clone());
+            tokio::spawn(async move {
+                wait_for(&config, false, &mut stop_rx).await;
+                let _ = window_tx.send(true);
+            })
+        };
+        let result = run_agent(config.clone(), status.clone(), window_rx).await;
+        window_end.abort();
+        result?;
+
+        /* stopped by the host or by the core, not by the schedule */
+        if *stop_rx.borrow() || active_now(&config) {
+            return Ok(());
+        }
+        tracing::info!("schedule window ended, agent stopped");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Window, active_at, parse_time, validate};
+
+    fn window(start: &str, end: &str, days: Option<Vec<u8>>) -> Window {
+        Window {
+            start: start.to_string(),
+            end: end.to_string(),
+            days,
+        }
+    }
+
+    /* 2024-01-06 was a Saturday */
+    const SATURDAY_MS: u64 = 1_704_499_200_000;
+    const HOUR_MS: u64 = 3_600_000;
+
+    #[test]
+    fn windows_by_local_time() {
+        let evenings = [window("18:00", "23:30", None)];
+        assert!(!active_at(&evenings, 0, SATURDAY_MS + 17 * HOUR_MS));
+        assert!(active_at(&evenings, 0, SATURDAY_MS + 18 * HOUR_MS));
+        let end = SATURDAY_MS + 23 * HOUR_MS + HOUR_MS / 2;
+        assert!(!active_at(&evenings, 0, end));
+        /* 16:00 UTC is 18:00 at +02:00 */
+        assert!(active_at(&evenings, 120, SATURDAY_MS + 16 * HOUR_MS));
+        assert!(!active_at(&evenings, -60, SATURDAY_MS + 18 * HOUR_MS));
+
+        /* Saturday 22:00 to Sunday 02:00 only */
+        let overnight = [window("22:00", "02:00", Some(vec![6]))];
+        assert!(active_at(&overnight, 0, SATURDAY_MS + 23 * HOUR_MS));
+        assert!(active_at(&overnight, 0, SATURDAY_MS + 25 * HOUR_MS));
+        assert!(!active_at(&overnight, 0, SATURDAY_MS + HOUR_MS));
+        assert!(!active_at(&overnight, 0, SATURDAY_MS + 47 * HOUR_MS));
+
+        assert!(active_at(&[], 0, SATURDAY_MS));
+    }
+
+    #[test]
+    fn invalid_windows() {
+        assert_eq!(parse_time("07:05"), Some(425));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("7pm"), None);
+
+        assert!(validate(&[window("18:00", "23:00", Some(vec![0, 6]))]).is_ok());
+        assert!(validate(&[window("18:00", "18:00", None)]).is_err());
+        assert!(validate(&[window("18:00", "25:00", None)]).is_err());
+        assert!(validate(&[window("18:00", "23:00", Some(vec![7]))]).is_err());
+        assert!(validate(&[window("18:00", "23:00", Some(vec![]))]).is_err());
+    }
+}
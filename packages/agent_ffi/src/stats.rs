@@ -0,0 +1,224 @@
+use std::os::raw::c_char;
+use std::sync::atomic::Ordering;
+
+use playit_agent_core::agent_control::maintained_control::ControlDiagnostics;
+use playit_agent_core::stats::StatsSnapshot;
+
+use crate::{
+    CONNECTED_SINCE, POLL_ERRORS, STATUS_CODE, STRUCT_ABI_VERSION, clock, connected_duration_ms,
+    log_rate, state, write_c_buffer, write_versioned,
+};
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PlayitStats {
+    pub struct_version: u32,
+    pub struct_size: u32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub active_tcp: u32,
+    pub active_udp: u32,
+    pub rejected_tcp: u64,
+    pub blocked: u64,
+    pub origin_refused: u64,
+}
+
+impl From<StatsSnapshot> for PlayitStats {
+    fn from(snapshot: StatsSnapshot) -> Self {
+        PlayitStats {
+            struct_version: STRUCT_ABI_VERSION,
+            struct_size: size_of::<PlayitStats>() as u32,
+            bytes_in: snapshot.bytes_in,
+            bytes_out: snapshot.bytes_out,
+            active_tcp: snapshot.active_tcp,
+            active_udp: snapshot.active_udp,
+            rejected_tcp: snapshot.rejected_tcp,
+            blocked: snapshot.blocked,
+            origin_refused: snapshot.origin_refused,
+        }
+    }
+}
+
+/// Zeroed counters, still with the version header set
+impl Default for PlayitStats {
+    fn default() -> Self {
+        PlayitStats::from(StatsSnapshot::default())
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_stats(out_stats: *mut PlayitStats) -> i32 {
+    if out_stats.is_null() {
+        return -1;
+    }
+
+    let stats = state()
+        .lock()
+        .expect("state lock poisoned")
+        .stats
+        .as_ref()
+        .map(|stats| stats.snapshot());
+
+    let (code, value) = match stats {
+        Some(snapshot) => (0, PlayitStats::from(snapshot)),
+        None => (-2, PlayitStats::default()),
+    };
+
+    unsafe { write_versioned(out_stats, value) };
+    code
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_tunnel_stats(tunnel_id: u64, out_stats: *mut PlayitStats) -> i32 {
+    if out_stats.is_null() {
+        return -1;
+    }
+
+    let (code, value) = {
+        let lock = state().lock().expect("state lock poisoned");
+        match &lock.stats {
+            None => (-2, PlayitStats::default()),
+            Some(stats) => match stats.tunnel_snapshot(tunnel_id) {
+                Some(snapshot) => (0, PlayitStats::from(snapshot)),
+                /* tunnel exists but hasn't seen any traffic yet */
+                None if lock
+                    .status
+                    .lock()
+                    .expect("status lock poisoned")
+                    .tunnels
+                    .iter()
+                    .any(|t| t.id == tunnel_id) =>
+                {
+                    (0, PlayitStats::default())
+                }
+                None => (-3, PlayitStats::default()),
+            },
+        }
+    };
+
+    unsafe { write_versioned(out_stats, value) };
+    code
+}
+
+/// Every counter in one struct, for dashboards that refresh often
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PlayitMetrics {
+    pub struct_version: u32,
+    pub struct_size: u32,
+    pub status_code: i32,
+    pub reconnect_attempts: u32,
+    pub active_tcp: u32,
+    pub active_udp: u32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub rejected_tcp: u64,
+    pub blocked: u64,
+    pub origin_refused: u64,
+    pub poll_errors: u64,
+    pub dropped_logs: u64,
+    pub connected_ms: i64,
+    pub control_rtt_ms: i64,
+    pub buffer_bytes_in_use: u64,
+    pub buffer_bytes_limit: u64,
+}
+
+/// Time from the latest ping of the tunnel session to its pong, -1 until one came back
+fn control_rtt_ms(diagnostics: &ControlDiagnostics) -> i64 {
+    if diagnostics.last_ping == 0 || diagnostics.last_pong < diagnostics.last_ping {
+        return -1;
+    }
+    (diagnostics.last_pong - diagnostics.last_ping) as i64
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_all_metrics(out_metrics: *mut PlayitMetrics) -> i32 {
+    if out_metrics.is_null() {
+        return -1;
+    }
+
+    let (running, totals, reconnect_attempts, control_rtt_ms, buffers) = {
+        let lock = state().lock().expect("state lock poisoned");
+        (
+            lock.running,
+            lock.stats.as_ref().map(|stats| stats.snapshot()).unwrap_or_default(),
+            lock.reconnect_attempts
+                .as_ref()
+                .map(|v| v.load(Ordering::SeqCst))
+                .unwrap_or(0),
+            lock.diagnostics
+                .as_ref()
+                .map(|v| control_rtt_ms(&v.lock().expect("diagnostics lock poisoned")))
+                .unwrap_or(-1),
+            lock.buffer_budget
+                .as_ref()
+                .map(|budget| (budget.in_use(), budget.limit().unwrap_or(0)))
+                .unwrap_or_default(),
+        )
+    };
+
+    let metrics = PlayitMetrics {
+        struct_version: STRUCT_ABI_VERSION,
+        struct_size: size_of::<PlayitMetrics>() as u32,
+        status_code: STATUS_CODE.load(Ordering::Acquire),
+        reconnect_attempts,
+        active_tcp: totals.active_tcp,
+        active_udp: totals.active_udp,
+        bytes_in: totals.bytes_in,
+        bytes_out: totals.bytes_out,
+        rejected_tcp: totals.rejected_tcp,
+        blocked: totals.blocked,
+        origin_refused: totals.origin_refused,
+        poll_errors: POLL_ERRORS.load(Ordering::Relaxed),
+        dropped_logs: log_rate::playit_get_dropped_log_count(),
+        connected_ms: connected_duration_ms(CONNECTED_SINCE.load(Ordering::Acquire), clock()),
+        control_rtt_ms,
+        buffer_bytes_in_use: buffers.0 as u64,
+        buffer_bytes_limit: buffers.1 as u64,
+    };
+
+    unsafe { write_versioned(out_metrics, metrics) };
+    if running { 0 } else { -2 }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_duration_histogram_json(buf: *mut c_char, len: usize) -> i32 {
+    let [under_10s, under_1m, under_10m, over_10m] = state()
+        .lock()
+        .expect("state lock poisoned")
+        .stats
+        .as_ref()
+        .map(|stats| stats.duration_histogram())
+        .unwrap_or_default();
+
+    let json = serde_json::json!({
+        "under_10s": under_10s,
+        "under_1m": under_1m,
+        "under_10m": under_10m,
+        "over_10m": over_10m,
+    })
+    .to_string();
+    unsafe { write_c_buffer(&json, buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use playit_agent_core::agent_control::maintained_control::ControlDiagnostics;
+
+    use super::control_rtt_ms;
+
+    #[test]
+    fn rtt_from_ping_pong() {
+        let mut diagnostics = ControlDiagnostics::default();
+        assert_eq!(control_rtt_ms(&diagnostics), -1);
+
+        diagnostics.last_ping = 10_000;
+        assert_eq!(control_rtt_ms(&diagnostics), -1);
+        diagnostics.last_pong = 10_045;
+        assert_eq!(control_rtt_ms(&diagnostics), 45);
+
+        /* a new ping still waiting on its pong */
+        diagnostics.last_ping = 11_100;
+        assert_eq!(control_rtt_ms(&diagnostics), -1);
+    }
+}
@@ -0,0 +1,75 @@
+//! One accessor per status field. Unlike `PlayitStatus` these never change shape, so hosts
+//! built against an older header keep working as fields are added.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::sync::atomic::Ordering;
+
+use crate::{STATUS_CODE, StatusSnapshot, state, write_c_buffer};
+
+fn with_status<T>(f: impl FnOnce(&StatusSnapshot) -> T) -> T {
+    let status = state().lock().expect("state lock poisoned").status.clone();
+    let lock = status.lock().expect("status lock poisoned");
+    f(&lock)
+}
+
+unsafe fn write_optional(value: Option<CString>, buf: *mut c_char, len: usize) -> i32 {
+    match value {
+        Some(value) => unsafe { write_c_buffer(&value.to_string_lossy(), buf, len) },
+        None => -1,
+    }
+}
+
+/// Same as `playit_get_status_code`
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_status_current_code() -> i32 {
+    STATUS_CODE.load(Ordering::Acquire)
+}
+
+/// The status code while it is an error status, 0 otherwise
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_status_error_code() -> i32 {
+    with_status(|status| {
+        if status.code.is_error() {
+            status.code as i32
+        } else {
+            0
+        }
+    })
+}
+
+/// Open TCP connections plus UDP flows, 0 when not running
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_status_connections() -> u32 {
+    let lock = state().lock().expect("state lock poisoned");
+    lock.stats
+        .as_ref()
+        .map(|stats| {
+            let snapshot = stats.snapshot();
+            snapshot.active_tcp + snapshot.active_udp
+        })
+        .unwrap_or(0)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_status_reconnect_attempts() -> u32 {
+    let lock = state().lock().expect("state lock poisoned");
+    lock.reconnect_attempts
+        .as_ref()
+        .map(|v| v.load(Ordering::SeqCst))
+        .unwrap_or(0)
+}
+
+/// Copies `last_address` into `buf`, -1 if there is none
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_status_last_address(buf: *mut c_char, len: usize) -> i32 {
+    let value = with_status(|status| status.last_address.clone());
+    unsafe { write_optional(value, buf, len) }
+}
+
+/// Copies `last_error` into `buf`, -1 if there is none
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_status_last_error(buf: *mut c_char, len: usize) -> i32 {
+    let value = with_status(|status| status.last_error.clone());
+    unsafe { write_optional(value, buf, len) }
+}
@@ -0,0 +1,60 @@
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::{Value, json};
+
+use crate::{PlayitStatusCode, write_c_buffer};
+
+const CODES: usize = PlayitStatusCode::ScheduledOff as usize + 1;
+
+/// Unix ms of the last change into each status code, by code, 0 if never. Kept across
+/// restarts so flapping between runs shows up too.
+static ENTERED: [AtomicU64; CODES] = [const { AtomicU64::new(0) }; CODES];
+
+pub(crate) fn entered(code: PlayitStatusCode, now_ms: u64) {
+    ENTERED[code as usize].store(now_ms.max(1), Ordering::Release);
+}
+
+pub(crate) fn clear() {
+    for entered in &ENTERED {
+        entered.store(0, Ordering::Release);
+    }
+}
+
+fn timeline_json(entered: &[AtomicU64]) -> Value {
+    let entries = entered.iter().enumerate().filter_map(|(code, entered)| {
+        let at = entered.load(Ordering::Acquire);
+        (at != 0).then(|| json!({ "code": code, "last_entered_ms": at }))
+    });
+    Value::from_iter(entries)
+}
+
+/// JSON array with the unix ms of the last change into each status code entered so far,
+/// by code
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_status_timeline_json(buf: *mut c_char, len: usize) -> i32 {
+    unsafe { write_c_buffer(&timeline_json(&ENTERED).to_string(), buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicU64;
+
+    use super::{CODES, timeline_json};
+
+    #[test]
+    fn only_entered_codes() {
+        let mut entered: [AtomicU64; CODES] = Default::default();
+        assert_eq!(timeline_json(&entered), serde_json::json!([]));
+
+        entered[2] = AtomicU64::new(3_000);
+        entered[12] = AtomicU64::new(2_000);
+        assert_eq!(
+            timeline_json(&entered),
+            serde_json::json!([
+                { "code": 2, "last_entered_ms": 3_000 },
+                { "code": 12, "last_entered_ms": 2_000 },
+            ])
+        );
+    }
+}
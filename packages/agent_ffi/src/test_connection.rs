@@ -0,0 +1,95 @@
+use std::os::raw::c_char;
+use std::sync::Arc;
+use std::time::Duration;
+
+use playit_agent_core::network::origin_lookup::OriginLookup;
+use playit_agent_core::playit_agent::PlayitAgent;
+
+use crate::{
+    FfiConfig, PlayitStatusCode, agent_settings, ensure_logging, parse_config_json, primary_address,
+};
+
+/* -1 to -4 are config errors shared with playit_init, same values as playit_ping_api */
+const TEST_ERR_RUNTIME: i32 = -5;
+const TEST_ERR_TIMEOUT: i32 = -7;
+
+const DEFAULT_TEST_TIMEOUT_MS: u32 = 15_000;
+
+/// Everything a start does up to Connected: load rundata (auth), find a tunnel address and
+/// set up the tunnel session. Returns the status code the main agent would have ended in.
+async fn run_test(config: &FfiConfig) -> PlayitStatusCode {
+    let api = config.create_api();
+    let data = match api.v1_agents_rundata().await {
+        Ok(data) => data,
+        Err(error) => {
+            tracing::warn!(?error, "connection test: failed to load run data");
+            return PlayitStatusCode::from_api_error(&error);
+        }
+    };
+
+    let Some(address) = primary_address(&data, config.required_tunnel_id) else {
+        tracing::warn!("connection test: no enabled tunnel");
+        return PlayitStatusCode::Disconnected;
+    };
+
+    let lookup = Arc::new(OriginLookup::default());
+    lookup.update_from_run_data(&data).await;
+    match PlayitAgent::new(agent_settings(config), lookup).await {
+        Ok(_agent) => {
+            tracing::info!(%address, "connection test: tunnel session established");
+            PlayitStatusCode::Connected
+        }
+        Err(error) => {
+            tracing::warn!(?error, "connection test: failed to setup agent");
+            PlayitStatusCode::Error
+        }
+    }
+}
+
+/// Runs the connection path of `playit_start` with `config_json` on its own runtime and
+/// tears it down again, leaving the global agent state alone. Blocks the calling thread.
+/// 0 once a tunnel session was established, otherwise the status code it failed with,
+/// a config error or -7 on timeout.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_test_connection(
+    config_json: *const c_char,
+    timeout_ms: u32,
+) -> i32 {
+    ensure_logging();
+    let config = match unsafe { parse_config_json(config_json) } {
+        Ok(v) => v,
+        Err(code) => return code,
+    };
+
+    let timeout_ms = if timeout_ms == 0 {
+        DEFAULT_TEST_TIMEOUT_MS
+    } else {
+        timeout_ms
+    };
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(error) => {
+            tracing::error!(?error, "failed to create runtime for connection test");
+            return TEST_ERR_RUNTIME;
+        }
+    };
+
+    let timeout = Duration::from_millis(timeout_ms as u64);
+    let result = runtime.block_on(tokio::time::timeout(timeout, run_test(&config)));
+    /* don't wait on sockets of the dropped session */
+    runtime.shutdown_background();
+
+    match result {
+        Ok(PlayitStatusCode::Connected) => 0,
+        Ok(code) => code as i32,
+        Err(_) => {
+            tracing::warn!(timeout_ms, "connection test timed out");
+            TEST_ERR_TIMEOUT
+        }
+    }
+}
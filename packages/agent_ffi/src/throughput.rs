@@ -0,0 +1,70 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use playit_agent_core::stats::{AgentStats, StatsSnapshot};
+use tokio::sync::watch;
+
+use crate::callbacks::{THROUGHPUT_CALLBACK, THROUGHPUT_INTERVAL_MS};
+use crate::{clock, until_stopped};
+
+/// Runs next to the agent for as long as it is running. Each sample covers the time
+/// since the previous one, so the first is only sent once a full interval has passed
+/// with a callback set; there is never a sample without a baseline.
+pub(crate) async fn run_sampler(stats: AgentStats, mut stop_rx: watch::Receiver<bool>) {
+    let mut baseline: Option<(u64, StatsSnapshot)> = None;
+
+    loop {
+        if THROUGHPUT_CALLBACK.get().is_none() {
+            baseline = None;
+        } else if baseline.is_none() {
+            baseline = Some((clock().now_ms(), stats.snapshot()));
+        }
+
+        let interval_ms = THROUGHPUT_INTERVAL_MS.load(Ordering::Relaxed);
+        let wait = tokio::time::sleep(Duration::from_millis(interval_ms));
+        if until_stopped(&mut stop_rx, wait).await.is_none() {
+            break;
+        }
+
+        let Some((callback, user_data)) = THROUGHPUT_CALLBACK.get() else {
+            continue;
+        };
+        let Some((sampled_at, previous)) = baseline.take() else {
+            continue;
+        };
+
+        let now = clock().now_ms();
+        let current = stats.snapshot();
+        let elapsed_ms = now.saturating_sub(sampled_at);
+
+        callback(
+            per_second(previous.bytes_in, current.bytes_in, elapsed_ms),
+            per_second(previous.bytes_out, current.bytes_out, elapsed_ms),
+            user_data,
+        );
+        baseline = Some((now, current));
+    }
+}
+
+fn per_second(before: u64, after: u64, elapsed_ms: u64) -> u64 {
+    if elapsed_ms == 0 {
+        return 0;
+    }
+
+    (after.saturating_sub(before) as u128 * 1_000 / elapsed_ms as u128) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::per_second;
+
+    #[test]
+    fn rate_over_interval() {
+        assert_eq!(per_second(0, 5_000, 1_000), 5_000);
+        assert_eq!(per_second(1_000, 2_000, 500), 2_000);
+        assert_eq!(per_second(0, 1, 3_000), 0);
+        assert_eq!(per_second(0, 100, 0), 0);
+        /* counters went backwards (agent restarted) */
+        assert_eq!(per_second(5_000, 10, 1_000), 0);
+    }
+}
@@ -0,0 +1,67 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Host set priorities by tunnel id, tunnels without one have priority 0. Kept across
+/// restarts like origin overrides.
+static PRIORITIES: Mutex<Option<HashMap<u64, i32>>> = Mutex::new(None);
+
+/// The candidate with the highest priority, the first one in iteration order on a tie
+pub(crate) fn pick<T>(candidates: impl Iterator<Item = T>, id: impl Fn(&T) -> u64) -> Option<T> {
+    let lock = PRIORITIES.lock().expect("tunnel priorities lock poisoned");
+    pick_with(candidates, id, lock.as_ref())
+}
+
+fn pick_with<T>(
+    candidates: impl Iterator<Item = T>,
+    id: impl Fn(&T) -> u64,
+    priorities: Option<&HashMap<u64, i32>>,
+) -> Option<T> {
+    let priority = |candidate: &T| {
+        priorities
+            .and_then(|priorities| priorities.get(&id(candidate)))
+            .copied()
+            .unwrap_or(0)
+    };
+    /* min_by_key keeps the first of equal keys, max_by_key the last */
+    candidates.min_by_key(|candidate| Reverse(priority(candidate)))
+}
+
+pub(crate) fn clear() {
+    *PRIORITIES.lock().expect("tunnel priorities lock poisoned") = None;
+}
+
+/// Preference for the status address among enabled tunnels, higher wins, 0 (the default)
+/// removes it. Negative values rank below tunnels without a priority. Used from the next
+/// rundata poll.
+#[unsafe(no_mangle)]
+pub extern "C" fn playit_set_tunnel_priority(tunnel_id: u64, priority: i32) {
+    let mut lock = PRIORITIES.lock().expect("tunnel priorities lock poisoned");
+    let priorities = lock.get_or_insert_with(HashMap::new);
+    if priority == 0 {
+        priorities.remove(&tunnel_id);
+    } else {
+        priorities.insert(tunnel_id, priority);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::pick_with;
+
+    #[test]
+    fn highest_priority_then_rundata_order() {
+        let tunnels = [3u64, 1, 2];
+        let pick = |priorities: &HashMap<u64, i32>| {
+            pick_with(tunnels.iter().copied(), |id| *id, Some(priorities))
+        };
+
+        assert_eq!(pick(&HashMap::new()), Some(3));
+        assert_eq!(pick(&HashMap::from([(2, 5)])), Some(2));
+        assert_eq!(pick(&HashMap::from([(1, 5), (2, 5)])), Some(1));
+        assert_eq!(pick(&HashMap::from([(3, -1)])), Some(1));
+        assert_eq!(pick_with([].into_iter(), |id: &u64| *id, None), None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::os::raw::c_char;
+
+use playit_agent_core::network::origin_lookup::OriginResource;
+use playit_api_client::api::{AgentTunnelV1, PortType, TunnelType};
+use serde::Serialize;
+
+use crate::{origin_override, state, write_c_buffer};
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PlayitTunnelType {
+    Tcp = 0,
+    Udp = 1,
+    TcpUdp = 2,
+    Web = 3,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PlayitDisabledReason {
+    /// Tunnel is enabled
+    None = 0,
+    OverQuota = 1,
+    AccountSuspended = 2,
+    RegionUnavailable = 3,
+    ManuallyDisabled = 4,
+    /// Reason this version doesn't know, check `disabled_reason_raw`
+    Unknown = 5,
+    /// Tunnel is no longer in rundata, only used by the tunnel state callback
+    Removed = 6,
+}
+
+impl PlayitDisabledReason {
+    fn from_raw(reason: Option<&str>) -> Self {
+        let Some(reason) = reason else {
+            return PlayitDisabledReason::None;
+        };
+
+        match reason.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "requires-premium" | "over-port-limit" | "over-quota" | "over-limit" => {
+                PlayitDisabledReason::OverQuota
+            }
+            "banned" | "account-suspended" | "account-delete-scheduled" => {
+                PlayitDisabledReason::AccountSuspended
+            }
+            "public-port-not-available" | "region-unavailable" | "region-not-available" => {
+                PlayitDisabledReason::RegionUnavailable
+            }
+            "disabled" | "user-disabled" | "disabled-by-user" | "manually-disabled" => {
+                PlayitDisabledReason::ManuallyDisabled
+            }
+            _ => PlayitDisabledReason::Unknown,
+        }
+    }
+}
+
+/// Tunnel entry from the latest rundata, serialized for `playit_get_tunnels_json`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct TunnelInfo {
+    /// Same id used by `playit_get_tunnel_stats`
+    pub id: u64,
+    pub name: String,
+    pub address: String,
+    /// Local origin connections are forwarded to, for the first port of the tunnel
+    pub local_address: Option<String>,
+    pub tunnel_type: i32,
+    /// Server provided type ex. "minecraft-java", null for generic tunnels
+    pub tunnel_type_name: Option<String>,
+    /// Only set for web tunnels
+    pub hostname: Option<String>,
+    pub url: Option<String>,
+    pub enabled: bool,
+    pub disabled_reason: i32,
+    /// Reason string as sent by the server, null when enabled
+    pub disabled_reason_raw: Option<String>,
+    /// First and last external port, null when the address doesn't carry one (ex. web
+    /// tunnels and domains resolved through SRV records)
+    pub from_port: Option<u16>,
+    pub to_port: Option<u16>,
+    pub port_count: u16,
+}
+
+impl TunnelInfo {
+    pub(crate) fn from_tunnel(tunnel: &AgentTunnelV1) -> Self {
+        let is_web = tunnel
+            .tunnel_type
+            .clone()
+            .and_then(|v| serde_json::from_value::<TunnelType>(serde_json::Value::String(v)).ok())
+            == Some(TunnelType::Https);
+
+        let tunnel_type = if is_web {
+            PlayitTunnelType::Web
+        } else {
+            match tunnel.port_type {
+                PortType::Tcp => PlayitTunnelType::Tcp,
+                PortType::Udp => PlayitTunnelType::Udp,
+                PortType::Both => PlayitTunnelType::TcpUdp,
+            }
+        };
+
+        let hostname = is_web.then(|| web_hostname(&tunnel.display_address));
+        let (from_port, to_port) = match port_range(&tunnel.display_address, tunnel.port_count) {
+            Some((from, to)) => (Some(from), Some(to)),
+            None => (None, None),
+        };
+
+        TunnelInfo {
+            id: tunnel.internal_id,
+            name: tunnel.name.clone(),
+            address: tunnel.display_address.clone(),
+            local_address: origin_override::get(tunnel.internal_id)
+                .or_else(|| {
+                    OriginResource::from_agent_tunnel(tunnel)
+                        .and_then(|origin| origin.resolve_local(0))
+                })
+                .map(|addr| addr.to_string()),
+            tunnel_type: tunnel_type as i32,
+            tunnel_type_name: tunnel.tunnel_type.clone(),
+            url: hostname.as_ref().map(|host| format!("https://{}", host)),
+            hostname,
+            enabled: tunnel.disabled_reason.is_none(),
+            disabled_reason: PlayitDisabledReason::from_raw(tunnel.disabled_reason.as_deref())
+                as i32,
+            disabled_reason_raw: tunnel.disabled_reason.as_ref().map(|v| v.to_string()),
+            from_port,
+            to_port,
+            port_count: tunnel.port_count,
+        }
+    }
+}
+
+/// External ports of a tunnel, from the port its display address ends in
+fn port_range(display_address: &str, port_count: u16) -> Option<(u16, u16)> {
+    let (_, port) = display_address.rsplit_once(':')?;
+    let from = port.parse::<u16>().ok()?;
+    let to = from.checked_add(port_count.max(1) - 1)?;
+    Some((from, to))
+}
+
+/// Edge in a tunnel's enabled state between two rundata polls
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct TunnelStateChange {
+    pub id: u64,
+    pub enabled: bool,
+    pub disabled_reason: i32,
+}
+
+/// New tunnels are reported with whatever state they come in with and removed tunnels
+/// as disabled with `Removed`. Tunnels whose enabled state didn't flip aren't reported.
+pub(crate) fn state_changes(
+    previous: &[TunnelInfo],
+    current: &[TunnelInfo],
+) -> Vec<TunnelStateChange> {
+    let mut changes = Vec::new();
+
+    for tunnel in current {
+        let flipped = match previous.iter().find(|t| t.id == tunnel.id) {
+            Some(before) => before.enabled != tunnel.enabled,
+            None => true,
+        };
+
+        if flipped {
+            changes.push(TunnelStateChange {
+                id: tunnel.id,
+                enabled: tunnel.enabled,
+                disabled_reason: tunnel.disabled_reason,
+            });
+        }
+    }
+
+    for tunnel in previous {
+        if !current.iter().any(|t| t.id == tunnel.id) {
+            changes.push(TunnelStateChange {
+                id: tunnel.id,
+                enabled: false,
+                disabled_reason: PlayitDisabledReason::Removed as i32,
+            });
+        }
+    }
+
+    changes
+}
+
+/// Web tunnel display addresses are a domain, strip anything that isn't part of it
+fn web_hostname(display_address: &str) -> String {
+    let host = display_address
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(display_address);
+    let host = host.split('/').next().unwrap_or(host);
+
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+        _ => host.to_string(),
+    }
+}
+
+/// Writes the tunnels from the latest rundata as a JSON array into `buf`, `[]` before
+/// the first rundata has loaded. Same return value as the other buffer functions.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn playit_get_tunnels_json(buf: *mut c_char, len: usize) -> i32 {
+    let json = {
+        let status = state()
+            .lock()
+            .expect("state lock poisoned")
+            .status
+            .clone();
+        let lock = status.lock().expect("status lock poisoned");
+        serde_json::to_string(&lock.tunnels).expect("failed to serialize tunnels")
+    };
+
+    unsafe { write_c_buffer(&json, buf, len) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PlayitDisabledReason, TunnelInfo, TunnelStateChange, port_range, state_changes};
+
+    fn tunnel(id: u64, disabled: Option<PlayitDisabledReason>) -> TunnelInfo {
+        TunnelInfo {
+            id,
+            name: format!("tunnel {}", id),
+            address: "example.playit.gg:1234".to_string(),
+            local_address: Some("127.0.0.1:25565".to_string()),
+            tunnel_type: 0,
+            tunnel_type_name: None,
+            hostname: None,
+            url: None,
+            enabled: disabled.is_none(),
+            disabled_reason: disabled.unwrap_or(PlayitDisabledReason::None) as i32,
+            disabled_reason_raw: None,
+            from_port: Some(1234),
+            to_port: Some(1234),
+            port_count: 1,
+        }
+    }
+
+    fn change(id: u64, enabled: bool, reason: PlayitDisabledReason) -> TunnelStateChange {
+        TunnelStateChange {
+            id,
+            enabled,
+            disabled_reason: reason as i32,
+        }
+    }
+
+    #[test]
+    fn tunnel_state_edges() {
+        let previous = vec![
+            tunnel(1, None),
+            tunnel(2, None),
+            tunnel(3, Some(PlayitDisabledReason::OverQuota)),
+        ];
+        let current = vec![
+            tunnel(1, None),
+            tunnel(2, Some(PlayitDisabledReason::OverQuota)),
+            tunnel(3, None),
+            tunnel(4, None),
+        ];
+
+        assert_eq!(state_changes(&previous, &previous), vec![]);
+        assert_eq!(
+            state_changes(&previous, &current),
+            vec![
+                change(2, false, PlayitDisabledReason::OverQuota),
+                change(3, true, PlayitDisabledReason::None),
+                change(4, true, PlayitDisabledReason::None),
+            ]
+        );
+        assert_eq!(
+            state_changes(&current, &current[..1]),
+            vec![
+                change(2, false, PlayitDisabledReason::Removed),
+                change(3, false, PlayitDisabledReason::Removed),
+                change(4, false, PlayitDisabledReason::Removed),
+            ]
+        );
+    }
+
+    #[test]
+    fn external_port_range() {
+        assert_eq!(port_range("example.ply.gg:30000", 1), Some((30000, 30000)));
+        assert_eq!(port_range("example.ply.gg:30000", 11), Some((30000, 30010)));
+        assert_eq!(port_range("147.185.221.1:30000", 0), Some((30000, 30000)));
+        assert_eq!(port_range("example.gl.joinmc.link", 1), None);
+        assert_eq!(port_range("https://example.playit.plus", 1), None);
+        assert_eq!(port_range("example.ply.gg:65535", 2), None);
+    }
+
+    #[test]
+    fn disabled_reason_mapping() {
+        assert_eq!(PlayitDisabledReason::from_raw(None), PlayitDisabledReason::None);
+        assert_eq!(
+            PlayitDisabledReason::from_raw(Some("requires-premium")),
+            PlayitDisabledReason::OverQuota
+        );
+        assert_eq!(
+            PlayitDisabledReason::from_raw(Some("Over_Port_Limit")),
+            PlayitDisabledReason::OverQuota
+        );
+        assert_eq!(
+            PlayitDisabledReason::from_raw(Some("banned")),
+            PlayitDisabledReason::AccountSuspended
+        );
+        assert_eq!(
+            PlayitDisabledReason::from_raw(Some("public-port-not-available")),
+            PlayitDisabledReason::RegionUnavailable
+        );
+        assert_eq!(
+            PlayitDisabledReason::from_raw(Some("disabled-by-user")),
+            PlayitDisabledReason::ManuallyDisabled
+        );
+        assert_eq!(
+            PlayitDisabledReason::from_raw(Some("some-new-reason")),
+            PlayitDisabledReason::Unknown
+        );
+    }
+}
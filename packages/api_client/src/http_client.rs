@@ -1,4 +1,5 @@
 use std::panic::Location;
+use std::time::Duration;
 
 use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
@@ -26,12 +27,47 @@ impl Clone for HttpClient {
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct HttpClientSettings {
+    /// Skip TLS certificate verification, only for development against self-hosted servers
+    pub danger_accept_invalid_certs: bool,
+    /// User-Agent header sent with every request, reqwest's default if not set
+    pub user_agent: Option<String>,
+    /// Limit for a whole request including reading the response, none if not set
+    pub timeout: Option<Duration>,
+}
+
 impl HttpClient {
     pub fn new(api_base: String, auth_header: Option<String>) -> Self {
+        Self::with_settings(api_base, auth_header, &HttpClientSettings::default())
+    }
+
+    pub fn with_settings(
+        api_base: String,
+        auth_header: Option<String>,
+        settings: &HttpClientSettings,
+    ) -> Self {
+        if settings.danger_accept_invalid_certs {
+            tracing::warn!(%api_base, "TLS certificate verification is DISABLED for API requests");
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .danger_accept_invalid_certs(settings.danger_accept_invalid_certs);
+        if let Some(user_agent) = &settings.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(timeout) = settings.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        let client = builder
+            .build()
+            .expect("failed to build http client");
+
         HttpClient {
             api_base,
             auth_header: RwLock::new(auth_header),
-            client: reqwest::Client::new(),
+            client,
         }
     }
 
@@ -39,6 +75,19 @@ impl HttpClient {
         &self.api_base
     }
 
+    /// HEAD request to the API base without auth, only checks the host is reachable.
+    /// Any HTTP response counts, whatever its status.
+    pub async fn ping(&self, timeout: Duration) -> Result<StatusCode, HttpClientError> {
+        let response = self
+            .client
+            .head(&self.api_base)
+            .timeout(timeout)
+            .send()
+            .await?;
+
+        Ok(response.status())
+    }
+
     pub async fn remove_auth(&self) {
         let mut lock = self.auth_header.write().await;
         let _ = lock.take();
@@ -72,7 +121,12 @@ impl PlayitHttpClient for HttpClient {
 
             let response_status = response.status();
             if response_status == StatusCode::TOO_MANY_REQUESTS {
-                return Err(HttpClientError::TooManyRequests);
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_retry_after);
+                return Err(HttpClientError::TooManyRequests { retry_after });
             }
 
             let response_txt = response.text().await?;
@@ -98,7 +152,8 @@ pub enum HttpClientError {
     SerializeError(serde_json::Error),
     ParseError(serde_json::Error, StatusCode, String),
     RequestError(reqwest::Error),
-    TooManyRequests,
+    /// HTTP 429, with the server's Retry-After if it sent one in seconds
+    TooManyRequests { retry_after: Option<Duration> },
 }
 
 impl From<reqwest::Error> for HttpClientError {
@@ -106,3 +161,23 @@ impl From<reqwest::Error> for HttpClientError {
         HttpClientError::RequestError(value)
     }
 }
+
+/// Only the delay-seconds form, an HTTP date is treated as missing
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::parse_retry_after;
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 5 "), Some(Duration::from_secs(5)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2026 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("-1"), None);
+    }
+}
@@ -1,5 +1,10 @@
-use crate::api::PlayitApiClient;
-use crate::http_client::HttpClient;
+use std::panic::Location;
+
+use crate::api::{
+    AgentRunDataV1, ApiErrorNoFail, ApiResult, PlayitApiClient, PlayitHttpClient,
+    ReqAgentsRundataV1,
+};
+use crate::http_client::{HttpClient, HttpClientError, HttpClientSettings};
 
 // mod api is auto generated
 pub mod api;
@@ -10,11 +15,54 @@ pub type PlayitApi = PlayitApiClient<HttpClient>;
 
 impl PlayitApi {
     pub fn create(api_base: String, secret: Option<String>) -> Self {
-        PlayitApiClient::new(HttpClient::new(
+        Self::create_with_settings(api_base, secret, &HttpClientSettings::default())
+    }
+
+    pub fn create_with_settings(
+        api_base: String,
+        secret: Option<String>,
+        settings: &HttpClientSettings,
+    ) -> Self {
+        PlayitApiClient::new(HttpClient::with_settings(
             api_base,
             secret.map(|v| format!("Agent-Key {}", v.trim())),
+            settings,
         ))
     }
+
+    /// Same as `v1_agents_rundata` but also returns the data exactly as the server sent
+    /// it, including fields `AgentRunDataV1` doesn't model yet.
+    #[track_caller]
+    pub fn v1_agents_rundata_raw(
+        &self,
+    ) -> impl Future<
+        Output = Result<(AgentRunDataV1, serde_json::Value), ApiErrorNoFail<HttpClientError>>,
+    > + '_ {
+        let caller = Location::caller();
+        async move {
+            let res = self
+                .get_client()
+                .call(caller, "/v1/agents/rundata", ReqAgentsRundataV1 {})
+                .await;
+
+            let raw: serde_json::Value = match res {
+                Ok(ApiResult::Success(v)) => v,
+                Ok(ApiResult::Fail(())) => panic!(),
+                Ok(ApiResult::Error(error)) => return Err(ApiErrorNoFail::ApiError(error)),
+                Err(error) => return Err(ApiErrorNoFail::ClientError(error)),
+            };
+
+            let data = serde_json::from_value(raw.clone()).map_err(|e| {
+                ApiErrorNoFail::ClientError(HttpClientError::ParseError(
+                    e,
+                    reqwest::StatusCode::OK,
+                    raw.to_string(),
+                ))
+            })?;
+
+            Ok((data, raw))
+        }
+    }
 }
 
 impl api::PortType {